@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS pokemon_cache (
+    id INT PRIMARY KEY,
+    data TEXT NOT NULL,
+    fetched_at BIGINT NOT NULL
+);
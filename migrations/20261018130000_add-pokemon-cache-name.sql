@@ -0,0 +1,3 @@
+ALTER TABLE pokemon_cache ADD COLUMN IF NOT EXISTS name TEXT;
+
+CREATE INDEX IF NOT EXISTS pokemon_cache_name ON pokemon_cache (name);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS assets (
+    name TEXT PRIMARY KEY,
+    oid OID NOT NULL,
+    content_type TEXT NOT NULL,
+    hash TEXT NOT NULL,
+    cache_control TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS asset_thumbnails (
+    name TEXT NOT NULL REFERENCES assets (name) ON DELETE CASCADE,
+    hash TEXT NOT NULL,
+    width BIGINT NOT NULL,
+    height BIGINT NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (name, hash, width, height)
+);
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS timers (
+    id TEXT PRIMARY KEY,
+    saved_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS timers_saved_at ON timers (saved_at);
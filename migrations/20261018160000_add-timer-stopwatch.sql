@@ -0,0 +1,8 @@
+ALTER TABLE timers
+    ADD COLUMN IF NOT EXISTS resumed_at BIGINT,
+    ADD COLUMN IF NOT EXISTS accumulated BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS stopped BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS laps TEXT NOT NULL DEFAULT '[]';
+
+-- Timers saved before were running since then
+UPDATE timers SET resumed_at = saved_at WHERE resumed_at IS NULL AND NOT stopped;
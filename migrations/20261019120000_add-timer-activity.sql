@@ -0,0 +1,9 @@
+ALTER TABLE timers ADD COLUMN IF NOT EXISTS active_at BIGINT;
+
+-- Timers were last active when they were saved
+UPDATE timers SET active_at = saved_at WHERE active_at IS NULL;
+ALTER TABLE timers ALTER COLUMN active_at SET NOT NULL;
+
+-- Timers expire by their activity instead
+DROP INDEX IF EXISTS timers_saved_at;
+CREATE INDEX IF NOT EXISTS timers_active_at ON timers (active_at);
@@ -49,45 +49,2313 @@
 //!
 //! 73034
 //! ```
-use std::io::Cursor;
+//!
+//! # Extension: Seeing through the goggles
+//!
+//! `/11/red_pixels` takes an `output` query parameter to show which pixels
+//! are magical red:
+//!
+//! * `count`: the number of magical red pixels, the default
+//! * `highlight`: a PNG of the image with all other pixels faded to gray
+//! * `mask`: a black and white PNG with the magical red pixels in white
+//! * `regions`: the regions of magical red pixels connected horizontally or
+//!   vertically as JSON, largest first. Smaller regions can be left out with
+//!   `min_pixels`.
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/11/red_pixels?output=regions&min_pixels=1000' \
+//!   -F 'image=@decoration.png'
+//!
+//! {
+//!   "count": 73034,
+//!   "regions": [{"x": 0, "y": 158, "width": 512, "height": 354, "pixels": 71197}]
+//! }
+//! ```
+//!
+//! Several images can be counted at once, each in an `image` field, with
+//! `output=count` or `output=regions`. The answer is a JSON list with the
+//! `filename` of each image:
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/11/red_pixels \
+//!   -F 'image=@decoration.png' \
+//!   -F 'image=@tree.png'
+//!
+//! [{"filename": "decoration.png", "count": 73034}, {"filename": "tree.png", "count": 1312}]
+//! ```
+//!
+//! Only PNG, JPEG and WebP images of up to 16 MiB and 25 megapixels are
+//! decoded. The dimensions are checked before decoding.
+//!
+//! # Extension: Analyzing images
+//!
+//! POST `/11/analyze` takes a PNG, JPEG or WebP image of at most 25 million
+//! pixels in the `image` field of a multipart request, and named color
+//! predicates as a JSON list in the `predicates` field. It counts the pixels
+//! matching every predicate, and also answers with a histogram of the color
+//! channels and a palette of the dominant colors.
+//! Without predicates, the magical red pixels are counted.
+//!
+//! A predicate is either an `expression` over the channels `r`, `g`, `b` and
+//! `a` (0 to 255) and the hue `h` (0 to 360), saturation `s` and value `v`
+//! (0 to 1), with arithmetic, comparisons, `&&`, `||` and `!`, or ranges of
+//! `hsv` values. Hue ranges may wrap around, like `[330, 30]` for red.
+//!
+//! The number of histogram bins per channel and the size of the palette can
+//! be set with the query parameters `bins` (16 by default) and `palette` (5 by
+//! default).
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/11/analyze?bins=4&palette=2' \
+//!   -F 'image=@decoration.png' \
+//!   -F 'predicates=[
+//!     {"name": "magical red", "expression": "r > g + b"},
+//!     {"name": "bright red", "hsv": {"h": [330, 30], "s": [0.5, 1], "v": [0.5, 1]}}
+//!   ]'
+//!
+//! {
+//!   "width": 512,
+//!   "height": 512,
+//!   "pixels": 262144,
+//!   "predicates": [
+//!     {"name": "magical red", "count": 73034, "percentage": 27.860260009765625},
+//!     {"name": "bright red", "count": 40933, "percentage": 15.614700317382812}
+//!   ],
+//!   "histogram": {
+//!     "bins": 4,
+//!     "r": [34063, 27851, 57165, 143065],
+//!     "g": [76499, 24160, 37698, 123787],
+//!     "b": [90721, 25083, 25138, 121202],
+//!     "a": [0, 0, 0, 262144]
+//!   },
+//!   "palette": [
+//!     {"color": "#fafafc", "count": 63772, "percentage": 24.32708740234375},
+//!     {"color": "#c5c6ca", "count": 7618, "percentage": 2.906036376953125}
+//!   ]
+//! }
+//! ```
+//!
+//! # Extension: Transforming images
+//!
+//! POST `/11/transform` takes a PNG, JPEG or WebP image in the `image` field
+//! of a multipart request and applies a pipeline of operations to it:
+//!
+//! * `resize`: to a `width` and/or `height`, keeping the aspect ratio unless
+//!   `exact`
+//! * `crop`: to the `width` and `height` at `x` and `y`
+//! * `rotate`: clockwise by `degrees`, a multiple of 90
+//! * `grayscale`
+//! * `blur`: with a standard deviation of `sigma` pixels, at most 20
+//!
+//! Images, read or created, must not have more than 25 million pixels.
+//!
+//! The result is a PNG, JPEG or WebP image in the `format` of the input
+//! unless given otherwise, JPEG images with a `quality` of 80 by default.
+//!
+//! The pipeline can be given as JSON in the `pipeline` field:
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/11/transform \
+//!   -F 'image=@decoration.png' \
+//!   -F 'pipeline={
+//!     "operations": [
+//!       {"op": "crop", "x": 0, "y": 0, "width": 256, "height": 256},
+//!       {"op": "resize", "width": 64},
+//!       {"op": "grayscale"}
+//!     ],
+//!     "format": "jpeg",
+//!     "quality": 90
+//!   }' \
+//!   -o thumbnail.jpg
+//! ```
+//!
+//! Or as query parameters, applied in their order. `resize` takes `200x100`,
+//! `200x` or `x100`, with a trailing `!` to ignore the aspect ratio, and
+//! `crop` takes `x,y,width,height`:
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/11/transform?crop=0,0,256,256&resize=64x&grayscale=&format=jpeg&quality=90' \
+//!   -F 'image=@decoration.png' \
+//!   -o thumbnail.jpg
+//! ```
+//!
+//! # Extension: Managing assets
+//!
+//! Assets can be uploaded with PUT and deleted with DELETE on
+//! `/11/assets/<name>`, with the token in `ASSET_TOKEN` as bearer token.
+//! Without `ASSET_TOKEN`, the assets are read-only. They are stored in the
+//! `assets` directory, or the one in `ASSETS_DIR`, or in Postgres large
+//! objects with `ASSET_STORE=postgres`. Names may only contain letters,
+//! digits, `.`, `_` and `-`.
+//!
+//! Only PNG, JPEG and WebP images can be uploaded, a declared `Content-Type`
+//! must match the image. Assets are served with the `Cache-Control` they were
+//! uploaded with, `public, max-age=3600` by default, and their SHA-256 as
+//! `ETag` for `If-None-Match`. A single byte `Range` can be requested.
+//!
+//! ```not_rust
+//! curl -X PUT http://localhost:8000/11/assets/tree.png \
+//!   -H "Authorization: Bearer $ASSET_TOKEN" \
+//!   -H 'Content-Type: image/png' \
+//!   -H 'Cache-Control: public, max-age=86400' \
+//!   --data-binary @tree.png
+//!
+//! curl -H 'Range: bytes=0-99' http://localhost:8000/11/assets/tree.png
+//!
+//! curl -X DELETE http://localhost:8000/11/assets/tree.png \
+//!   -H "Authorization: Bearer $ASSET_TOKEN"
+//! ```
+//!
+//! Images can be scaled down to fit a width `w` and/or height `h` of 32, 64,
+//! 128, 256 or 512 pixels, keeping the aspect ratio. The thumbnails are stored
+//! with the asset until it is replaced or deleted.
+//!
+//! ```not_rust
+//! curl 'http://localhost:8000/11/assets/decoration.png?w=64' -o thumbnail.png
+//! ```
+use std::{
+    collections::HashMap,
+    io::{Cursor, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{
+        multipart::{Field, MultipartError},
+        DefaultBodyLimit, FromRef, FromRequestParts, Multipart, Path, Query, State,
+    },
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::future::BoxFuture;
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    DynamicImage, GenericImageView, GrayImage, ImageFormat, ImageResult, Luma, Rgba, RgbaImage,
+};
+use sea_query::{Alias, Expr, Func, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_binder::{SqlxBinder, SqlxValues};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Get Day 11 routes
+///
+/// * `/11/assets/<name>`
+/// * `/11/red_pixels`
+/// * `/11/analyze`
+/// * `/11/transform`
+pub fn get_routes(pool: PgPool) -> Router {
+    let store: Arc<dyn AssetStore> = match std::env::var("ASSET_STORE").as_deref() {
+        Ok("postgres") => Arc::new(PgStore { pool }),
+        _ => Arc::new(DiskStore::new(
+            std::env::var("ASSETS_DIR").unwrap_or_else(|_| "assets".to_string()),
+        )),
+    };
+
+    let token = std::env::var("ASSET_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty());
+
+    router(store, token.as_deref())
+}
+
+fn router(store: Arc<dyn AssetStore>, token: Option<&str>) -> Router {
+    let state = AssetState {
+        store,
+        token_hash: token.map(sha256::digest),
+    };
+
+    Router::new()
+        .route(
+            "/11/assets/:name",
+            get(get_asset)
+                .put(put_asset)
+                .layer(DefaultBodyLimit::max(MAX_ASSET_SIZE))
+                .delete(delete_asset),
+        )
+        .route(
+            "/11/red_pixels",
+            post(red_pixels).layer(DefaultBodyLimit::max(MAX_RED_PIXELS_REQUEST)),
+        )
+        .route(
+            "/11/analyze",
+            post(analyze).layer(DefaultBodyLimit::max(MAX_RED_PIXELS_REQUEST)),
+        )
+        .route(
+            "/11/transform",
+            post(transform).layer(DefaultBodyLimit::max(MAX_RED_PIXELS_REQUEST)),
+        )
+        .with_state(state)
+}
+
+/// What `/11/red_pixels` answers with
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum RedOutput {
+    /// The number of magical red pixels as text
+    #[default]
+    Count,
+    /// A PNG of the image with everything but the magical red pixels faded
+    Highlight,
+    /// A black and white PNG with the magical red pixels in white
+    Mask,
+    /// The connected regions of magical red pixels as JSON
+    Regions,
+}
+
+#[derive(Deserialize, Default)]
+struct RedParams {
+    #[serde(default)]
+    output: RedOutput,
+    /// Smallest region reported by [`RedOutput::Regions`]
+    min_pixels: Option<u64>,
+}
+
+/// Largest image file `/11/red_pixels` reads
+const MAX_IMAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest request `/11/red_pixels`, `/11/analyze` and `/11/transform` read,
+/// with room for a few images
+const MAX_RED_PIXELS_REQUEST: usize = 4 * MAX_IMAGE_SIZE;
+
+/// Most images `/11/red_pixels` counts at once
+const MAX_IMAGES: usize = 16;
+
+/// Largest number of pixels of an image `/11/red_pixels` decodes, to not be
+/// blown up by a small file of a huge image
+const MAX_IMAGE_PIXELS: u64 = 25_000_000;
+
+/// Most memory decoding an image may allocate, enough for the largest image
+/// with 16-bit RGBA pixels
+const MAX_IMAGE_ALLOC: u64 = MAX_IMAGE_PIXELS * 8;
+
+/// Why an uploaded image was rejected
+#[derive(thiserror::Error, Debug)]
+enum UploadError {
+    #[error(transparent)]
+    Multipart(#[from] MultipartError),
+    #[error("missing the `image` field")]
+    MissingImage,
+    #[error("at most {MAX_IMAGES} images can be counted at once")]
+    TooManyImages,
+    #[error("only a single image can be shown as {0}")]
+    SingleImageOutput(&'static str),
+    #[error("unsupported content type `{0}`, expected a PNG, JPEG or WebP image")]
+    UnsupportedContentType(String),
+    #[error("the image is not a PNG, JPEG or WebP image")]
+    UnsupportedFormat,
+    #[error("declared as `{declared}` but the image is `{actual}`")]
+    ContentTypeMismatch {
+        declared: &'static str,
+        actual: &'static str,
+    },
+    #[error("images must not be larger than {MAX_IMAGE_SIZE} bytes")]
+    TooLarge,
+    #[error("images must not have more than {MAX_IMAGE_PIXELS} pixels, got {0}x{1}")]
+    TooManyPixels(u32, u32),
+    #[error("decoding the image needs too much memory: {0}")]
+    TooMuchMemory(image::ImageError),
+    #[error("invalid image: {0}")]
+    InvalidImage(image::ImageError),
+    #[error("failed to encode the image: {0}")]
+    Encode(image::ImageError),
+}
+
+impl UploadError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::Multipart(e) => e.status(),
+            Self::MissingImage | Self::TooManyImages | Self::SingleImageOutput(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::UnsupportedContentType(_)
+            | Self::UnsupportedFormat
+            | Self::ContentTypeMismatch { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooLarge | Self::TooManyPixels(..) | Self::TooMuchMemory(_) => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            Self::InvalidImage(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Encode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<UploadError> for (StatusCode, String) {
+    fn from(e: UploadError) -> Self {
+        (e.status(), e.to_string())
+    }
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        <(StatusCode, String)>::from(self).into_response()
+    }
+}
+
+/// An uploaded image, not decoded yet
+struct Upload {
+    filename: Option<String>,
+    data: Vec<u8>,
+    format: ImageFormat,
+}
+
+/// Image formats that are decoded
+fn is_supported(format: ImageFormat) -> bool {
+    OutputFormat::from_image_format(format).is_some()
+}
+
+impl Upload {
+    /// Read an image field, checking its content type and size
+    async fn read(mut field: Field<'_>) -> Result<Self, UploadError> {
+        let content_type = field.content_type().map(str::to_string);
+        let filename = field.file_name().map(str::to_string);
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            if data.len() + chunk.len() > MAX_IMAGE_SIZE {
+                return Err(UploadError::TooLarge);
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Self::new(filename, data, content_type.as_deref())
+    }
+
+    /// Check that the data is an image of a supported format, and of the
+    /// content type it is declared as, if any
+    fn new(
+        filename: Option<String>,
+        data: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<Self, UploadError> {
+        let content_type = content_type.map(|c| c.split(';').next().unwrap_or_default().trim());
+        let declared = match content_type {
+            None | Some("application/octet-stream") => None,
+            Some(content_type) => Some(
+                ImageFormat::from_mime_type(content_type)
+                    .filter(|format| is_supported(*format))
+                    .ok_or_else(|| UploadError::UnsupportedContentType(content_type.to_string()))?,
+            ),
+        };
+
+        // Trust the content, not the declaration
+        let format = image::guess_format(&data)
+            .ok()
+            .filter(|format| is_supported(*format))
+            .ok_or(UploadError::UnsupportedFormat)?;
+        if let Some(declared) = declared.filter(|declared| *declared != format) {
+            return Err(UploadError::ContentTypeMismatch {
+                declared: declared.to_mime_type(),
+                actual: format.to_mime_type(),
+            });
+        }
+
+        Ok(Self {
+            filename,
+            data,
+            format,
+        })
+    }
+
+    /// Decode the image, if it is not too large
+    fn decode(&self) -> Result<DynamicImage, UploadError> {
+        let reader = || image::ImageReader::with_format(Cursor::new(&self.data[..]), self.format);
+
+        // Only the header is read for the dimensions
+        let (width, height) = reader()
+            .into_dimensions()
+            .map_err(UploadError::InvalidImage)?;
+        if u64::from(width) * u64::from(height) > MAX_IMAGE_PIXELS {
+            return Err(UploadError::TooManyPixels(width, height));
+        }
+
+        let mut limits = image::Limits::default();
+        limits.max_alloc = Some(MAX_IMAGE_ALLOC);
+        let mut reader = reader();
+        reader.limits(limits);
+        reader.decode().map_err(|e| match e {
+            image::ImageError::Limits(_) => UploadError::TooMuchMemory(e),
+            e => UploadError::InvalidImage(e),
+        })
+    }
+}
+
+/// Magical red pixels of one of several images
+#[derive(Serialize)]
+struct RedFile {
+    filename: Option<String>,
+    count: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    regions: Option<Vec<Region>>,
+}
+
+fn count_magical_red(image: &DynamicImage) -> u64 {
+    image
+        .pixels()
+        .filter(|(_x, _y, p)| is_magical_red(*p))
+        .count() as u64
+}
+
+async fn red_pixels(
+    Query(params): Query<RedParams>,
+    mut multipart: Multipart,
+) -> Result<Response, UploadError> {
+    let mut uploads = Vec::new();
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("image") {
+            if uploads.len() == MAX_IMAGES {
+                return Err(UploadError::TooManyImages);
+            }
+            uploads.push(Upload::read(field).await?);
+        }
+    }
+
+    let min_pixels = params.min_pixels.unwrap_or(1);
+    if let [upload] = &uploads[..] {
+        let image = upload.decode()?;
+
+        return match params.output {
+            RedOutput::Count => Ok(count_magical_red(&image).to_string().into_response()),
+            RedOutput::Highlight => png(&DynamicImage::ImageRgba8(highlight(&image))),
+            RedOutput::Mask => png(&DynamicImage::ImageLuma8(mask(&image))),
+            RedOutput::Regions => {
+                let mask = mask(&image);
+                let regions = regions(&mask, min_pixels);
+
+                Ok(Json(RedRegions {
+                    count: mask.pixels().filter(|p| p[0] > 0).count() as u64,
+                    regions,
+                })
+                .into_response())
+            }
+        };
+    }
+
+    if uploads.is_empty() {
+        return Err(UploadError::MissingImage);
+    }
+    match params.output {
+        RedOutput::Highlight => return Err(UploadError::SingleImageOutput("highlight")),
+        RedOutput::Mask => return Err(UploadError::SingleImageOutput("mask")),
+        RedOutput::Count | RedOutput::Regions => {}
+    }
+
+    let mut files = Vec::with_capacity(uploads.len());
+    for upload in uploads {
+        let image = upload.decode()?;
+        let regions =
+            (params.output == RedOutput::Regions).then(|| regions(&mask(&image), min_pixels));
+        files.push(RedFile {
+            filename: upload.filename,
+            count: count_magical_red(&image),
+            regions,
+        });
+    }
+
+    Ok(Json(files).into_response())
+}
+
+/// Is the pixel red when seen through Santa's night vision goggles
+fn is_magical_red(p: Rgba<u8>) -> bool {
+    u16::from(p[0]) > u16::from(p[1]) + u16::from(p[2])
+}
+
+/// White where the pixels of the image are magical red, black elsewhere
+fn mask(image: &DynamicImage) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        Luma([if is_magical_red(image.get_pixel(x, y)) {
+            u8::MAX
+        } else {
+            0
+        }])
+    })
+}
+
+/// The image with every pixel that is not magical red faded to light gray
+fn highlight(image: &DynamicImage) -> RgbaImage {
+    let mut highlighted = image.to_rgba8();
+    for pixel in highlighted.pixels_mut() {
+        if !is_magical_red(*pixel) {
+            let Rgba([r, g, b, a]) = *pixel;
+            let luma = (u32::from(r) * 299 + u32::from(g) * 587 + u32::from(b) * 114) / 1000;
+            let faded = (170 + luma / 3) as u8;
+            *pixel = Rgba([faded, faded, faded, a]);
+        }
+    }
+
+    highlighted
+}
+
+/// Encode an image as a PNG response
+fn png(image: &DynamicImage) -> Result<Response, UploadError> {
+    let mut data = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+        .map_err(UploadError::Encode)?;
+
+    Ok(([(header::CONTENT_TYPE, "image/png")], data).into_response())
+}
+
+/// A connected region of magical red pixels
+#[derive(Serialize, Debug, PartialEq)]
+struct Region {
+    /// Left edge of the bounding box
+    x: u32,
+    /// Top edge of the bounding box
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: u64,
+}
+
+#[derive(Serialize)]
+struct RedRegions {
+    /// Number of magical red pixels
+    count: u64,
+    /// Regions with at least `min_pixels` pixels, largest first
+    regions: Vec<Region>,
+}
+
+/// Find the regions of white pixels of a mask connected horizontally or
+/// vertically, with at least `min_pixels` pixels
+fn regions(mask: &GrayImage, min_pixels: u64) -> Vec<Region> {
+    let (width, height) = mask.dimensions();
+    let mut visited = vec![false; width as usize * height as usize];
+    let idx = |x: u32, y: u32| y as usize * width as usize + x as usize;
+    let mut regions = Vec::new();
+
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel[0] == 0 || visited[idx(x, y)] {
+            continue;
+        }
+
+        // Flood fill the region with an explicit stack, it can be huge
+        visited[idx(x, y)] = true;
+        let mut stack = vec![(x, y)];
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (x, y, x, y);
+        let mut pixels = 0u64;
+        while let Some((x, y)) = stack.pop() {
+            pixels += 1;
+            (min_x, min_y) = (min_x.min(x), min_y.min(y));
+            (max_x, max_y) = (max_x.max(x), max_y.max(y));
+
+            let neighbors = [
+                x.checked_sub(1).map(|x| (x, y)),
+                (x + 1 < width).then_some((x + 1, y)),
+                y.checked_sub(1).map(|y| (x, y)),
+                (y + 1 < height).then_some((x, y + 1)),
+            ];
+            for (nx, ny) in neighbors.into_iter().flatten() {
+                if mask.get_pixel(nx, ny)[0] > 0 && !visited[idx(nx, ny)] {
+                    visited[idx(nx, ny)] = true;
+                    stack.push((nx, ny));
+                }
+            }
+        }
+
+        if pixels >= min_pixels {
+            regions.push(Region {
+                x: min_x,
+                y: min_y,
+                width: max_x - min_x + 1,
+                height: max_y - min_y + 1,
+                pixels,
+            });
+        }
+    }
+
+    regions.sort_by(|a, b| b.pixels.cmp(&a.pixels).then((a.y, a.x).cmp(&(b.y, b.x))));
+    regions
+}
+
+/// Default number of histogram bins per channel of `/11/analyze`
+const HISTOGRAM_BINS: usize = 16;
+
+/// Default number of colors in the palette of `/11/analyze`
+const PALETTE_SIZE: usize = 5;
+
+/// Most colors in the palette of `/11/analyze`
+const MAX_PALETTE_SIZE: usize = 64;
+
+/// Bits per channel kept when grouping colors for the palette
+const PALETTE_BITS: u32 = 4;
+
+/// Longest expression of a predicate
+const MAX_EXPRESSION_LEN: usize = 1000;
+
+/// Deepest nesting of an expression of a predicate
+const MAX_EXPRESSION_DEPTH: usize = 64;
+
+/// A channel of a pixel in the RGB or HSV color model
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Channel {
+    R,
+    G,
+    B,
+    A,
+    H,
+    S,
+    V,
+}
+
+/// A pixel with its color in the RGB and HSV color models
+struct Color {
+    rgba: [u8; 4],
+    /// Hue in degrees
+    h: f64,
+    /// Saturation from 0 to 1
+    s: f64,
+    /// Value from 0 to 1
+    v: f64,
+}
+
+impl Color {
+    fn new(Rgba(rgba): Rgba<u8>) -> Self {
+        let [r, g, b] = [rgba[0], rgba[1], rgba[2]].map(|c| f64::from(c) / 255.);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        let h = if delta == 0. {
+            0.
+        } else if max == r {
+            60. * ((g - b) / delta).rem_euclid(6.)
+        } else if max == g {
+            60. * ((b - r) / delta + 2.)
+        } else {
+            60. * ((r - g) / delta + 4.)
+        };
+        let s = if max == 0. { 0. } else { delta / max };
+
+        Self { rgba, h, s, v: max }
+    }
+
+    fn channel(&self, channel: Channel) -> f64 {
+        match channel {
+            Channel::R => f64::from(self.rgba[0]),
+            Channel::G => f64::from(self.rgba[1]),
+            Channel::B => f64::from(self.rgba[2]),
+            Channel::A => f64::from(self.rgba[3]),
+            Channel::H => self.h,
+            Channel::S => self.s,
+            Channel::V => self.v,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+/// An arithmetic or logical expression over the channels of a pixel
+///
+/// Comparisons and logical operators evaluate to 1 for true and 0 for false,
+/// and every value but 0 is true.
+#[derive(Debug, PartialEq)]
+enum ColorExpr {
+    Number(f64),
+    Channel(Channel),
+    Neg(Box<ColorExpr>),
+    Not(Box<ColorExpr>),
+    Binary(BinaryOp, Box<ColorExpr>, Box<ColorExpr>),
+}
+
+impl ColorExpr {
+    fn eval(&self, color: &Color) -> f64 {
+        let truth = |b: bool| if b { 1. } else { 0. };
+
+        match self {
+            Self::Number(n) => *n,
+            Self::Channel(channel) => color.channel(*channel),
+            Self::Neg(expr) => -expr.eval(color),
+            Self::Not(expr) => truth(expr.eval(color) == 0.),
+            Self::Binary(BinaryOp::And, lhs, rhs) => {
+                truth(lhs.eval(color) != 0. && rhs.eval(color) != 0.)
+            }
+            Self::Binary(BinaryOp::Or, lhs, rhs) => {
+                truth(lhs.eval(color) != 0. || rhs.eval(color) != 0.)
+            }
+            Self::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.eval(color), rhs.eval(color));
+                match op {
+                    BinaryOp::Add => lhs + rhs,
+                    BinaryOp::Sub => lhs - rhs,
+                    BinaryOp::Mul => lhs * rhs,
+                    BinaryOp::Div => lhs / rhs,
+                    BinaryOp::Lt => truth(lhs < rhs),
+                    BinaryOp::Le => truth(lhs <= rhs),
+                    BinaryOp::Gt => truth(lhs > rhs),
+                    BinaryOp::Ge => truth(lhs >= rhs),
+                    BinaryOp::Eq => truth(lhs == rhs),
+                    BinaryOp::Ne => truth(lhs != rhs),
+                    BinaryOp::And | BinaryOp::Or => unreachable!("handled above"),
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ColorExpr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > MAX_EXPRESSION_LEN {
+            return Err(format!(
+                "must not be longer than {MAX_EXPRESSION_LEN} characters"
+            ));
+        }
+
+        let mut parser = ExprParser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected `{token}`")),
+        }
+    }
+}
+
+/// Split an expression into numbers, names and operators
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    const OPERATORS: [&str; 15] = [
+        "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "(", ")", "<", ">", "!",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_digit() || c == '.' {
+            rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len())
+        } else if c.is_ascii_alphabetic() {
+            rest.find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len())
+        } else {
+            OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .map(|op| op.len())
+                .ok_or_else(|| format!("unexpected `{c}`"))?
+        };
+
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser of [`ColorExpr`], from the lowest precedence to
+/// the highest
+struct ExprParser {
+    tokens: Vec<String>,
+    pos: usize,
+    depth: usize,
+}
+
+impl ExprParser {
+    /// Consume the next token if it is one of `ops`
+    fn next_op(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let token = self.tokens.get(self.pos)?;
+        let (_, op) = ops.iter().find(|(symbol, _)| symbol == token)?;
+        self.pos += 1;
+        Some(*op)
+    }
+
+    /// Parse operators of the same precedence, associating to the left
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        operand: fn(&mut Self) -> Result<ColorExpr, String>,
+    ) -> Result<ColorExpr, String> {
+        let mut lhs = operand(self)?;
+        while let Some(op) = self.next_op(ops) {
+            lhs = ColorExpr::Binary(op, Box::new(lhs), Box::new(operand(self)?));
+        }
+        Ok(lhs)
+    }
+
+    fn or(&mut self) -> Result<ColorExpr, String> {
+        self.binary(&[("||", BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<ColorExpr, String> {
+        self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<ColorExpr, String> {
+        let lhs = self.additive()?;
+        let ops = [
+            ("<", BinaryOp::Lt),
+            ("<=", BinaryOp::Le),
+            (">", BinaryOp::Gt),
+            (">=", BinaryOp::Ge),
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+        ];
+        match self.next_op(&ops) {
+            Some(op) => Ok(ColorExpr::Binary(
+                op,
+                Box::new(lhs),
+                Box::new(self.additive()?),
+            )),
+            None => Ok(lhs),
+        }
+    }
+
+    fn additive(&mut self) -> Result<ColorExpr, String> {
+        self.binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Self::multiplicative,
+        )
+    }
+
+    fn multiplicative(&mut self) -> Result<ColorExpr, String> {
+        self.binary(&[("*", BinaryOp::Mul), ("/", BinaryOp::Div)], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<ColorExpr, String> {
+        self.depth += 1;
+        if self.depth > MAX_EXPRESSION_DEPTH {
+            return Err(format!(
+                "must not be nested deeper than {MAX_EXPRESSION_DEPTH} levels"
+            ));
+        }
+
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        let expr = match token.as_deref() {
+            Some("-") => ColorExpr::Neg(Box::new(self.unary()?)),
+            Some("!") => ColorExpr::Not(Box::new(self.unary()?)),
+            Some("(") => {
+                let expr = self.or()?;
+                if self.tokens.get(self.pos).map(String::as_str) != Some(")") {
+                    return Err("missing `)`".to_string());
+                }
+                self.pos += 1;
+                expr
+            }
+            Some(token) if token.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+                ColorExpr::Number(
+                    token
+                        .parse()
+                        .map_err(|_| format!("`{token}` is not a number"))?,
+                )
+            }
+            Some(token) => ColorExpr::Channel(match token {
+                "r" => Channel::R,
+                "g" => Channel::G,
+                "b" => Channel::B,
+                "a" => Channel::A,
+                "h" => Channel::H,
+                "s" => Channel::S,
+                "v" => Channel::V,
+                _ => return Err(format!("unknown channel `{token}`")),
+            }),
+            None => return Err("unexpected end of the expression".to_string()),
+        };
+
+        self.depth -= 1;
+        Ok(expr)
+    }
+}
+
+/// Ranges of HSV values, all of which must contain a pixel
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct HsvRange {
+    /// Hue in degrees, wrapping around if the start is larger than the end
+    h: Option<[f64; 2]>,
+    s: Option<[f64; 2]>,
+    v: Option<[f64; 2]>,
+}
+
+impl HsvRange {
+    fn contains(&self, color: &Color) -> bool {
+        let within = |range: Option<[f64; 2]>, value: f64| {
+            range.is_none_or(|[min, max]| (min..=max).contains(&value))
+        };
+        let hue = self.h.is_none_or(|[min, max]| {
+            if min <= max {
+                (min..=max).contains(&color.h)
+            } else {
+                color.h >= min || color.h <= max
+            }
+        });
+
+        hue && within(self.s, color.s) && within(self.v, color.v)
+    }
+}
+
+/// A named predicate as given to `/11/analyze`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PredicateSpec {
+    name: String,
+    expression: Option<String>,
+    hsv: Option<HsvRange>,
+}
+
+/// Test of a color
+enum ColorTest {
+    Expression(ColorExpr),
+    Hsv(HsvRange),
+}
+
+/// A named test of the pixels
+struct Predicate {
+    name: String,
+    test: ColorTest,
+}
+
+impl Predicate {
+    /// The predicate of Santa's night vision goggles
+    fn magical_red() -> Self {
+        Self {
+            name: "magical red".to_string(),
+            test: ColorTest::Expression("r > g + b".parse().expect("valid expression")),
+        }
+    }
+
+    fn matches(&self, color: &Color) -> bool {
+        match &self.test {
+            ColorTest::Expression(expr) => expr.eval(color) != 0.,
+            ColorTest::Hsv(range) => range.contains(color),
+        }
+    }
+}
+
+impl TryFrom<PredicateSpec> for Predicate {
+    type Error = String;
+
+    fn try_from(spec: PredicateSpec) -> Result<Self, Self::Error> {
+        let test = match (spec.expression, spec.hsv) {
+            (Some(expression), None) => ColorTest::Expression(
+                expression
+                    .parse()
+                    .map_err(|e| format!("predicate `{}`: {e}", spec.name))?,
+            ),
+            (None, Some(hsv)) => ColorTest::Hsv(hsv),
+            _ => {
+                return Err(format!(
+                    "predicate `{}` needs either an expression or hsv ranges",
+                    spec.name
+                ))
+            }
+        };
+
+        Ok(Self {
+            name: spec.name,
+            test,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct AnalyzeParams {
+    bins: Option<usize>,
+    palette: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct PredicateCount {
+    name: String,
+    count: u64,
+    percentage: f64,
+}
+
+#[derive(Serialize)]
+struct Histogram {
+    bins: usize,
+    r: Vec<u64>,
+    g: Vec<u64>,
+    b: Vec<u64>,
+    a: Vec<u64>,
+}
+
+#[derive(Serialize)]
+struct PaletteColor {
+    /// Average color of the group, like `#ff0000`
+    color: String,
+    count: u64,
+    percentage: f64,
+}
+
+#[derive(Serialize)]
+struct Analysis {
+    width: u32,
+    height: u32,
+    pixels: u64,
+    predicates: Vec<PredicateCount>,
+    histogram: Histogram,
+    palette: Vec<PaletteColor>,
+}
+
+/// Count the predicates, and collect the histogram and palette of an image in
+/// a single pass
+fn analyze_image(
+    image: &DynamicImage,
+    predicates: &[Predicate],
+    bins: usize,
+    palette_size: usize,
+) -> Analysis {
+    let mut counts = vec![0u64; predicates.len()];
+    let mut histogram = [(); 4].map(|_| vec![0u64; bins]);
+    // Count and sum of the channels per group of similar colors
+    let mut groups: HashMap<[u8; 3], (u64, [u64; 3])> = HashMap::new();
+
+    for (_x, _y, pixel) in image.pixels() {
+        let color = Color::new(pixel);
+        for (count, predicate) in counts.iter_mut().zip(predicates) {
+            if predicate.matches(&color) {
+                *count += 1;
+            }
+        }
+
+        for (histogram, value) in histogram.iter_mut().zip(color.rgba) {
+            histogram[usize::from(value) * bins / 256] += 1;
+        }
+
+        // Fully transparent pixels have no color to speak of
+        if color.rgba[3] > 0 {
+            let [r, g, b, _] = color.rgba;
+            let key = [r, g, b].map(|c| c >> (8 - PALETTE_BITS));
+            let (count, sums) = groups.entry(key).or_default();
+            *count += 1;
+            for (sum, c) in sums.iter_mut().zip([r, g, b]) {
+                *sum += u64::from(c);
+            }
+        }
+    }
+
+    let pixels = u64::from(image.width()) * u64::from(image.height());
+    let percentage = |count: u64| {
+        if pixels == 0 {
+            0.
+        } else {
+            count as f64 * 100. / pixels as f64
+        }
+    };
+
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by(|(a_key, (a, _)), (b_key, (b, _))| b.cmp(a).then(a_key.cmp(b_key)));
+    let palette = groups
+        .into_iter()
+        .take(palette_size)
+        .map(|(_, (count, sums))| {
+            let [r, g, b] = sums.map(|sum| (sum + count / 2) / count);
+            PaletteColor {
+                color: format!("#{r:02x}{g:02x}{b:02x}"),
+                count,
+                percentage: percentage(count),
+            }
+        })
+        .collect();
+
+    let [r, g, b, a] = histogram;
+    Analysis {
+        width: image.width(),
+        height: image.height(),
+        pixels,
+        predicates: predicates
+            .iter()
+            .zip(counts)
+            .map(|(predicate, count)| PredicateCount {
+                name: predicate.name.clone(),
+                count,
+                percentage: percentage(count),
+            })
+            .collect(),
+        histogram: Histogram { bins, r, g, b, a },
+        palette,
+    }
+}
+
+/// Count pixels matching color predicates, and describe the colors of an
+/// image
+async fn analyze(
+    Query(params): Query<AnalyzeParams>,
+    mut multipart: Multipart,
+) -> Result<Json<Analysis>, (StatusCode, String)> {
+    let bad_request =
+        |e: axum::extract::multipart::MultipartError| (StatusCode::BAD_REQUEST, e.body_text());
+    let invalid = |message: String| (StatusCode::UNPROCESSABLE_ENTITY, message);
+
+    let bins = params.bins.unwrap_or(HISTOGRAM_BINS);
+    if !(1..=256).contains(&bins) {
+        return Err(invalid("bins must be between 1 and 256".to_string()));
+    }
+    let palette_size = params.palette.unwrap_or(PALETTE_SIZE);
+    if palette_size > MAX_PALETTE_SIZE {
+        return Err(invalid(format!(
+            "palette must not have more than {MAX_PALETTE_SIZE} colors"
+        )));
+    }
+
+    let mut upload = None;
+    let mut predicates = None;
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        match field.name() {
+            Some("image") => upload = Some(Upload::read(field).await?),
+            Some("predicates") => {
+                let text = field.text().await.map_err(bad_request)?;
+                let specs: Vec<PredicateSpec> = serde_json::from_str(&text)
+                    .map_err(|e| invalid(format!("invalid predicates: {e}")))?;
+                predicates = Some(
+                    specs
+                        .into_iter()
+                        .map(Predicate::try_from)
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(invalid)?,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let upload = upload.ok_or(UploadError::MissingImage)?;
+    let predicates = predicates.unwrap_or_else(|| vec![Predicate::magical_red()]);
+
+    // Decoding and analyzing takes a while, so it doesn't block other requests
+    tokio::task::spawn_blocking(move || {
+        let image = upload.decode()?;
+        Ok(Json(analyze_image(&image, &predicates, bins, palette_size)))
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+/// Largest width or height of an image created by `/11/transform`
+const MAX_DIMENSION: u32 = 8192;
+
+/// Largest standard deviation in pixels of the blur of `/11/transform`
+const MAX_BLUR_SIGMA: f32 = 20.;
+
+/// Quality of JPEG images if not given
+const JPEG_QUALITY: u8 = 80;
+
+/// An operation of the pipeline of `/11/transform`
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+enum Operation {
+    /// Scale to a width and/or height, keeping the aspect ratio unless
+    /// `exact`
+    Resize {
+        width: Option<u32>,
+        height: Option<u32>,
+        #[serde(default)]
+        exact: bool,
+    },
+    Crop {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Rotate clockwise by a multiple of 90 degrees
+    Rotate {
+        degrees: u32,
+    },
+    Grayscale,
+    /// Gaussian blur with a standard deviation of `sigma` pixels
+    Blur {
+        sigma: f32,
+    },
+}
+
+impl Operation {
+    /// Parse an operation given as query parameter, like `resize=200x100`
+    fn from_query(key: &str, value: &str) -> Result<Self, String> {
+        let numbers = |count: usize| -> Result<Vec<u32>, String> {
+            let numbers = value
+                .split(',')
+                .map(|n| n.trim().parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("`{key}={value}` must be {count} numbers"))?;
+            if numbers.len() != count {
+                return Err(format!("`{key}={value}` must be {count} numbers"));
+            }
+            Ok(numbers)
+        };
+
+        match key {
+            // `WxH`, `Wx`, `xH`, with a trailing `!` to ignore the aspect ratio
+            "resize" => {
+                let (size, exact) = match value.strip_suffix('!') {
+                    Some(size) => (size, true),
+                    None => (value, false),
+                };
+                let (width, height) = size
+                    .split_once('x')
+                    .ok_or_else(|| format!("`resize={value}` must be like `200x100`"))?;
+                let dimension = |d: &str| {
+                    (!d.is_empty())
+                        .then(|| d.parse::<u32>())
+                        .transpose()
+                        .map_err(|_| format!("`resize={value}` must be like `200x100`"))
+                };
+                Ok(Self::Resize {
+                    width: dimension(width)?,
+                    height: dimension(height)?,
+                    exact,
+                })
+            }
+            "crop" => match numbers(4)?[..] {
+                [x, y, width, height] => Ok(Self::Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                }),
+                _ => unreachable!("4 numbers"),
+            },
+            "rotate" => Ok(Self::Rotate {
+                degrees: numbers(1)?[0],
+            }),
+            "grayscale" => Ok(Self::Grayscale),
+            "blur" => Ok(Self::Blur {
+                sigma: value
+                    .parse()
+                    .map_err(|_| format!("`blur={value}` must be a number"))?,
+            }),
+            _ => Err(format!("unknown operation `{key}`")),
+        }
+    }
+
+    fn apply(&self, image: DynamicImage) -> Result<DynamicImage, String> {
+        let check = |width: u32, height: u32| {
+            if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+                return Err(format!(
+                    "images must be between 1x1 and {MAX_DIMENSION}x{MAX_DIMENSION} pixels"
+                ));
+            }
+            if u64::from(width) * u64::from(height) > MAX_IMAGE_PIXELS {
+                return Err(format!(
+                    "images must not have more than {MAX_IMAGE_PIXELS} pixels, got {width}x{height}"
+                ));
+            }
+            Ok(())
+        };
+
+        Ok(match *self {
+            Self::Resize {
+                width,
+                height,
+                exact,
+            } => {
+                let (w, h) = (u64::from(image.width()), u64::from(image.height()));
+                let scaled = |size: u32, from: u64, to: u64| {
+                    u32::try_from((u64::from(size) * to + from / 2) / from.max(1))
+                        .unwrap_or(u32::MAX)
+                        .max(1)
+                };
+                let (width, height) = match (width, height) {
+                    (Some(width), Some(height)) => (width, height),
+                    (Some(width), None) => (width, scaled(width, w, h)),
+                    (None, Some(height)) => (scaled(height, h, w), height),
+                    (None, None) => return Err("resize needs a width or height".to_string()),
+                };
+                check(width, height)?;
+
+                if exact {
+                    image.resize_exact(width, height, FilterType::Lanczos3)
+                } else {
+                    image.resize(width, height, FilterType::Lanczos3)
+                }
+            }
+            Self::Crop {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let inside = |start: u32, len: u32, max: u32| {
+                    start.checked_add(len).is_some_and(|end| end <= max)
+                };
+                if !(inside(x, width, image.width()) && inside(y, height, image.height())) {
+                    return Err(format!(
+                        "crop of {width}x{height} at {x},{y} is outside the {}x{} image",
+                        image.width(),
+                        image.height()
+                    ));
+                }
+                check(width, height)?;
+
+                image.crop_imm(x, y, width, height)
+            }
+            Self::Rotate { degrees } => match degrees % 360 {
+                0 => image,
+                90 => image.rotate90(),
+                180 => image.rotate180(),
+                270 => image.rotate270(),
+                _ => return Err("rotate only supports multiples of 90 degrees".to_string()),
+            },
+            Self::Grayscale => image.grayscale(),
+            Self::Blur { sigma } => {
+                if !(0. ..=MAX_BLUR_SIGMA).contains(&sigma) {
+                    return Err(format!("blur must be between 0 and {MAX_BLUR_SIGMA}"));
+                }
+                image.blur(sigma)
+            }
+        })
+    }
+}
+
+/// Format of the images created by `/11/transform`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::WebP => Some(Self::Webp),
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+
+    /// Encode an image, JPEG with the given quality
+    fn encode(self, image: &DynamicImage, quality: u8) -> ImageResult<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut writer = Cursor::new(&mut data);
+        match self {
+            Self::Png => image.write_to(&mut writer, ImageFormat::Png)?,
+            // JPEG has no alpha channel
+            Self::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut writer, quality))?,
+            Self::Webp => DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut writer))?,
+        }
+
+        Ok(data)
+    }
+}
+
+/// A pipeline of `/11/transform` given as JSON
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Pipeline {
+    #[serde(default)]
+    operations: Vec<Operation>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+}
+
+impl Pipeline {
+    /// Read a pipeline from query parameters, applying the operations in the
+    /// order of the parameters
+    fn from_query(params: &[(String, String)]) -> Result<Self, String> {
+        let mut pipeline = Self {
+            operations: Vec::new(),
+            format: None,
+            quality: None,
+        };
+
+        for (key, value) in params {
+            match key.as_str() {
+                "format" => {
+                    pipeline.format = Some(
+                        serde_json::from_value(serde_json::Value::String(value.to_lowercase()))
+                            .map_err(|_| format!("unknown format `{value}`"))?,
+                    );
+                }
+                "quality" => {
+                    pipeline.quality = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("`quality={value}` must be a number"))?,
+                    );
+                }
+                _ => pipeline.operations.push(Operation::from_query(key, value)?),
+            }
+        }
+
+        Ok(pipeline)
+    }
+}
+
+/// Apply a pipeline of operations to an image
+///
+/// The pipeline is given either as query parameters or as JSON in the
+/// `pipeline` field of the multipart request.
+async fn transform(
+    Query(params): Query<Vec<(String, String)>>,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, String)> {
+    let bad_request =
+        |e: axum::extract::multipart::MultipartError| (StatusCode::BAD_REQUEST, e.body_text());
+    let invalid = |message: String| (StatusCode::UNPROCESSABLE_ENTITY, message);
+
+    let mut upload = None;
+    let mut pipeline = None;
+    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
+        match field.name() {
+            Some("image") => upload = Some(Upload::read(field).await?),
+            Some("pipeline") => {
+                let text = field.text().await.map_err(bad_request)?;
+                pipeline = Some(
+                    serde_json::from_str::<Pipeline>(&text)
+                        .map_err(|e| invalid(format!("invalid pipeline: {e}")))?,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let pipeline = match pipeline {
+        Some(_) if !params.is_empty() => {
+            return Err(invalid(
+                "the pipeline must be given either as query or as JSON".to_string(),
+            ))
+        }
+        Some(pipeline) => pipeline,
+        None => Pipeline::from_query(&params).map_err(invalid)?,
+    };
+    let quality = pipeline.quality.unwrap_or(JPEG_QUALITY);
+    if !(1..=100).contains(&quality) {
+        return Err(invalid("quality must be between 1 and 100".to_string()));
+    }
+
+    let upload = upload.ok_or(UploadError::MissingImage)?;
+
+    // Decoding, transforming and encoding takes a while, so it doesn't block
+    // other requests
+    tokio::task::spawn_blocking(move || {
+        let mut image = upload.decode()?;
+        for operation in &pipeline.operations {
+            image = operation.apply(image).map_err(invalid)?;
+        }
+
+        let format = pipeline
+            .format
+            .or_else(|| OutputFormat::from_image_format(upload.format))
+            .unwrap_or(OutputFormat::Png);
+        let data = format
+            .encode(&image, quality)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+        Ok(([(header::CONTENT_TYPE, format.content_type())], data).into_response())
+    })
+    .await
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+/// Largest asset `/11/assets` stores
+const MAX_ASSET_SIZE: usize = 32 * 1024 * 1024;
+
+/// `Cache-Control` of assets uploaded without one
+const DEFAULT_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// A stored file with its metadata
+#[derive(Clone, Debug)]
+struct Asset {
+    data: Bytes,
+    content_type: String,
+    /// SHA-256 of the data, used as ETag
+    hash: String,
+    cache_control: String,
+}
+
+impl Asset {
+    fn new(data: Bytes, content_type: String, cache_control: String) -> Self {
+        Self {
+            hash: sha256::digest(&data[..]),
+            data,
+            content_type,
+            cache_control,
+        }
+    }
+}
+
+/// Metadata of an asset, stored next to its data
+#[derive(Serialize, Deserialize)]
+struct AssetMeta {
+    content_type: String,
+    hash: String,
+    cache_control: String,
+}
+
+/// A thumbnail of a version of an asset, a size of 0 is not constrained
+#[derive(Clone, Debug, PartialEq)]
+struct ThumbnailKey {
+    hash: String,
+    width: u32,
+    height: u32,
+}
+
+/// Why the asset store failed
+#[derive(thiserror::Error, Debug)]
+enum StoreError {
+    #[error("asset storage failed: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("asset database failed: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<StoreError> for (StatusCode, String) {
+    fn from(e: StoreError) -> Self {
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+/// State of `/11/assets`
+#[derive(Clone)]
+struct AssetState {
+    store: Arc<dyn AssetStore>,
+    /// SHA-256 of the token needed to change assets, which are read-only
+    /// without one
+    token_hash: Option<String>,
+}
+
+impl FromRef<AssetState> for Arc<dyn AssetStore> {
+    fn from_ref(state: &AssetState) -> Self {
+        state.store.clone()
+    }
+}
+
+/// A request allowed to change assets, by the bearer token in `ASSET_TOKEN`
+struct AssetWriter;
 
-use axum::{extract::Multipart, http::StatusCode, routing::post, Router};
-use image::GenericImageView;
-use tower_http::services::ServeDir;
+#[axum::async_trait]
+impl FromRequestParts<AssetState> for AssetWriter {
+    type Rejection = Response;
 
-/// Get Day 11 routes
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AssetState,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token_hash) = &state.token_hash else {
+            return Err((StatusCode::FORBIDDEN, "assets are read-only").into_response());
+        };
+
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        // Hashes are compared to not leak the token by timing
+        match token {
+            Some(token) if sha256::digest(token.trim()) == *token_hash => Ok(Self),
+            _ => Err((
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                "changing assets needs a valid bearer token",
+            )
+                .into_response()),
+        }
+    }
+}
+
+/// Where the assets of `/11/assets` are stored
+trait AssetStore: Send + Sync {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<Asset>, StoreError>>;
+
+    /// Store an asset, replacing an existing one and its thumbnails
+    ///
+    /// Returns if the asset is new.
+    fn put<'a>(&'a self, name: &'a str, asset: Asset) -> BoxFuture<'a, Result<bool, StoreError>>;
+
+    /// Delete an asset and its thumbnails, returns if there was one
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, StoreError>>;
+
+    fn thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+    ) -> BoxFuture<'a, Result<Option<Bytes>, StoreError>>;
+
+    fn put_thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StoreError>>;
+}
+
+/// Assets in a directory
 ///
-/// * `/11/assets`
-/// * `/11/red_pixels`
-pub fn get_routes() -> Router {
-    Router::new()
-        .nest_service("/11/assets", ServeDir::new("assets"))
-        .route("/11/red_pixels", post(red_pixels))
-}
-
-async fn red_pixels(mut multipart: Multipart) -> Result<String, StatusCode> {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        if name == "image" {
-            let data = field.bytes().await.unwrap();
-            let decoder = image::ImageReader::new(Cursor::new(data))
-                .with_guessed_format()
-                .unwrap()
-                .decode()
-                .unwrap();
-            let magical_red = decoder.pixels().fold(0u64, |acc, (_x, _y, p)| {
-                if u16::from(p[0]) > u16::from(p[1]) + u16::from(p[2]) {
-                    acc + 1
-                } else {
-                    acc
+/// The metadata is stored in `.meta`, the thumbnails in `.thumbnails`. Files
+/// put into the directory by other means are served too, with their content
+/// type guessed from the extension.
+struct DiskStore {
+    root: PathBuf,
+}
+
+impl DiskStore {
+    fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn meta_path(&self, name: &str) -> PathBuf {
+        self.root.join(".meta").join(format!("{name}.json"))
+    }
+
+    fn thumbnail_dir(&self, name: &str) -> PathBuf {
+        self.root.join(".thumbnails").join(name)
+    }
+
+    fn thumbnail_path(&self, name: &str, key: &ThumbnailKey) -> PathBuf {
+        self.thumbnail_dir(name)
+            .join(format!("{}-{}x{}", key.hash, key.width, key.height))
+    }
+
+    /// Write a file atomically, so readers never see half of it
+    async fn write(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+        let tmp = Self::write_tmp(path, data).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+
+    /// Write a temporary file next to a file, to be renamed to it
+    async fn write_tmp(path: &std::path::Path, data: &[u8]) -> std::io::Result<PathBuf> {
+        let dir = path.parent().expect("asset paths have a parent");
+        tokio::fs::create_dir_all(dir).await?;
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = dir.join(format!(".tmp.{file_name}.{nanos}"));
+        if let Err(e) = tokio::fs::write(&tmp, data).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(e);
+        }
+
+        Ok(tmp)
+    }
+
+    async fn remove(path: &std::path::Path) -> std::io::Result<bool> {
+        match tokio::fs::remove_file(path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn remove_thumbnails(&self, name: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_dir_all(self.thumbnail_dir(name)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl AssetStore for DiskStore {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<Asset>, StoreError>> {
+        Box::pin(async move {
+            let data = match tokio::fs::read(self.root.join(name)).await {
+                Ok(data) => Bytes::from(data),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            let meta = tokio::fs::read(self.meta_path(name))
+                .await
+                .ok()
+                .and_then(|meta| serde_json::from_slice::<AssetMeta>(&meta).ok());
+            Ok(Some(match meta {
+                Some(meta) => Asset {
+                    data,
+                    content_type: meta.content_type,
+                    hash: meta.hash,
+                    cache_control: meta.cache_control,
+                },
+                None => Asset::new(
+                    data,
+                    content_type_of(name).to_string(),
+                    DEFAULT_CACHE_CONTROL.to_string(),
+                ),
+            }))
+        })
+    }
+
+    fn put<'a>(&'a self, name: &'a str, asset: Asset) -> BoxFuture<'a, Result<bool, StoreError>> {
+        Box::pin(async move {
+            let path = self.root.join(name);
+            let created = !tokio::fs::try_exists(&path).await?;
+
+            let meta = serde_json::to_vec(&AssetMeta {
+                content_type: asset.content_type,
+                hash: asset.hash,
+                cache_control: asset.cache_control,
+            })
+            .expect("metadata can be serialized");
+
+            // Both are written before either replaces the old one, so a failed
+            // write leaves the old asset. The data goes first, so its old bytes
+            // are never served with the new ETag.
+            let meta_path = self.meta_path(name);
+            let meta_tmp = Self::write_tmp(&meta_path, &meta).await?;
+            let data_tmp = match Self::write_tmp(&path, &asset.data).await {
+                Ok(tmp) => tmp,
+                Err(e) => {
+                    let _ = tokio::fs::remove_file(&meta_tmp).await;
+                    return Err(e.into());
                 }
-            });
+            };
+            tokio::fs::rename(&data_tmp, &path).await?;
+            tokio::fs::rename(&meta_tmp, &meta_path).await?;
+            self.remove_thumbnails(name).await?;
+
+            Ok(created)
+        })
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, StoreError>> {
+        Box::pin(async move {
+            let existed = Self::remove(&self.root.join(name)).await?;
+            Self::remove(&self.meta_path(name)).await?;
+            self.remove_thumbnails(name).await?;
+
+            Ok(existed)
+        })
+    }
+
+    fn thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+    ) -> BoxFuture<'a, Result<Option<Bytes>, StoreError>> {
+        Box::pin(async move {
+            match tokio::fs::read(self.thumbnail_path(name, key)).await {
+                Ok(data) => Ok(Some(data.into())),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn put_thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        Box::pin(async move { Ok(Self::write(&self.thumbnail_path(name, key), &data).await?) })
+    }
+}
+
+#[derive(Iden)]
+enum Assets {
+    Table,
+    Name,
+    Oid,
+    ContentType,
+    Hash,
+    CacheControl,
+}
+
+#[derive(Iden)]
+enum AssetThumbnails {
+    Table,
+    Name,
+    Hash,
+    Width,
+    Height,
+    Data,
+}
+
+#[derive(FromRow)]
+struct AssetRow {
+    content_type: String,
+    hash: String,
+    cache_control: String,
+    data: Vec<u8>,
+}
+
+#[derive(FromRow)]
+struct ThumbnailRow(Vec<u8>);
+
+/// Assets in Postgres, the data in large objects and thumbnails in the
+/// `asset_thumbnails` table
+struct PgStore {
+    pool: PgPool,
+}
 
-            return Ok(magical_red.to_string());
+impl PgStore {
+    /// Unlink the large object of an asset
+    fn unlink(name: &str) -> (String, SqlxValues) {
+        sea_query::Query::select()
+            .expr(Func::cust(Alias::new("lo_unlink")).arg(Expr::col(Assets::Oid)))
+            .from(Assets::Table)
+            .and_where(Expr::col(Assets::Name).eq(name))
+            .build_sqlx(PostgresQueryBuilder)
+    }
+}
+
+impl AssetStore for PgStore {
+    fn get<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<Option<Asset>, StoreError>> {
+        Box::pin(async move {
+            let (sql, values) = sea_query::Query::select()
+                .columns([Assets::ContentType, Assets::Hash, Assets::CacheControl])
+                .expr_as(
+                    Func::cust(Alias::new("lo_get")).arg(Expr::col(Assets::Oid)),
+                    Alias::new("data"),
+                )
+                .from(Assets::Table)
+                .and_where(Expr::col(Assets::Name).eq(name))
+                .build_sqlx(PostgresQueryBuilder);
+
+            let row = sqlx::query_as_with::<_, AssetRow, _>(&sql, values)
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(row.map(|row| Asset {
+                data: row.data.into(),
+                content_type: row.content_type,
+                hash: row.hash,
+                cache_control: row.cache_control,
+            }))
+        })
+    }
+
+    fn put<'a>(&'a self, name: &'a str, asset: Asset) -> BoxFuture<'a, Result<bool, StoreError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            let (sql, values) = Self::unlink(name);
+            let replaced = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(AssetThumbnails::Table)
+                .and_where(Expr::col(AssetThumbnails::Name).eq(name))
+                .build_sqlx(PostgresQueryBuilder);
+            sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+
+            let (sql, values) = sea_query::Query::insert()
+                .into_table(Assets::Table)
+                .columns([
+                    Assets::Name,
+                    Assets::Oid,
+                    Assets::ContentType,
+                    Assets::Hash,
+                    Assets::CacheControl,
+                ])
+                .values_panic([
+                    name.into(),
+                    Func::cust(Alias::new("lo_from_bytea"))
+                        .args([Expr::val(0).into(), Expr::val(asset.data.to_vec()).into()])
+                        .into(),
+                    asset.content_type.into(),
+                    asset.hash.into(),
+                    asset.cache_control.into(),
+                ])
+                .on_conflict(
+                    OnConflict::column(Assets::Name)
+                        .update_columns([
+                            Assets::Oid,
+                            Assets::ContentType,
+                            Assets::Hash,
+                            Assets::CacheControl,
+                        ])
+                        .to_owned(),
+                )
+                .build_sqlx(PostgresQueryBuilder);
+            sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+
+            tx.commit().await?;
+
+            Ok(!replaced)
+        })
+    }
+
+    fn delete<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, StoreError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            let (sql, values) = Self::unlink(name);
+            sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+
+            // The thumbnails are deleted with the asset
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(Assets::Table)
+                .and_where(Expr::col(Assets::Name).eq(name))
+                .build_sqlx(PostgresQueryBuilder);
+            let deleted = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected()
+                > 0;
+
+            tx.commit().await?;
+
+            Ok(deleted)
+        })
+    }
+
+    fn thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+    ) -> BoxFuture<'a, Result<Option<Bytes>, StoreError>> {
+        Box::pin(async move {
+            let (sql, values) = sea_query::Query::select()
+                .column(AssetThumbnails::Data)
+                .from(AssetThumbnails::Table)
+                .and_where(Expr::col(AssetThumbnails::Name).eq(name))
+                .and_where(Expr::col(AssetThumbnails::Hash).eq(key.hash.as_str()))
+                .and_where(Expr::col(AssetThumbnails::Width).eq(i64::from(key.width)))
+                .and_where(Expr::col(AssetThumbnails::Height).eq(i64::from(key.height)))
+                .build_sqlx(PostgresQueryBuilder);
+
+            let row = sqlx::query_as_with::<_, ThumbnailRow, _>(&sql, values)
+                .fetch_optional(&self.pool)
+                .await?;
+
+            Ok(row.map(|row| row.0.into()))
+        })
+    }
+
+    fn put_thumbnail<'a>(
+        &'a self,
+        name: &'a str,
+        key: &'a ThumbnailKey,
+        data: Bytes,
+    ) -> BoxFuture<'a, Result<(), StoreError>> {
+        Box::pin(async move {
+            let (sql, values) = sea_query::Query::insert()
+                .into_table(AssetThumbnails::Table)
+                .columns([
+                    AssetThumbnails::Name,
+                    AssetThumbnails::Hash,
+                    AssetThumbnails::Width,
+                    AssetThumbnails::Height,
+                    AssetThumbnails::Data,
+                ])
+                .values_panic([
+                    name.into(),
+                    key.hash.clone().into(),
+                    i64::from(key.width).into(),
+                    i64::from(key.height).into(),
+                    data.to_vec().into(),
+                ])
+                .on_conflict(
+                    OnConflict::columns([
+                        AssetThumbnails::Name,
+                        AssetThumbnails::Hash,
+                        AssetThumbnails::Width,
+                        AssetThumbnails::Height,
+                    ])
+                    .do_nothing()
+                    .to_owned(),
+                )
+                .build_sqlx(PostgresQueryBuilder);
+
+            sqlx::query_with(&sql, values).execute(&self.pool).await?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Guess the content type of a file from its extension
+///
+/// Only the images that can be uploaded are recognized, everything else is
+/// served as a download.
+fn content_type_of(name: &str) -> &'static str {
+    let extension = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Names of assets are plain file names
+fn check_asset_name(name: &str) -> Result<(), (StatusCode, String)> {
+    let valid = (1..=255).contains(&name.len())
+        && !name.starts_with('.')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'));
+    if !valid {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "asset names must only contain letters, digits, `.`, `_` and `-`, and not start with `.`"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Widths and heights thumbnails can be scaled to, so only a few are stored
+const THUMBNAIL_SIZES: [u32; 5] = [32, 64, 128, 256, 512];
+
+#[derive(Deserialize)]
+struct ThumbnailParams {
+    w: Option<u32>,
+    h: Option<u32>,
+}
+
+/// Serve an asset, or a thumbnail of it with `?w=` and/or `?h=`
+async fn get_asset(
+    State(store): State<Arc<dyn AssetStore>>,
+    Path(name): Path<String>,
+    Query(params): Query<ThumbnailParams>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    check_asset_name(&name)?;
+    let asset = store
+        .get(&name)
+        .await?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no asset `{name}`")))?;
+
+    if params.w.is_none() && params.h.is_none() {
+        let etag = format!("\"{}\"", asset.hash);
+        return Ok(serve(
+            &headers,
+            &name,
+            asset.data,
+            &asset.content_type,
+            &etag,
+            &asset.cache_control,
+        ));
+    }
+
+    let invalid = |message: String| (StatusCode::UNPROCESSABLE_ENTITY, message);
+    let size = |size: Option<u32>| match size {
+        None => Ok(0),
+        Some(size) if THUMBNAIL_SIZES.contains(&size) => Ok(size),
+        Some(_) => Err(invalid(format!(
+            "thumbnails can only be {THUMBNAIL_SIZES:?} pixels"
+        ))),
+    };
+    let key = ThumbnailKey {
+        hash: asset.hash.clone(),
+        width: size(params.w)?,
+        height: size(params.h)?,
+    };
+    let format = asset
+        .content_type
+        .strip_prefix("image/")
+        .and_then(ImageFormat::from_extension)
+        .and_then(OutputFormat::from_image_format)
+        .unwrap_or(OutputFormat::Png);
+
+    let data = match store.thumbnail(&name, &key).await? {
+        Some(data) => data,
+        None => {
+            let upload = Upload::new(None, asset.data.to_vec(), None)
+                .map_err(|e| invalid(format!("`{name}` is not an image: {e}")))?;
+            let image = upload.decode()?;
+            let bound = |size: u32| if size == 0 { MAX_DIMENSION } else { size };
+            let thumbnail = image.thumbnail(bound(key.width), bound(key.height));
+            let data = Bytes::from(
+                format
+                    .encode(&thumbnail, JPEG_QUALITY)
+                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+            );
+
+            store.put_thumbnail(&name, &key, data.clone()).await?;
+            data
         }
+    };
+
+    let etag = format!("\"{}-{}x{}\"", key.hash, key.width, key.height);
+    Ok(serve(
+        &headers,
+        &name,
+        data,
+        format.content_type(),
+        &etag,
+        &asset.cache_control,
+    ))
+}
+
+/// Respond with data, honoring `If-None-Match`, `Range` and `If-Range`
+///
+/// Browsers are told to not guess the content type, and to download anything
+/// but images.
+fn serve(
+    headers: &HeaderMap,
+    name: &str,
+    data: Bytes,
+    content_type: &str,
+    etag: &str,
+    cache_control: &str,
+) -> Response {
+    // Names are plain file names, which need no escaping
+    let disposition = if content_type.starts_with("image/") {
+        format!("inline; filename=\"{name}\"")
+    } else {
+        format!("attachment; filename=\"{name}\"")
+    };
+    let common = [
+        (header::ETAG, etag.to_string()),
+        (header::CACHE_CONTROL, cache_control.to_string()),
+        (header::ACCEPT_RANGES, "bytes".to_string()),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    let header = |name: header::HeaderName| headers.get(name).and_then(|v| v.to_str().ok());
+
+    let not_modified = header(header::IF_NONE_MATCH).is_some_and(|tags| {
+        tags.split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag)
+    });
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, common).into_response();
+    }
+
+    // Ranges of an outdated version are ignored
+    let range =
+        header(header::RANGE).filter(|_| header(header::IF_RANGE).is_none_or(|tag| tag == etag));
+    let len = data.len() as u64;
+    match range.map(|range| parse_range(range, len)) {
+        Some(Some(Ok((start, end)))) => (
+            StatusCode::PARTIAL_CONTENT,
+            common,
+            [
+                (header::CONTENT_TYPE, content_type.to_string()),
+                (header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+            ],
+            data.slice(start as usize..=end as usize),
+        )
+            .into_response(),
+        Some(Some(Err(()))) => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            common,
+            [(header::CONTENT_RANGE, format!("bytes */{len}"))],
+        )
+            .into_response(),
+        // Without a range, or one we don't support
+        _ => (
+            common,
+            [(header::CONTENT_TYPE, content_type.to_string())],
+            data,
+        )
+            .into_response(),
     }
+}
+
+/// Parse a single byte range like `bytes=0-499`, `bytes=500-` or `bytes=-500`
+/// into the first and last byte
+///
+/// Returns `None` for ranges that are ignored, like multiple ranges, and an
+/// error for ranges outside the data.
+fn parse_range(range: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    let range = match (start.is_empty(), end.is_empty()) {
+        (false, _) => {
+            let start: u64 = start.parse().ok()?;
+            let end: u64 = if end.is_empty() {
+                u64::MAX
+            } else {
+                end.parse().ok()?
+            };
+            if end < start {
+                return None;
+            }
+            (start, end.min(len.saturating_sub(1)))
+        }
+        (true, false) => {
+            let suffix: u64 = end.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        (true, true) => return None,
+    };
 
-    Err(StatusCode::BAD_REQUEST)
+    Some(if range.0 < len { Ok(range) } else { Err(()) })
+}
+
+/// Upload an image as asset, with the `Cache-Control` to serve it with
+async fn put_asset(
+    _writer: AssetWriter,
+    State(store): State<Arc<dyn AssetStore>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    data: Bytes,
+) -> Result<Response, (StatusCode, String)> {
+    check_asset_name(&name)?;
+    let header = |name: header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+
+    // Only images are served from our origin, so no scripts can be uploaded
+    let content_type = header(header::CONTENT_TYPE);
+    let upload = Upload::new(None, data.to_vec(), content_type.as_deref())?;
+    let asset = Asset::new(
+        data,
+        upload.format.to_mime_type().to_string(),
+        header(header::CACHE_CONTROL).unwrap_or_else(|| DEFAULT_CACHE_CONTROL.to_string()),
+    );
+    let etag = format!("\"{}\"", asset.hash);
+    let created = store.put(&name, asset).await?;
+
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    };
+    Ok((status, [(header::ETAG, etag)]).into_response())
+}
+
+async fn delete_asset(
+    _writer: AssetWriter,
+    State(store): State<Arc<dyn AssetStore>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    check_asset_name(&name)?;
+
+    match store.delete(&name).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err((StatusCode::NOT_FOUND, format!("no asset `{name}`"))),
+        Err(e) => Err(e.into()),
+    }
 }
 
 #[cfg(test)]
@@ -100,9 +2368,30 @@ mod test {
     use axum_test::TestServer;
     use tower::util::ServiceExt;
 
+    fn app() -> Router {
+        router(Arc::new(DiskStore::new("assets")), None)
+    }
+
+    const TOKEN: &str = "elf-access";
+
+    /// The header and an empty first chunk of a 6000x5000 PNG, which must be
+    /// rejected before decoding
+    const HUGE_PNG: [u8; 45] = [
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x17, 0x70, 0x00, 0x00, 0x13, 0x88, 0x08, 0x00, 0x00, 0x00, 0x00, 0xcb,
+        0xb1, 0x67, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x49, 0x44, 0x41, 0x54, 0x35, 0xaf, 0x06, 0x1e,
+    ];
+
+    /// A server that accepts changes of assets with [`TOKEN`]
+    fn writable(store: Arc<DiskStore>) -> TestServer {
+        let mut server = TestServer::new(router(store, Some(TOKEN))).unwrap();
+        server.add_header(header::AUTHORIZATION, format!("Bearer {TOKEN}"));
+        server
+    }
+
     #[tokio::test]
     async fn test_task1() {
-        let app = get_routes();
+        let app = app();
 
         let req = Request::builder()
             .method(Method::GET)
@@ -118,7 +2407,7 @@ mod test {
 
     #[tokio::test]
     async fn test_task2() {
-        let app = get_routes();
+        let app = app();
 
         let server = TestServer::new(app).unwrap();
 
@@ -130,4 +2419,665 @@ mod test {
 
         response.assert_text("73034");
     }
+
+    fn image_form(predicates: Option<&str>) -> axum_test::multipart::MultipartForm {
+        let file = std::fs::read("assets/decoration.png").unwrap();
+        let form = axum_test::multipart::MultipartForm::new()
+            .add_part("image", axum_test::multipart::Part::bytes(file));
+        match predicates {
+            Some(predicates) => form.add_text("predicates", predicates.to_string()),
+            None => form,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analyze() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .post("/11/analyze")
+            .add_query_param("bins", 4)
+            .add_query_param("palette", 3)
+            .multipart(image_form(Some(
+                r#"[
+                    {"name": "magical red", "expression": "r > g + b"},
+                    {"name": "also magical red", "expression": "!(r <= (g + b) * 1)"},
+                    {"name": "opaque", "expression": "a == 255 || a > 254.5"},
+                    {"name": "everything", "hsv": {}}
+                ]"#,
+            )))
+            .await;
+
+        response.assert_status_ok();
+        let analysis: serde_json::Value = response.json();
+        let pixels = analysis["pixels"].as_u64().unwrap();
+        assert_eq!(
+            pixels,
+            analysis["width"].as_u64().unwrap() * analysis["height"].as_u64().unwrap()
+        );
+        let predicates = analysis["predicates"].as_array().unwrap();
+        assert_eq!(predicates[0]["name"], "magical red");
+        assert_eq!(predicates[0]["count"], 73034);
+        assert_eq!(predicates[1]["count"], 73034);
+        assert_eq!(predicates[3]["count"], pixels);
+        assert_eq!(predicates[3]["percentage"], 100.0);
+
+        for channel in ["r", "g", "b", "a"] {
+            let bins = analysis["histogram"][channel].as_array().unwrap();
+            assert_eq!(bins.len(), 4);
+            assert_eq!(
+                bins.iter().map(|n| n.as_u64().unwrap()).sum::<u64>(),
+                pixels
+            );
+        }
+        let palette = analysis["palette"].as_array().unwrap();
+        assert_eq!(palette.len(), 3);
+        assert!(palette[0]["count"].as_u64() >= palette[1]["count"].as_u64());
+        assert!(palette[0]["color"].as_str().unwrap().starts_with('#'));
+
+        // Magical red by default
+        let response = server.post("/11/analyze").multipart(image_form(None)).await;
+        let analysis: serde_json::Value = response.json();
+        assert_eq!(analysis["predicates"][0]["count"], 73034);
+
+        // Images larger than the default body limit
+        let file = noisy_png(800, 800);
+        assert!(file.len() > 2 * 1024 * 1024);
+        let form = axum_test::multipart::MultipartForm::new()
+            .add_part("image", axum_test::multipart::Part::bytes(file));
+        let response = server.post("/11/analyze").multipart(form).await;
+        response.assert_status_ok();
+        let analysis: serde_json::Value = response.json();
+        assert_eq!(analysis["pixels"], 640000);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_invalid() {
+        let server = TestServer::new(app()).unwrap();
+
+        for predicates in [
+            r#"[{"name": "x", "expression": "r > "}]"#,
+            r#"[{"name": "x", "expression": "q > 1"}]"#,
+            r#"[{"name": "x", "expression": "(r > 1"}]"#,
+            r#"[{"name": "x"}]"#,
+            r#"{"name": "x"}"#,
+        ] {
+            server
+                .post("/11/analyze")
+                .multipart(image_form(Some(predicates)))
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let deep = format!("{}r{}", "(".repeat(100), ")".repeat(100));
+        assert!(deep.parse::<ColorExpr>().is_err());
+
+        let uploads = [
+            (b"not an image".to_vec(), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            (HUGE_PNG.to_vec(), StatusCode::PAYLOAD_TOO_LARGE),
+            (vec![0; MAX_IMAGE_SIZE + 1], StatusCode::PAYLOAD_TOO_LARGE),
+        ];
+        for (data, status) in uploads {
+            let form = axum_test::multipart::MultipartForm::new()
+                .add_part("image", axum_test::multipart::Part::bytes(data));
+            server
+                .post("/11/analyze")
+                .multipart(form)
+                .await
+                .assert_status(status);
+        }
+    }
+
+    #[test]
+    fn test_color_expr() {
+        let color = Color::new(Rgba([255, 0, 0, 255]));
+        let eval = |expr: &str| expr.parse::<ColorExpr>().unwrap().eval(&color);
+
+        assert_eq!(eval("r - 2 * 100 / 4"), 205.);
+        assert_eq!(eval("-(r - 5) + 1"), -249.);
+        assert_eq!(eval("h == 0 && s == 1 && v == 1"), 1.);
+        assert_eq!(eval("g > 0 || !b"), 1.);
+        // Comparisons don't chain
+        assert!("1 < 2 == 1".parse::<ColorExpr>().is_err());
+
+        let purple = Color::new(Rgba([128, 0, 255, 255]));
+        assert!((purple.h - 270.1).abs() < 0.1);
+    }
+
+    #[tokio::test]
+    async fn test_red_pixels_output() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .post("/11/red_pixels?output=mask")
+            .multipart(image_form(None))
+            .await;
+        response.assert_status_ok();
+        response.assert_header(header::CONTENT_TYPE, "image/png");
+        let mask = image::load_from_memory(&response.into_bytes())
+            .unwrap()
+            .to_luma8();
+        assert_eq!(mask.pixels().filter(|p| p[0] == 255).count(), 73034);
+        assert!(mask.pixels().all(|p| p[0] == 0 || p[0] == 255));
+
+        let response = server
+            .post("/11/red_pixels?output=highlight")
+            .multipart(image_form(None))
+            .await;
+        response.assert_header(header::CONTENT_TYPE, "image/png");
+        let highlighted = image::load_from_memory(&response.into_bytes()).unwrap();
+        let original = image::open("assets/decoration.png").unwrap();
+        assert_eq!(highlighted.dimensions(), original.dimensions());
+        let red = highlighted
+            .pixels()
+            .filter(|(_, _, p)| is_magical_red(*p))
+            .count();
+        assert_eq!(red, 73034);
+
+        let response = server
+            .post("/11/red_pixels?output=regions")
+            .multipart(image_form(None))
+            .await;
+        let regions: serde_json::Value = response.json();
+        assert_eq!(regions["count"], 73034);
+        let total: u64 = regions["regions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["pixels"].as_u64().unwrap())
+            .sum();
+        assert_eq!(total, 73034);
+
+        server
+            .post("/11/red_pixels?output=poster")
+            .multipart(image_form(None))
+            .await
+            .assert_status_bad_request();
+    }
+
+    #[tokio::test]
+    async fn test_red_pixels_uploads() {
+        use axum_test::multipart::{MultipartForm, Part};
+
+        let server = TestServer::new(app()).unwrap();
+        let file = std::fs::read("assets/decoration.png").unwrap();
+        let image =
+            |name: &str, mime: &str| Part::bytes(file.clone()).file_name(name).mime_type(mime);
+        let red_pixels = |form: MultipartForm| server.post("/11/red_pixels").multipart(form);
+
+        let form = MultipartForm::new()
+            .add_part("image", image("a.png", "image/png"))
+            .add_part("image", image("b.png", "application/octet-stream"));
+        let response = red_pixels(form).await;
+        response.assert_status_ok();
+        response.assert_json(&serde_json::json!([
+            {"filename": "a.png", "count": 73034},
+            {"filename": "b.png", "count": 73034}
+        ]));
+
+        let form = MultipartForm::new()
+            .add_part("image", image("a.png", "image/png"))
+            .add_part("image", image("b.png", "image/png"));
+        server
+            .post("/11/red_pixels?output=mask")
+            .multipart(form)
+            .await
+            .assert_status_bad_request();
+
+        let mut form = MultipartForm::new();
+        for _ in 0..=MAX_IMAGES {
+            form = form.add_part("image", image("a.png", "image/png"));
+        }
+        red_pixels(form).await.assert_status_bad_request();
+
+        red_pixels(MultipartForm::new().add_text("picture", "decoration.png"))
+            .await
+            .assert_status_bad_request();
+
+        // The declared content type and the content are checked
+        let form = MultipartForm::new().add_part("image", image("a.png", "text/plain"));
+        red_pixels(form)
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let form = MultipartForm::new().add_part("image", image("a.jpg", "image/jpeg"));
+        red_pixels(form)
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let form = MultipartForm::new().add_part("image", Part::bytes(b"red".to_vec()));
+        red_pixels(form)
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let form = MultipartForm::new().add_part("image", Part::bytes(file[..100].to_vec()));
+        red_pixels(form)
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+
+        let form = MultipartForm::new().add_part("image", Part::bytes(vec![0; MAX_IMAGE_SIZE + 1]));
+        red_pixels(form)
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+        let form = MultipartForm::new().add_part("image", Part::bytes(HUGE_PNG.to_vec()));
+        let response = red_pixels(form).await;
+        response.assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        response.assert_text("images must not have more than 25000000 pixels, got 6000x5000");
+    }
+
+    #[test]
+    fn test_regions() {
+        // Two regions, diagonal neighbors are not connected
+        let mask = GrayImage::from_fn(5, 4, |x, y| {
+            let white = matches!(
+                (x, y),
+                (0, 0) | (1, 0) | (1, 1) | (2, 2) | (4, 0) | (4, 1) | (4, 2) | (4, 3)
+            );
+            Luma([if white { 255 } else { 0 }])
+        });
+
+        assert_eq!(
+            regions(&mask, 1),
+            vec![
+                Region {
+                    x: 4,
+                    y: 0,
+                    width: 1,
+                    height: 4,
+                    pixels: 4
+                },
+                Region {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 2,
+                    pixels: 3
+                },
+                Region {
+                    x: 2,
+                    y: 2,
+                    width: 1,
+                    height: 1,
+                    pixels: 1
+                },
+            ]
+        );
+        assert_eq!(regions(&mask, 2).len(), 2);
+    }
+
+    async fn transform_with(
+        server: &TestServer,
+        query: &str,
+        pipeline: Option<&str>,
+    ) -> axum_test::TestResponse {
+        let file = std::fs::read("assets/decoration.png").unwrap();
+        let mut form = axum_test::multipart::MultipartForm::new()
+            .add_part("image", axum_test::multipart::Part::bytes(file));
+        if let Some(pipeline) = pipeline {
+            form = form.add_text("pipeline", pipeline.to_string());
+        }
+
+        server
+            .post(&format!("/11/transform{query}"))
+            .multipart(form)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_transform() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = transform_with(
+            &server,
+            "?crop=0,0,256,128&resize=64x&rotate=90&grayscale=&format=jpeg&quality=90",
+            None,
+        )
+        .await;
+        response.assert_status_ok();
+        response.assert_header(header::CONTENT_TYPE, "image/jpeg");
+        let image = image::load_from_memory(&response.into_bytes()).unwrap();
+        assert_eq!(image.dimensions(), (32, 64));
+
+        let response = transform_with(
+            &server,
+            "",
+            Some(
+                r#"{
+                    "operations": [
+                        {"op": "resize", "width": 100, "height": 50, "exact": true},
+                        {"op": "blur", "sigma": 1.5}
+                    ],
+                    "format": "webp"
+                }"#,
+            ),
+        )
+        .await;
+        response.assert_status_ok();
+        response.assert_header(header::CONTENT_TYPE, "image/webp");
+        let image = image::load_from_memory(&response.into_bytes()).unwrap();
+        assert_eq!(image.dimensions(), (100, 50));
+
+        // The format of the input by default
+        let response = transform_with(&server, "?resize=10x10", None).await;
+        response.assert_header(header::CONTENT_TYPE, "image/png");
+        let image = image::load_from_memory(&response.into_bytes()).unwrap();
+        assert_eq!(image.dimensions(), (10, 10));
+
+        // Images larger than the default body limit
+        let file = noisy_png(800, 800);
+        assert!(file.len() > 2 * 1024 * 1024);
+        let form = axum_test::multipart::MultipartForm::new()
+            .add_part("image", axum_test::multipart::Part::bytes(file));
+        let response = server
+            .post("/11/transform?resize=80x")
+            .multipart(form)
+            .await;
+        response.assert_status_ok();
+        let image = image::load_from_memory(&response.into_bytes()).unwrap();
+        assert_eq!(image.dimensions(), (80, 80));
+    }
+
+    #[tokio::test]
+    async fn test_transform_invalid() {
+        let server = TestServer::new(app()).unwrap();
+
+        for query in [
+            "?crop=500,500,100,100",
+            "?crop=1,2,3",
+            "?rotate=45",
+            "?resize=100000x",
+            "?resize=8000x8000!",
+            "?resize=big",
+            "?sharpen=1",
+            "?format=gif",
+            "?quality=0",
+            "?blur=1000",
+            "?blur=20.5",
+        ] {
+            transform_with(&server, query, None)
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        transform_with(&server, "?grayscale=", Some(r#"{"operations": []}"#))
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        transform_with(&server, "", Some(r#"{"operations": [{"op": "explode"}]}"#))
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        let form = axum_test::multipart::MultipartForm::new().add_part(
+            "image",
+            axum_test::multipart::Part::bytes(HUGE_PNG.to_vec()),
+        );
+        server
+            .post("/11/transform?grayscale=")
+            .multipart(form)
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    /// A PNG of noise, which doesn't compress
+    fn noisy_png(width: u32, height: u32) -> Vec<u8> {
+        let mut state = 0x2023_1211_u32;
+        let image = image::RgbaImage::from_fn(width, height, |_, _| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            Rgba(state.to_le_bytes())
+        });
+        OutputFormat::Png
+            .encode(&DynamicImage::ImageRgba8(image), JPEG_QUALITY)
+            .unwrap()
+    }
+
+    /// A blank PNG of the given size
+    fn blank_png(width: u32, height: u32) -> Vec<u8> {
+        OutputFormat::Png
+            .encode(&DynamicImage::new_rgb8(width, height), JPEG_QUALITY)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_assets() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = writable(Arc::new(DiskStore::new(dir.path())));
+        let (first, second) = (blank_png(2, 2), blank_png(3, 3));
+
+        server
+            .get("/11/assets/star.png")
+            .await
+            .assert_status_not_found();
+
+        let response = server
+            .put("/11/assets/star.png")
+            .add_header(header::CACHE_CONTROL, "no-cache")
+            .bytes(first.clone().into())
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        let etag = format!("\"{}\"", sha256::digest(&first[..]));
+        assert_eq!(response.header(header::ETAG), etag);
+
+        let response = server.get("/11/assets/star.png").await;
+        response.assert_status_ok();
+        assert_eq!(response.as_bytes()[..], first[..]);
+        assert_eq!(response.header(header::ETAG), etag);
+        assert_eq!(response.header(header::CACHE_CONTROL), "no-cache");
+        assert_eq!(response.header(header::CONTENT_TYPE), "image/png");
+        assert_eq!(response.header(header::X_CONTENT_TYPE_OPTIONS), "nosniff");
+        assert_eq!(
+            response.header(header::CONTENT_DISPOSITION),
+            "inline; filename=\"star.png\""
+        );
+
+        server
+            .get("/11/assets/star.png")
+            .add_header(header::IF_NONE_MATCH, etag.clone())
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+
+        let response = server
+            .put("/11/assets/star.png")
+            .add_header(header::CONTENT_TYPE, "image/png")
+            .bytes(second.clone().into())
+            .await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        let response = server
+            .get("/11/assets/star.png")
+            .add_header(header::IF_NONE_MATCH, etag)
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.as_bytes()[..], second[..]);
+        assert_eq!(
+            response.header(header::CACHE_CONTROL),
+            DEFAULT_CACHE_CONTROL
+        );
+
+        server
+            .delete("/11/assets/star.png")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        server
+            .delete("/11/assets/star.png")
+            .await
+            .assert_status_not_found();
+        server
+            .get("/11/assets/star.png")
+            .await
+            .assert_status_not_found();
+
+        // Only images that match their declared type can be uploaded
+        let uploads = [
+            ("text/html", b"<script>alert('grinch')</script>".to_vec()),
+            (
+                "image/svg+xml",
+                b"<svg onload=\"alert('grinch')\"/>".to_vec(),
+            ),
+            ("image/jpeg", first.clone()),
+        ];
+        for (content_type, data) in uploads {
+            server
+                .put("/11/assets/star.png")
+                .add_header(header::CONTENT_TYPE, content_type)
+                .bytes(data.into())
+                .await
+                .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        server
+            .put("/11/assets/star.html")
+            .bytes("<script>alert('grinch')</script>".into())
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // Other files in the directory are downloaded
+        std::fs::write(dir.path().join("notes.html"), "<p>Ho ho ho!</p>").unwrap();
+        let response = server.get("/11/assets/notes.html").await;
+        assert_eq!(
+            response.header(header::CONTENT_TYPE),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            response.header(header::CONTENT_DISPOSITION),
+            "attachment; filename=\"notes.html\""
+        );
+
+        for name in [".meta", "..hidden", "a%2Fb", "a%20b"] {
+            server
+                .put(&format!("/11/assets/{name}"))
+                .bytes("".into())
+                .await
+                .assert_status_bad_request();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_asset_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(DiskStore::new(dir.path()));
+
+        let server = TestServer::new(router(store.clone(), None)).unwrap();
+        server
+            .put("/11/assets/note.txt")
+            .bytes("Ho ho ho!".into())
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        server
+            .delete("/11/assets/decoration.png")
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        let server = TestServer::new(router(store, Some(TOKEN))).unwrap();
+        for authorization in [None, Some("Bearer grinch"), Some(TOKEN)] {
+            let mut request = server.put("/11/assets/note.txt").bytes("Ho ho ho!".into());
+            if let Some(authorization) = authorization {
+                request = request.add_header(header::AUTHORIZATION, authorization);
+            }
+            let response = request.await;
+            response.assert_status(StatusCode::UNAUTHORIZED);
+            assert_eq!(response.header(header::WWW_AUTHENTICATE), "Bearer");
+        }
+        server
+            .get("/11/assets/note.txt")
+            .await
+            .assert_status_not_found();
+    }
+
+    #[tokio::test]
+    async fn test_asset_ranges() {
+        let server = TestServer::new(app()).unwrap();
+        let file = std::fs::read("assets/decoration.png").unwrap();
+
+        let range = |range: &'static str| {
+            server
+                .get("/11/assets/decoration.png")
+                .add_header(header::RANGE, range)
+        };
+
+        let response = range("bytes=0-99").await;
+        response.assert_status(StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.header(header::CONTENT_RANGE), "bytes 0-99/787297");
+        assert_eq!(response.as_bytes()[..], file[..100]);
+
+        let response = range("bytes=787200-").await;
+        response.assert_status(StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.header(header::CONTENT_RANGE),
+            "bytes 787200-787296/787297"
+        );
+        assert_eq!(response.as_bytes()[..], file[787200..]);
+
+        let response = range("bytes=-10").await;
+        response.assert_status(StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.as_bytes()[..], file[file.len() - 10..]);
+
+        let response = range("bytes=787297-").await;
+        response.assert_status(StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.header(header::CONTENT_RANGE), "bytes */787297");
+
+        // Multiple ranges and outdated versions get the whole file
+        range("bytes=0-1,5-6").await.assert_status_ok();
+        range("bytes=0-99")
+            .add_header(header::IF_RANGE, "\"outdated\"")
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_asset_thumbnails() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(DiskStore::new(dir.path()));
+        let server = writable(store.clone());
+
+        let file = std::fs::read("assets/decoration.png").unwrap();
+        let hash = sha256::digest(&file[..]);
+        server
+            .put("/11/assets/decoration.png")
+            .bytes(file.into())
+            .await
+            .assert_status(StatusCode::CREATED);
+
+        let response = server.get("/11/assets/decoration.png?w=64").await;
+        response.assert_status_ok();
+        assert_eq!(response.header(header::CONTENT_TYPE), "image/png");
+        let thumbnail = image::load_from_memory(response.as_bytes()).unwrap();
+        assert_eq!(thumbnail.dimensions(), (64, 64));
+
+        let key = ThumbnailKey {
+            hash: hash.clone(),
+            width: 64,
+            height: 0,
+        };
+        assert_eq!(
+            store.thumbnail("decoration.png", &key).await.unwrap(),
+            Some(Bytes::copy_from_slice(response.as_bytes()))
+        );
+        assert_eq!(response.header(header::ETAG), format!("\"{hash}-64x0\""));
+
+        let response = server.get("/11/assets/decoration.png?w=128&h=32").await;
+        let thumbnail = image::load_from_memory(response.as_bytes()).unwrap();
+        assert_eq!(thumbnail.dimensions(), (32, 32));
+
+        // Replacing the asset drops its thumbnails
+        server
+            .put("/11/assets/decoration.png")
+            .bytes(blank_png(2, 2).into())
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert_eq!(store.thumbnail("decoration.png", &key).await.unwrap(), None);
+
+        std::fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        server
+            .get("/11/assets/notes.txt?w=64")
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        std::fs::write(dir.path().join("huge.png"), HUGE_PNG).unwrap();
+        server
+            .get("/11/assets/huge.png?w=64")
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        for size in ["w=0", "w=100", "h=8192"] {
+            server
+                .get(&format!("/11/assets/decoration.png?{size}"))
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
 }
@@ -112,74 +112,1548 @@
 //!   "LSB is 1": 5
 //! }
 //! ```
+//!
+//! # Extension: Persistent timers
+//!
+//! The timers are stored in the `timers` table with the wall-clock time they
+//! were saved, so they survive restarts and are shared by every instance.
+//! `TIMER_STORE=memory` keeps them in memory instead, and `TIMER_CACHE=true`
+//! caches the database in memory for a single instance.
+//!
+//! With `TIMER_TTL` set to a number of seconds, timers that were neither
+//! saved nor changed by an action for that long expire.
+//!
+//! A timer is deleted with DELETE `/12/save/<string>`, and GET `/12/timers`
+//! lists all timers:
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/12/save/packet20231212
+//! curl http://localhost:8000/12/timers
+//!
+//! [
+//!   {
+//!     "id": "packet20231212",
+//!     "status": "running",
+//!     "saved_at": "2023-12-12T10:00:00.000Z",
+//!     "elapsed": 3,
+//!     "laps": []
+//!   }
+//! ]
+//!
+//! curl -X DELETE http://localhost:8000/12/save/packet20231212
+//! ```
+//!
+//! # Extension: Stopwatches
+//!
+//! Timers are stopwatches, started when they are saved. POST
+//! `/12/timers/<id>/<action>` controls them with the actions `start`, `pause`,
+//! `resume`, `lap` and `stop`, and answers with their status like GET
+//! `/12/timers/<id>`. Actions that don't fit the status of the stopwatch, like
+//! resuming a running one, are a conflict. Actions on the same stopwatch take
+//! turns, also on different instances, so none of them is lost.
+//!
+//! Elapsed times are whole seconds, or milliseconds with `?unit=ms`, also for
+//! `/12/load/<string>` and `/12/timers`. Laps have the elapsed time at their
+//! end as `split` and their own `time`.
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/12/timers/packet20231212/start
+//! sleep 1.2
+//! curl -X POST http://localhost:8000/12/timers/packet20231212/lap
+//! sleep 0.8
+//! curl -X POST 'http://localhost:8000/12/timers/packet20231212/pause?unit=ms'
+//!
+//! {
+//!   "id": "packet20231212",
+//!   "status": "paused",
+//!   "saved_at": "2023-12-12T10:00:00.000Z",
+//!   "elapsed": 2000,
+//!   "laps": [{"lap": 1, "split": 1200, "time": 1200}]
+//! }
+//! ```
+//!
+//! # Extension: Watching timers
+//!
+//! GET `/12/watch/<string>` streams a timer as server-sent events, and
+//! `/12/watch` all timers. A `tick` with the status of the timer, or a list of
+//! all of them, comes right away and then every `interval` milliseconds, from
+//! a second, the default, to a minute. All watchers share one listing of the
+//! timers, made every second, so ticks may be up to a second behind. Changes
+//! come as `save`, `delete`, `expire` and the stopwatch actions, with the
+//! status of the timer after them. Times are in the `unit` like everywhere
+//! else.
+//!
+//! ```not_rust
+//! curl -N 'http://localhost:8000/12/watch/packet20231212?unit=ms'
+//!
+//! event: tick
+//! data: {"id":"packet20231212","status":"running","saved_at":"2023-12-12T10:00:00.000Z","elapsed":1000,"laps":[]}
+//!
+//! event: save
+//! data: {"id":"packet20231212","status":"running","saved_at":"2023-12-12T10:00:01.500Z","elapsed":0,"laps":[]}
+//! ```
+//!
+//! Changes made by other instances sharing the database only show in the
+//! ticks.
+//!
+//! # Extension: ULID and UUID toolkit
+//!
+//! POST `/12/convert` takes a JSON array of ULIDs and UUIDs and answers with
+//! both forms of each, in the same order. POST `/12/inspect` also describes
+//! the `version` of UUIDs, the `timestamp` of ULIDs and UUIDv7, and their
+//! `random` bits. Invalid identifiers get an `error` of their own.
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/12/inspect \
+//!   -H 'Content-Type: application/json' \
+//!   -d '["01BJQ0E1C3Z56ABCD0E11HYX4M", "not an ID"]'
+//!
+//! [
+//!   {
+//!     "input": "01BJQ0E1C3Z56ABCD0E11HYX4M",
+//!     "kind": "ulid",
+//!     "ulid": "01BJQ0E1C3Z56ABCD0E11HYX4M",
+//!     "uuid": "015cae07-0583-f94c-a5b1-a070431f7494",
+//!     "version": null,
+//!     "timestamp_ms": 1497568314755,
+//!     "timestamp": "2017-06-15T23:11:54.755Z",
+//!     "random": "f94ca5b1a070431f7494",
+//!     "random_bits": 80
+//!   },
+//!   {
+//!     "input": "not an ID",
+//!     "error": "invalid UUID: invalid character: expected an optional prefix of `urn:uuid:` followed by [0-9a-fA-F-], found `n` at 1"
+//!   }
+//! ]
+//! ```
+//!
+//! POST `/12/ulids/generate` and `/12/uuids/generate` generate up to 1000
+//! identifiers at once with `count`. ULIDs are increasing within the
+//! same millisecond with `monotonic=true`, UUIDs are version 4 unless
+//! `version=7`.
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/12/ulids/generate?count=3&monotonic=true'
+//! curl -X POST 'http://localhost:8000/12/uuids/generate?count=3&version=7'
+//! ```
+//!
+//! # Extension: ULID date analytics
+//!
+//! `/12/ulids/<weekday>` takes the IANA timezone of the dates as `tz`, UTC by
+//! default. POST `/12/ulids/analyze` also counts the ULIDs generated on
+//! named `dates`, Christmas Eve by default, and with random bits matching
+//! the hexadecimal `mask` and `value` of `bits`, "LSB is 1" by default. It
+//! can group the ULIDs into `histograms` by `year`, `month`, `weekday` and
+//! `hour`. Names of dates and of bits must be unique.
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/12/ulids/analyze?tz=Europe/Oslo' \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{
+//!     "ulids": ["01HH9SJEG0KY16H81S3N1BMXM4", "01HH9SJEG0P9M22Z9VGHH9C8CX"],
+//!     "dates": [{"name": "advent", "month": 12, "day": 10}],
+//!     "weekday": 6,
+//!     "bits": [{"name": "even", "mask": "0x1", "value": "0x0"}],
+//!     "histograms": ["hour"]
+//!   }'
+//!
+//! {
+//!   "count": 2,
+//!   "dates": {"advent": 2},
+//!   "weekday": 2,
+//!   "in the future": 0,
+//!   "bits": {"even": 1},
+//!   "histograms": {"hour": {"13": 2}}
+//! }
+//! ```
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::Infallible,
+    str::FromStr,
     sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    routing::{get, post},
+    Json, Router,
+};
+
+use chrono::{DateTime, Datelike, NaiveDate, SecondsFormat, Timelike, Utc};
+use chrono_tz::Tz;
+use futures_util::{
+    future::BoxFuture,
+    stream::{self, Stream, StreamExt},
 };
+use sea_query::{Expr, Iden, OnConflict, Order, PostgresQueryBuilder};
+use sea_query_binder::{SqlxBinder, SqlxValues};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch as latest, Notify,
+};
+use ulid::Ulid;
+use uuid::Uuid;
+
+/// Get Day 12 routes
+///
+/// * `/12/save/<string>`
+/// * `/12/load/<string>`
+/// * `/12/timers`
+/// * `/12/watch`
+/// * `/12/watch/<string>`
+/// * `/12/timers/<id>`
+/// * `/12/timers/<id>/<action>`
+/// * `/12/ulids`
+/// * `/12/ulids/generate`
+/// * `/12/uuids/generate`
+/// * `/12/convert`
+/// * `/12/inspect`
+/// * `/12/ulids/<weekday>`
+/// * `/12/ulids/analyze`
+pub fn get_routes(pool: PgPool) -> Router {
+    let flag = |name: &str| std::env::var(name).is_ok_and(|value| value == "true");
+    let timers: Arc<dyn TimerStore> = match std::env::var("TIMER_STORE").as_deref() {
+        Ok("memory") => Arc::new(MemoryTimers::default()),
+        _ if flag("TIMER_CACHE") => Arc::new(PgTimers::new(pool).with_cache()),
+        _ => Arc::new(PgTimers::new(pool)),
+    };
+    let ttl = std::env::var("TIMER_TTL")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .map(Duration::from_secs);
+
+    router(AppState::new(timers).with_ttl(ttl))
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/12/save/:string", post(save_string).delete(delete_string))
+        .route("/12/load/:string", get(load_string))
+        .route("/12/timers", get(list_timers))
+        .route("/12/watch", get(watch_timers))
+        .route("/12/watch/:string", get(watch_timer))
+        .route("/12/timers/:id", get(stopwatch_status))
+        .route("/12/timers/:id/:action", post(stopwatch_action))
+        .route("/12/ulids", post(ulids))
+        .route("/12/ulids/generate", post(generate_ulids))
+        .route("/12/uuids/generate", post(generate_uuids))
+        .route("/12/convert", post(convert))
+        .route("/12/inspect", post(inspect))
+        .route("/12/ulids/:weekday", post(ulids_weekday))
+        .route("/12/ulids/analyze", post(analyze_ulids))
+        .with_state(state)
+}
+
+#[derive(Clone)]
+struct AppState {
+    pub timers: Arc<dyn TimerStore>,
+    /// How long timers live after they were saved
+    pub ttl: Option<Duration>,
+    pub clock: Arc<dyn Clock>,
+    /// Changes of timers, for watchers
+    ///
+    /// Unlike the broadcast of Day 19, slow watchers don't hold up changes.
+    pub events: broadcast::Sender<TimerEvent>,
+    /// The timers listed for watchers, while there are any
+    listing: Arc<Mutex<Option<Listing>>>,
+}
+
+/// Timers listed regularly by a task shared by all watchers
+#[derive(Clone)]
+struct Listing {
+    timers: Arc<latest::Sender<Arc<SavedTimers>>>,
+    /// Asks for the timers to be listed right away
+    refresh: Arc<Notify>,
+}
+
+impl AppState {
+    fn new(timers: Arc<dyn TimerStore>) -> Self {
+        Self {
+            timers,
+            ttl: None,
+            clock: Arc::new(SystemClock),
+            events: broadcast::channel(64).0,
+            listing: Arc::default(),
+        }
+    }
+
+    /// Tell the watchers about a change of a timer
+    fn publish(&self, change: TimerChange, id: &str, stopwatch: Option<&Stopwatch>) {
+        // Without watchers nobody needs to know
+        let _ = self.events.send(TimerEvent {
+            change,
+            id: id.to_string(),
+            stopwatch: stopwatch.cloned(),
+        });
+    }
+
+    fn with_ttl(self, ttl: Option<Duration>) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// When timers last active before expire
+    fn expiry(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.ttl
+            .and_then(|ttl| chrono::Duration::from_std(ttl).ok())
+            .and_then(|ttl| now.checked_sub_signed(ttl))
+    }
+
+    /// Load a timer, deleting it if it expired
+    async fn load(&self, id: &str, now: DateTime<Utc>) -> Result<Option<Stopwatch>, TimerError> {
+        let Some(stopwatch) = self.timers.load(id).await? else {
+            return Ok(None);
+        };
+
+        match self.expiry(now) {
+            Some(expiry) if stopwatch.active_at < expiry => {
+                // Unless it was saved again in the meantime
+                if self.timers.delete_expired(id, expiry).await? {
+                    self.publish(TimerChange::Expired, id, None);
+                }
+                Ok(None)
+            }
+            _ => Ok(Some(stopwatch)),
+        }
+    }
+
+    /// Delete the expired timers
+    async fn expire(&self, now: DateTime<Utc>) -> Result<(), TimerError> {
+        let Some(expiry) = self.expiry(now) else {
+            return Ok(());
+        };
+
+        for id in self.timers.expire(expiry).await? {
+            self.publish(TimerChange::Expired, &id, None);
+        }
+
+        Ok(())
+    }
+
+    /// All timers that haven't expired
+    async fn list(&self, now: DateTime<Utc>) -> Result<SavedTimers, TimerError> {
+        self.expire(now).await?;
+        self.timers.list().await
+    }
+
+    /// Get the timers listed for watchers, listed again right away
+    ///
+    /// The first watcher starts listing them, and listing stops some time
+    /// after the last one left.
+    fn watch_listing(&self) -> latest::Receiver<Arc<SavedTimers>> {
+        let mut listing = self.listing.lock().unwrap();
+        let listing = listing.get_or_insert_with(|| {
+            let listing = Listing {
+                timers: Arc::new(latest::channel(Arc::default()).0),
+                refresh: Arc::new(Notify::new()),
+            };
+            tokio::spawn(self.clone().keep_listing(listing.clone()));
+            listing
+        });
+
+        listing.refresh.notify_one();
+        listing.timers.subscribe()
+    }
+
+    /// List the timers for watchers every [`WATCH_LISTING_INTERVAL`] while
+    /// there are any
+    async fn keep_listing(self, listing: Listing) {
+        loop {
+            match self.list(self.clock.now()).await {
+                Ok(timers) => {
+                    listing.timers.send_replace(Arc::new(timers));
+                }
+                Err(e) => tracing::warn!("failed to list the watched timers: {e}"),
+            }
+
+            tokio::select! {
+                () = self.clock.sleep(WATCH_LISTING_INTERVAL) => {}
+                () = listing.refresh.notified() => {}
+            }
+
+            let mut current = self.listing.lock().unwrap();
+            if listing.timers.receiver_count() == 0 {
+                *current = None;
+                return;
+            }
+        }
+    }
+}
+
+/// Where the handlers get the current time from
+trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Wait until a duration has passed
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The wall-clock time of the system
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Why the timers could not be stored
+#[derive(thiserror::Error, Debug)]
+enum TimerError {
+    #[error("timer database failed: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl From<TimerError> for (StatusCode, String) {
+    fn from(e: TimerError) -> Self {
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+/// A stopwatch, started when it is saved
+#[derive(Clone, Debug, PartialEq)]
+struct Stopwatch {
+    /// When the stopwatch was saved or started
+    saved_at: DateTime<Utc>,
+    /// When the stopwatch was last saved or changed, timers expire relative
+    /// to this
+    active_at: DateTime<Utc>,
+    /// When the stopwatch was last started or resumed, if it is running
+    resumed_at: Option<DateTime<Utc>>,
+    /// Milliseconds counted before it was last resumed
+    accumulated: i64,
+    stopped: bool,
+    /// The elapsed milliseconds at every lap
+    laps: Vec<i64>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum StopwatchStatus {
+    Running,
+    Paused,
+    Stopped,
+}
+
+impl std::fmt::Display for StopwatchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Running => "running",
+            Self::Paused => "paused",
+            Self::Stopped => "stopped",
+        })
+    }
+}
+
+/// Most laps a stopwatch records
+const MAX_LAPS: usize = 1000;
+
+/// Why an action could not be applied to a stopwatch
+#[derive(thiserror::Error, Debug, PartialEq)]
+enum StopwatchError {
+    #[error("the stopwatch is {0}")]
+    Status(StopwatchStatus),
+    #[error("a stopwatch records at most {MAX_LAPS} laps")]
+    TooManyLaps,
+    #[error("there is no such timer")]
+    NotFound,
+}
+
+impl From<StopwatchError> for (StatusCode, String) {
+    fn from(e: StopwatchError) -> Self {
+        let status = match e {
+            StopwatchError::Status(_) => StatusCode::CONFLICT,
+            StopwatchError::TooManyLaps => StatusCode::UNPROCESSABLE_ENTITY,
+            StopwatchError::NotFound => StatusCode::NOT_FOUND,
+        };
+
+        (status, e.to_string())
+    }
+}
+
+impl Stopwatch {
+    fn start(now: DateTime<Utc>) -> Self {
+        Self {
+            saved_at: now,
+            active_at: now,
+            resumed_at: Some(now),
+            accumulated: 0,
+            stopped: false,
+            laps: Vec::new(),
+        }
+    }
+
+    fn status(&self) -> StopwatchStatus {
+        match (self.resumed_at, self.stopped) {
+            (Some(_), _) => StopwatchStatus::Running,
+            (None, false) => StopwatchStatus::Paused,
+            (None, true) => StopwatchStatus::Stopped,
+        }
+    }
+
+    /// Milliseconds counted until now
+    fn elapsed(&self, now: DateTime<Utc>) -> i64 {
+        let running = self
+            .resumed_at
+            .map_or(0, |resumed_at| (now - resumed_at).num_milliseconds().max(0));
+
+        self.accumulated + running
+    }
+
+    /// Apply an action, if the stopwatch is in a state that allows it
+    fn apply(&mut self, action: Action, now: DateTime<Utc>) -> Result<(), StopwatchError> {
+        match (action, self.status()) {
+            (Action::Start, _) => *self = Self::start(now),
+            (Action::Pause, StopwatchStatus::Running) => {
+                self.accumulated = self.elapsed(now);
+                self.resumed_at = None;
+            }
+            (Action::Resume, StopwatchStatus::Paused) => self.resumed_at = Some(now),
+            (Action::Lap, StopwatchStatus::Running) if self.laps.len() == MAX_LAPS => {
+                return Err(StopwatchError::TooManyLaps)
+            }
+            (Action::Lap, StopwatchStatus::Running) => self.laps.push(self.elapsed(now)),
+            (Action::Stop, StopwatchStatus::Running | StopwatchStatus::Paused) => {
+                self.accumulated = self.elapsed(now);
+                self.resumed_at = None;
+                self.stopped = true;
+            }
+            (_, status) => return Err(StopwatchError::Status(status)),
+        }
+        self.active_at = now;
+
+        Ok(())
+    }
+}
+
+/// IDs of timers with their stopwatches
+type SavedTimers = Vec<(String, Stopwatch)>;
+
+/// A change of a timer, given the stopwatch saved before if there is one
+type StopwatchChange<'a> =
+    Box<dyn FnOnce(Option<Stopwatch>) -> Result<Stopwatch, StopwatchError> + Send + 'a>;
+
+/// Where the timers are stored
+trait TimerStore: Send + Sync {
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        stopwatch: &'a Stopwatch,
+    ) -> BoxFuture<'a, Result<(), TimerError>>;
+
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Stopwatch>, TimerError>>;
+
+    /// Load a timer, change it and save it, without other changes of it in
+    /// between
+    ///
+    /// Nothing is saved if the change fails.
+    fn update<'a>(
+        &'a self,
+        id: &'a str,
+        change: StopwatchChange<'a>,
+    ) -> BoxFuture<'a, Result<Result<Stopwatch, StopwatchError>, TimerError>>;
+
+    /// Delete a timer, returns if there was one
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, TimerError>>;
+
+    /// Delete a timer if it was last active before a time, returns if it was
+    fn delete_expired<'a>(
+        &'a self,
+        id: &'a str,
+        before: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<bool, TimerError>>;
+
+    /// All timers, ordered by their ID
+    fn list(&self) -> BoxFuture<'_, Result<SavedTimers, TimerError>>;
+
+    /// Delete the timers last active before a time, returns their IDs
+    fn expire(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<String>, TimerError>>;
+}
+
+/// Timers of this instance
+#[derive(Default)]
+struct MemoryTimers {
+    timers: Mutex<HashMap<String, Stopwatch>>,
+}
+
+impl TimerStore for MemoryTimers {
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        stopwatch: &'a Stopwatch,
+    ) -> BoxFuture<'a, Result<(), TimerError>> {
+        self.timers
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), stopwatch.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Stopwatch>, TimerError>> {
+        let stopwatch = self.timers.lock().unwrap().get(id).cloned();
+        Box::pin(async move { Ok(stopwatch) })
+    }
+
+    fn update<'a>(
+        &'a self,
+        id: &'a str,
+        change: StopwatchChange<'a>,
+    ) -> BoxFuture<'a, Result<Result<Stopwatch, StopwatchError>, TimerError>> {
+        let mut timers = self.timers.lock().unwrap();
+        let result = change(timers.get(id).cloned());
+        if let Ok(stopwatch) = &result {
+            timers.insert(id.to_string(), stopwatch.clone());
+        }
+        Box::pin(async move { Ok(result) })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, TimerError>> {
+        let deleted = self.timers.lock().unwrap().remove(id).is_some();
+        Box::pin(async move { Ok(deleted) })
+    }
+
+    fn delete_expired<'a>(
+        &'a self,
+        id: &'a str,
+        before: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<bool, TimerError>> {
+        let mut timers = self.timers.lock().unwrap();
+        let expired = timers
+            .get(id)
+            .is_some_and(|stopwatch| stopwatch.active_at < before);
+        if expired {
+            timers.remove(id);
+        }
+        Box::pin(async move { Ok(expired) })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<SavedTimers, TimerError>> {
+        let mut timers: Vec<_> = self
+            .timers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, stopwatch)| (id.clone(), stopwatch.clone()))
+            .collect();
+        timers.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Box::pin(async move { Ok(timers) })
+    }
+
+    fn expire(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<String>, TimerError>> {
+        let mut expired = Vec::new();
+        self.timers.lock().unwrap().retain(|id, stopwatch| {
+            let keep = stopwatch.active_at >= before;
+            if !keep {
+                expired.push(id.clone());
+            }
+            keep
+        });
+        expired.sort();
+        Box::pin(async move { Ok(expired) })
+    }
+}
+
+#[derive(Iden)]
+enum Timers {
+    Table,
+    Id,
+    SavedAt,
+    ActiveAt,
+    ResumedAt,
+    Accumulated,
+    Stopped,
+    Laps,
+}
+
+/// The columns of a timer
+const TIMER_COLUMNS: [Timers; 7] = [
+    Timers::Id,
+    Timers::SavedAt,
+    Timers::ActiveAt,
+    Timers::ResumedAt,
+    Timers::Accumulated,
+    Timers::Stopped,
+    Timers::Laps,
+];
+
+#[derive(FromRow)]
+struct TimerRow {
+    id: String,
+    /// Milliseconds since the Unix epoch
+    saved_at: i64,
+    /// Milliseconds since the Unix epoch
+    active_at: i64,
+    /// Milliseconds since the Unix epoch
+    resumed_at: Option<i64>,
+    accumulated: i64,
+    stopped: bool,
+    /// The laps as JSON list
+    laps: String,
+}
+
+impl TimerRow {
+    fn into_timer(self) -> (String, Stopwatch) {
+        let time = |ms| DateTime::from_timestamp_millis(ms).unwrap_or_default();
+        let stopwatch = Stopwatch {
+            saved_at: time(self.saved_at),
+            active_at: time(self.active_at),
+            resumed_at: self.resumed_at.map(time),
+            accumulated: self.accumulated,
+            stopped: self.stopped,
+            laps: serde_json::from_str(&self.laps).unwrap_or_default(),
+        };
+
+        (self.id, stopwatch)
+    }
+}
+
+/// Timers in the `timers` table
+///
+/// Timers can be cached in memory, if no other instance changes them.
+struct PgTimers {
+    pool: PgPool,
+    cache: Option<MemoryTimers>,
+}
+
+impl PgTimers {
+    fn new(pool: PgPool) -> Self {
+        Self { pool, cache: None }
+    }
+
+    fn with_cache(self) -> Self {
+        Self {
+            cache: Some(MemoryTimers::default()),
+            ..self
+        }
+    }
+
+    /// Insert or replace a timer
+    fn save_query(id: &str, stopwatch: &Stopwatch) -> (String, SqlxValues) {
+        let laps = serde_json::to_string(&stopwatch.laps).expect("laps can be serialized");
+        sea_query::Query::insert()
+            .into_table(Timers::Table)
+            .columns(TIMER_COLUMNS)
+            .values_panic([
+                id.into(),
+                stopwatch.saved_at.timestamp_millis().into(),
+                stopwatch.active_at.timestamp_millis().into(),
+                stopwatch.resumed_at.map(|at| at.timestamp_millis()).into(),
+                stopwatch.accumulated.into(),
+                stopwatch.stopped.into(),
+                laps.into(),
+            ])
+            .on_conflict(
+                OnConflict::column(Timers::Id)
+                    .update_columns(TIMER_COLUMNS.into_iter().skip(1))
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder)
+    }
+}
+
+impl TimerStore for PgTimers {
+    fn save<'a>(
+        &'a self,
+        id: &'a str,
+        stopwatch: &'a Stopwatch,
+    ) -> BoxFuture<'a, Result<(), TimerError>> {
+        Box::pin(async move {
+            let (sql, values) = Self::save_query(id, stopwatch);
+            sqlx::query_with(&sql, values).execute(&self.pool).await?;
+
+            if let Some(cache) = &self.cache {
+                cache.save(id, stopwatch).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn load<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<Option<Stopwatch>, TimerError>> {
+        Box::pin(async move {
+            if let Some(cache) = &self.cache {
+                if let Some(stopwatch) = cache.load(id).await? {
+                    return Ok(Some(stopwatch));
+                }
+            }
+
+            let (sql, values) = sea_query::Query::select()
+                .columns(TIMER_COLUMNS)
+                .from(Timers::Table)
+                .and_where(Expr::col(Timers::Id).eq(id))
+                .build_sqlx(PostgresQueryBuilder);
+            let stopwatch = sqlx::query_as_with::<_, TimerRow, _>(&sql, values)
+                .fetch_optional(&self.pool)
+                .await?
+                .map(|row| row.into_timer().1);
+
+            if let (Some(cache), Some(stopwatch)) = (&self.cache, &stopwatch) {
+                cache.save(id, stopwatch).await?;
+            }
+
+            Ok(stopwatch)
+        })
+    }
+
+    fn update<'a>(
+        &'a self,
+        id: &'a str,
+        change: StopwatchChange<'a>,
+    ) -> BoxFuture<'a, Result<Result<Stopwatch, StopwatchError>, TimerError>> {
+        Box::pin(async move {
+            let mut tx = self.pool.begin().await?;
+
+            // Other changes of the timer wait for this one
+            let (sql, values) = sea_query::Query::select()
+                .columns(TIMER_COLUMNS)
+                .from(Timers::Table)
+                .and_where(Expr::col(Timers::Id).eq(id))
+                .lock_exclusive()
+                .build_sqlx(PostgresQueryBuilder);
+            let stopwatch = sqlx::query_as_with::<_, TimerRow, _>(&sql, values)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|row| row.into_timer().1);
+
+            let stopwatch = match change(stopwatch) {
+                Ok(stopwatch) => stopwatch,
+                Err(e) => return Ok(Err(e)),
+            };
+            let (sql, values) = Self::save_query(id, &stopwatch);
+            sqlx::query_with(&sql, values).execute(&mut *tx).await?;
+
+            // Cached while the timer is locked, so changes are cached in order
+            if let Some(cache) = &self.cache {
+                cache.save(id, &stopwatch).await?;
+            }
+            if let Err(e) = tx.commit().await {
+                if let Some(cache) = &self.cache {
+                    cache.delete(id).await?;
+                }
+                return Err(e.into());
+            }
+
+            Ok(Ok(stopwatch))
+        })
+    }
+
+    fn delete<'a>(&'a self, id: &'a str) -> BoxFuture<'a, Result<bool, TimerError>> {
+        Box::pin(async move {
+            if let Some(cache) = &self.cache {
+                cache.delete(id).await?;
+            }
+
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(Timers::Table)
+                .and_where(Expr::col(Timers::Id).eq(id))
+                .build_sqlx(PostgresQueryBuilder);
+            let result = sqlx::query_with(&sql, values).execute(&self.pool).await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn delete_expired<'a>(
+        &'a self,
+        id: &'a str,
+        before: DateTime<Utc>,
+    ) -> BoxFuture<'a, Result<bool, TimerError>> {
+        Box::pin(async move {
+            if let Some(cache) = &self.cache {
+                cache.delete_expired(id, before).await?;
+            }
+
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(Timers::Table)
+                .and_where(Expr::col(Timers::Id).eq(id))
+                .and_where(Expr::col(Timers::ActiveAt).lt(before.timestamp_millis()))
+                .build_sqlx(PostgresQueryBuilder);
+            let result = sqlx::query_with(&sql, values).execute(&self.pool).await?;
+
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<SavedTimers, TimerError>> {
+        Box::pin(async move {
+            let (sql, values) = sea_query::Query::select()
+                .columns(TIMER_COLUMNS)
+                .from(Timers::Table)
+                .order_by(Timers::Id, Order::Asc)
+                .build_sqlx(PostgresQueryBuilder);
+            let rows = sqlx::query_as_with::<_, TimerRow, _>(&sql, values)
+                .fetch_all(&self.pool)
+                .await?;
+
+            Ok(rows.into_iter().map(TimerRow::into_timer).collect())
+        })
+    }
+
+    fn expire(&self, before: DateTime<Utc>) -> BoxFuture<'_, Result<Vec<String>, TimerError>> {
+        Box::pin(async move {
+            if let Some(cache) = &self.cache {
+                cache.expire(before).await?;
+            }
+
+            let (sql, values) = sea_query::Query::delete()
+                .from_table(Timers::Table)
+                .and_where(Expr::col(Timers::ActiveAt).lt(before.timestamp_millis()))
+                .returning_col(Timers::Id)
+                .build_sqlx(PostgresQueryBuilder);
+            let mut expired: Vec<String> = sqlx::query_scalar_with(&sql, values)
+                .fetch_all(&self.pool)
+                .await?;
+            expired.sort();
+
+            Ok(expired)
+        })
+    }
+}
+
+/// Unit of elapsed times
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    /// Whole seconds
+    #[default]
+    S,
+    Ms,
+}
+
+impl Unit {
+    fn of(self, ms: i64) -> i64 {
+        match self {
+            Self::S => ms / 1000,
+            Self::Ms => ms,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct UnitParams {
+    #[serde(default)]
+    unit: Unit,
+}
+
+async fn save_string(
+    State(state): State<AppState>,
+    Path(string): Path<String>,
+) -> Result<(), (StatusCode, String)> {
+    let stopwatch = Stopwatch::start(state.clock.now());
+    state.timers.save(&string, &stopwatch).await?;
+    state.publish(TimerChange::Saved, &string, Some(&stopwatch));
+
+    Ok(())
+}
+
+async fn load_string(
+    State(state): State<AppState>,
+    Path(string): Path<String>,
+    Query(params): Query<UnitParams>,
+) -> Result<String, (StatusCode, String)> {
+    let now = state.clock.now();
+    let stopwatch = state
+        .load(&string, now)
+        .await?
+        .ok_or_else(|| (StatusCode::NOT_ACCEPTABLE, format!("no timer `{string}`")))?;
+
+    Ok(params.unit.of(stopwatch.elapsed(now)).to_string())
+}
+
+async fn delete_string(
+    State(state): State<AppState>,
+    Path(string): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    if state.timers.delete(&string).await? {
+        state.publish(TimerChange::Deleted, &string, None);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, format!("no timer `{string}`")))
+    }
+}
+
+/// What can be done with a stopwatch
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    /// Start from zero, like saving
+    Start,
+    Pause,
+    Resume,
+    /// Record the elapsed time
+    Lap,
+    Stop,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Lap {
+    lap: usize,
+    /// Elapsed time at the end of the lap
+    split: i64,
+    /// Time of this lap alone
+    time: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct Timer {
+    id: String,
+    status: StopwatchStatus,
+    saved_at: String,
+    elapsed: i64,
+    laps: Vec<Lap>,
+}
+
+impl Timer {
+    fn new(id: String, stopwatch: &Stopwatch, now: DateTime<Utc>, unit: Unit) -> Self {
+        let laps = stopwatch
+            .laps
+            .iter()
+            .scan(0, |previous, &split| {
+                let time = split - *previous;
+                *previous = split;
+                Some((split, time))
+            })
+            .enumerate()
+            .map(|(i, (split, time))| Lap {
+                lap: i + 1,
+                split: unit.of(split),
+                time: unit.of(time),
+            })
+            .collect();
+
+        Self {
+            id,
+            status: stopwatch.status(),
+            saved_at: stopwatch
+                .saved_at
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            elapsed: unit.of(stopwatch.elapsed(now)),
+            laps,
+        }
+    }
+}
+
+/// Get the status of a stopwatch
+async fn stopwatch_status(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<UnitParams>,
+) -> Result<Json<Timer>, (StatusCode, String)> {
+    let now = state.clock.now();
+    let stopwatch = state
+        .load(&id, now)
+        .await?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no timer `{id}`")))?;
+
+    Ok(Json(Timer::new(id, &stopwatch, now, params.unit)))
+}
+
+/// Start, pause, resume, lap or stop a stopwatch
+async fn stopwatch_action(
+    State(state): State<AppState>,
+    Path((id, action)): Path<(String, Action)>,
+    Query(params): Query<UnitParams>,
+) -> Result<Json<Timer>, (StatusCode, String)> {
+    let now = state.clock.now();
+    let expiry = state.expiry(now);
+    let mut expired = false;
+    let change = Box::new(|stopwatch: Option<Stopwatch>| {
+        // Expired timers are gone, even if they were not deleted yet
+        let stopwatch = stopwatch.filter(|stopwatch| {
+            expired = expiry.is_some_and(|expiry| stopwatch.active_at < expiry);
+            !expired
+        });
+        match (stopwatch, action) {
+            (_, Action::Start) => Ok(Stopwatch::start(now)),
+            (Some(mut stopwatch), _) => {
+                stopwatch.apply(action, now)?;
+                Ok(stopwatch)
+            }
+            (None, _) => Err(StopwatchError::NotFound),
+        }
+    });
+    let result = state.timers.update(&id, change).await?;
+
+    let stopwatch = match result {
+        Ok(stopwatch) => {
+            if expired {
+                state.publish(TimerChange::Expired, &id, None);
+            }
+            stopwatch
+        }
+        Err(StopwatchError::NotFound) => {
+            if expired {
+                // Deletes the expired timer
+                state.load(&id, now).await?;
+            }
+            return Err((StatusCode::NOT_FOUND, format!("no timer `{id}`")));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    state.publish(TimerChange::Action(action), &id, Some(&stopwatch));
+
+    Ok(Json(Timer::new(id, &stopwatch, now, params.unit)))
+}
+
+/// How a timer changed
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TimerChange {
+    Saved,
+    Deleted,
+    Expired,
+    Action(Action),
+}
+
+impl TimerChange {
+    /// Name of the server-sent event
+    fn name(self) -> &'static str {
+        match self {
+            Self::Saved => "save",
+            Self::Deleted => "delete",
+            Self::Expired => "expire",
+            Self::Action(Action::Start) => "start",
+            Self::Action(Action::Pause) => "pause",
+            Self::Action(Action::Resume) => "resume",
+            Self::Action(Action::Lap) => "lap",
+            Self::Action(Action::Stop) => "stop",
+        }
+    }
+}
+
+/// A change of a timer, with the stopwatch after it if there still is one
+#[derive(Clone, Debug)]
+struct TimerEvent {
+    change: TimerChange,
+    id: String,
+    stopwatch: Option<Stopwatch>,
+}
+
+/// What watchers of timers get
+#[derive(Debug)]
+enum WatchUpdate {
+    Event(TimerEvent),
+    /// The watched timers, regularly
+    Tick(SavedTimers),
+}
+
+/// Shortest and longest time between ticks of `/12/watch`, in milliseconds
+const WATCH_INTERVALS: std::ops::RangeInclusive<u64> = 1000..=60_000;
+
+/// How often the timers are listed for the ticks of all watchers
+const WATCH_LISTING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Deserialize)]
+struct WatchParams {
+    #[serde(default)]
+    unit: Unit,
+    /// Milliseconds between ticks, a second by default
+    interval: Option<u64>,
+}
+
+impl WatchParams {
+    fn interval(&self) -> Result<Duration, (StatusCode, String)> {
+        match self.interval.unwrap_or(1000) {
+            ms if WATCH_INTERVALS.contains(&ms) => Ok(Duration::from_millis(ms)),
+            _ => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "interval must be between {} and {} milliseconds",
+                    WATCH_INTERVALS.start(),
+                    WATCH_INTERVALS.end()
+                ),
+            )),
+        }
+    }
+}
+
+/// Ticks with the timers, or only one of them, and the changes of them
+///
+/// The changes are those of this instance, other instances sharing the
+/// database only show up in the ticks.
+fn watch(
+    state: AppState,
+    id: Option<String>,
+    interval: Duration,
+) -> impl Stream<Item = WatchUpdate> {
+    let events = stream::unfold(state.events.subscribe(), |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((event, events)),
+                // Watchers too slow to keep up miss changes, ticks catch them up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let filter = id.clone();
+    let events = events
+        .filter(move |event| {
+            let watched = filter.as_ref().is_none_or(|id| *id == event.id);
+            async move { watched }
+        })
+        .map(WatchUpdate::Event);
+
+    // Every tick waits for the timers to be listed again after it is due
+    let clock = state.clock.clone();
+    let ticks = stream::unfold(
+        (state.watch_listing(), None),
+        move |(mut listing, due): (_, Option<DateTime<Utc>>)| {
+            let clock = clock.clone();
+            async move {
+                let due = match due {
+                    Some(due) => {
+                        let wait = (due - clock.now()).to_std().unwrap_or_default();
+                        clock.sleep(wait).await;
+                        due
+                    }
+                    None => clock.now(),
+                };
+                listing.changed().await.ok()?;
+                let timers = listing.borrow_and_update().clone();
+                Some((timers, (listing, Some(due + interval))))
+            }
+        },
+    )
+    .map(move |timers| {
+        let timers = match &id {
+            Some(id) => timers.iter().filter(|(i, _)| i == id).cloned().collect(),
+            None => timers.to_vec(),
+        };
+        WatchUpdate::Tick(timers)
+    });
+
+    stream::select(events, ticks)
+}
+
+/// Turn watch updates into server-sent events
+fn watch_events(
+    state: AppState,
+    id: Option<String>,
+    params: WatchParams,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    let interval = params.interval()?;
+    let clock = state.clock.clone();
+    let single = id.is_some();
+
+    let events = watch(state, id, interval).map(move |update| {
+        let now = clock.now();
+        let event = match update {
+            WatchUpdate::Event(event) => {
+                let data = match &event.stopwatch {
+                    Some(stopwatch) => {
+                        serde_json::to_string(&Timer::new(event.id, stopwatch, now, params.unit))
+                    }
+                    None => serde_json::to_string(&serde_json::json!({ "id": event.id })),
+                };
+                Event::default()
+                    .event(event.change.name())
+                    .data(data.unwrap_or_default())
+            }
+            WatchUpdate::Tick(timers) => {
+                let mut timers = timers
+                    .into_iter()
+                    .map(|(id, stopwatch)| Timer::new(id, &stopwatch, now, params.unit));
+                let data = match (single, timers.next()) {
+                    (true, Some(timer)) => serde_json::to_string(&timer),
+                    (true, None) => Ok("null".to_string()),
+                    (false, first) => {
+                        serde_json::to_string(&first.into_iter().chain(timers).collect::<Vec<_>>())
+                    }
+                };
+                Event::default()
+                    .event("tick")
+                    .data(data.unwrap_or_default())
+            }
+        };
+
+        Ok(event)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Stream the changes of a timer and its elapsed time
+async fn watch_timer(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(params): Query<WatchParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    watch_events(state, Some(id), params)
+}
+
+/// Stream the changes of all timers and their elapsed times
+async fn watch_timers(
+    State(state): State<AppState>,
+    Query(params): Query<WatchParams>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, String)> {
+    watch_events(state, None, params)
+}
+
+/// List all timers that have not expired
+async fn list_timers(
+    State(state): State<AppState>,
+    Query(params): Query<UnitParams>,
+) -> Result<Json<Vec<Timer>>, (StatusCode, String)> {
+    let now = state.clock.now();
+    let timers = state.list(now).await?;
+
+    Ok(Json(
+        timers
+            .into_iter()
+            .map(|(id, stopwatch)| Timer::new(id, &stopwatch, now, params.unit))
+            .collect(),
+    ))
+}
+
+async fn ulids(Json(payload): Json<Vec<Ulid>>) -> Json<Vec<Uuid>> {
+    let response: Vec<Uuid> = payload
+        .iter()
+        .rev()
+        .map(|id| Uuid::from_bytes(id.to_bytes()))
+        .collect();
+
+    Json(response)
+}
+
+/// Most identifiers generated at once
+const MAX_GENERATED: usize = 1000;
+
+/// A ULID or a UUID, the same 128 bits
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Identifier {
+    Ulid(Ulid),
+    Uuid(Uuid),
+}
+
+impl FromStr for Identifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // Crockford's base32 without hyphens, which is a ULID even of the wrong
+        // length, unless it is the 32 hex digits of a UUID
+        let base32 = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() && !"IiLlOoUu".contains(c));
+        let hex = s.len() == 32 && s.chars().all(|c| c.is_ascii_hexdigit());
+        if s.len() == ulid::ULID_LEN || (base32 && !hex) {
+            Ulid::from_string(s)
+                .map(Self::Ulid)
+                .map_err(|e| format!("invalid ULID: {e}"))
+        } else {
+            Uuid::parse_str(s)
+                .map(Self::Uuid)
+                .map_err(|e| format!("invalid UUID: {e}"))
+        }
+    }
+}
+
+impl Identifier {
+    fn to_ulid(self) -> Ulid {
+        match self {
+            Self::Ulid(id) => id,
+            Self::Uuid(id) => Ulid::from_bytes(id.into_bytes()),
+        }
+    }
+
+    fn to_uuid(self) -> Uuid {
+        match self {
+            Self::Ulid(id) => Uuid::from_bytes(id.to_bytes()),
+            Self::Uuid(id) => id,
+        }
+    }
+}
+
+/// The result for one of many inputs, which fails on its own
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum ItemResult<T> {
+    Ok(T),
+    Err { input: String, error: String },
+}
+
+/// Apply a function to every input that is a valid identifier
+fn for_each_identifier<T>(
+    inputs: Vec<String>,
+    f: impl Fn(String, Identifier) -> T,
+) -> Vec<ItemResult<T>> {
+    inputs
+        .into_iter()
+        .map(|input| match input.parse() {
+            Ok(id) => ItemResult::Ok(f(input, id)),
+            Err(error) => ItemResult::Err { input, error },
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+struct Conversion {
+    input: String,
+    ulid: Ulid,
+    uuid: Uuid,
+}
 
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{get, post},
-    Json, Router,
-};
+/// Convert ULIDs to UUIDs and back
+async fn convert(Json(payload): Json<Vec<String>>) -> Json<Vec<ItemResult<Conversion>>> {
+    Json(for_each_identifier(payload, |input, id| Conversion {
+        input,
+        ulid: id.to_ulid(),
+        uuid: id.to_uuid(),
+    }))
+}
 
-use chrono::Datelike;
-use serde::Serialize;
-use tokio::time::Instant;
-use ulid::Ulid;
-use uuid::Uuid;
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum IdentifierKind {
+    Ulid,
+    Uuid,
+}
 
-/// Get Day 12 routes
-///
-/// * `/12/save/<string>`
-/// * `/12/load/<string>`
-/// * `/12/ulids`
-/// * `/12/load/<weekday>`
-pub fn get_routes() -> Router {
-    let state = AppState {
-        save_string: Arc::new(Mutex::new(HashMap::new())),
-    };
+#[derive(Serialize, Debug)]
+struct Inspection {
+    input: String,
+    kind: IdentifierKind,
+    ulid: Ulid,
+    uuid: Uuid,
+    /// Version of a UUID
+    version: Option<usize>,
+    /// Milliseconds since the Unix epoch, of ULIDs and UUIDv7
+    timestamp_ms: Option<u64>,
+    timestamp: Option<String>,
+    /// The random bits as hex
+    random: Option<String>,
+    random_bits: Option<u32>,
+}
 
-    Router::new()
-        .route("/12/save/:string", post(save_string))
-        .route("/12/load/:string", get(load_string))
-        .route("/12/ulids", post(ulids))
-        .route("/12/ulids/:weekday", post(ulids_weekday))
-        .with_state(state)
+impl Inspection {
+    fn new(input: String, id: Identifier) -> Self {
+        let bits = id.to_uuid().as_u128();
+        // The random bits around the version and the variant of UUIDs
+        let rand_a = (bits >> 64) & 0xfff;
+        let rand_b = bits & ((1 << 62) - 1);
+
+        let (kind, version, timestamp_ms, random) = match id {
+            Identifier::Ulid(id) => (
+                IdentifierKind::Ulid,
+                None,
+                Some(id.timestamp_ms()),
+                Some((id.random(), 80u32)),
+            ),
+            Identifier::Uuid(id) => {
+                let version = id.get_version_num();
+                let (timestamp_ms, random) = match version {
+                    4 => (
+                        None,
+                        Some(((bits >> 80) << 74 | rand_a << 62 | rand_b, 122)),
+                    ),
+                    7 => (Some((bits >> 80) as u64), Some((rand_a << 62 | rand_b, 74))),
+                    _ => (None, None),
+                };
+                (IdentifierKind::Uuid, Some(version), timestamp_ms, random)
+            }
+        };
+
+        Self {
+            input,
+            kind,
+            ulid: id.to_ulid(),
+            uuid: id.to_uuid(),
+            version,
+            timestamp_ms,
+            timestamp: timestamp_ms
+                .and_then(|ms| DateTime::from_timestamp_millis(ms as i64))
+                .map(|ts| ts.to_rfc3339_opts(SecondsFormat::Millis, true)),
+            random: random.map(|(random, bits)| {
+                format!("{random:0width$x}", width = bits.div_ceil(4) as usize)
+            }),
+            random_bits: random.map(|(_, bits)| bits),
+        }
+    }
 }
 
-#[derive(Clone)]
-struct AppState {
-    pub save_string: Arc<Mutex<HashMap<String, Instant>>>,
+/// Describe the timestamps and random bits of ULIDs and UUIDs
+async fn inspect(Json(payload): Json<Vec<String>>) -> Json<Vec<ItemResult<Inspection>>> {
+    Json(for_each_identifier(payload, Inspection::new))
 }
 
-async fn save_string(State(state): State<AppState>, Path(string): Path<String>) {
-    state
-        .save_string
-        .lock()
-        .unwrap()
-        .insert(string, Instant::now());
+#[derive(Deserialize)]
+struct GenerateParams {
+    count: Option<usize>,
+    /// Generate increasing ULIDs, even within a millisecond
+    #[serde(default)]
+    monotonic: bool,
+    /// Version of UUIDs, 4 or 7
+    version: Option<u8>,
 }
 
-async fn load_string(
+impl GenerateParams {
+    fn count(&self) -> Result<usize, (StatusCode, String)> {
+        match self.count.unwrap_or(1) {
+            count @ 1..=MAX_GENERATED => Ok(count),
+            _ => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("count must be between 1 and {MAX_GENERATED}"),
+            )),
+        }
+    }
+}
+
+/// Generate ULIDs for the current time
+async fn generate_ulids(
     State(state): State<AppState>,
-    Path(string): Path<String>,
-) -> Result<String, StatusCode> {
-    let lock = state.save_string.lock().unwrap();
-    let instant = lock.get(&string).ok_or(StatusCode::NOT_ACCEPTABLE)?;
+    Query(params): Query<GenerateParams>,
+) -> Result<Json<Vec<Ulid>>, (StatusCode, String)> {
+    let count = params.count()?;
+    let now = SystemTime::from(state.clock.now());
+
+    let ulids = if params.monotonic {
+        let mut generator = ulid::Generator::new();
+        (0..count)
+            .map(|_| generator.generate_from_datetime(now))
+            .collect::<Result<_, _>>()
+            .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?
+    } else {
+        (0..count).map(|_| Ulid::from_datetime(now)).collect()
+    };
 
-    Ok(instant.elapsed().as_secs().to_string())
+    Ok(Json(ulids))
 }
 
-async fn ulids(Json(payload): Json<Vec<Ulid>>) -> Json<Vec<Uuid>> {
-    let response: Vec<Uuid> = payload
-        .iter()
-        .rev()
-        .map(|id| Uuid::from_bytes(id.to_bytes()))
-        .collect();
+/// Generate random UUIDv4 or UUIDv7 for the current time
+async fn generate_uuids(
+    State(state): State<AppState>,
+    Query(params): Query<GenerateParams>,
+) -> Result<Json<Vec<Uuid>>, (StatusCode, String)> {
+    let count = params.count()?;
+    let now = state.clock.now();
 
-    Json(response)
+    let uuids = match params.version.unwrap_or(4) {
+        4 => (0..count).map(|_| Uuid::new_v4()).collect(),
+        7 => {
+            let timestamp = uuid::Timestamp::from_unix(
+                uuid::NoContext,
+                now.timestamp().max(0) as u64,
+                now.timestamp_subsec_nanos(),
+            );
+            (0..count).map(|_| Uuid::new_v7(timestamp)).collect()
+        }
+        _ => {
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "version must be 4 or 7".to_string(),
+            ))
+        }
+    };
+
+    Ok(Json(uuids))
 }
 
 #[derive(Serialize, Default)]
@@ -193,11 +1667,44 @@ struct Weekday {
     lsb: u16,
 }
 
-async fn ulids_weekday(Path(weekday): Path<u8>, Json(payload): Json<Vec<Ulid>>) -> Json<Weekday> {
+#[derive(Deserialize, Default)]
+struct TzParams {
+    /// IANA timezone the dates are in, UTC by default
+    tz: Option<String>,
+}
+
+impl TzParams {
+    fn tz(&self) -> Result<Tz, (StatusCode, String)> {
+        self.tz.as_deref().map_or(Ok(Tz::UTC), |tz| {
+            tz.parse().map_err(|_| {
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("unknown timezone `{tz}`"),
+                )
+            })
+        })
+    }
+}
+
+/// The time a ULID was generated at in a timezone
+fn ulid_time(id: Ulid, tz: Tz) -> DateTime<Tz> {
+    DateTime::from_timestamp_millis(id.timestamp_ms() as i64)
+        .expect("ULID timestamps are in range")
+        .with_timezone(&tz)
+}
+
+async fn ulids_weekday(
+    State(state): State<AppState>,
+    Path(weekday): Path<u8>,
+    Query(params): Query<TzParams>,
+    Json(payload): Json<Vec<Ulid>>,
+) -> Result<Json<Weekday>, (StatusCode, String)> {
+    let tz = params.tz()?;
     let mut response = Weekday::default();
+    let now = state.clock.now();
 
     for id in payload {
-        let ts = chrono::DateTime::from_timestamp_millis(id.timestamp_ms() as i64).unwrap();
+        let ts = ulid_time(id, tz);
         if ts.month() == 12 && ts.day() == 24 {
             response.christmas_eve += 1;
         }
@@ -206,7 +1713,7 @@ async fn ulids_weekday(Path(weekday): Path<u8>, Json(payload): Json<Vec<Ulid>>)
             response.weekday += 1;
         }
 
-        if ts.timestamp() > chrono::Utc::now().timestamp() {
+        if ts.timestamp() > now.timestamp() {
             response.in_the_future += 1;
         }
 
@@ -215,7 +1722,227 @@ async fn ulids_weekday(Path(weekday): Path<u8>, Json(payload): Json<Vec<Ulid>>)
         }
     }
 
-    Json(response)
+    Ok(Json(response))
+}
+
+/// Most dates, bit predicates or histograms of an analysis
+const MAX_RULES: usize = 100;
+
+/// A date that comes every year, like a holiday
+#[derive(Deserialize, Debug)]
+struct NamedDate {
+    name: String,
+    month: u32,
+    day: u32,
+}
+
+/// A predicate on the 80 random bits of ULIDs, true if the bits of the mask
+/// have the value
+#[derive(Deserialize, Debug)]
+struct BitPredicate {
+    name: String,
+    /// Hexadecimal, like `0x1`
+    mask: String,
+    /// Hexadecimal, the mask by default
+    value: Option<String>,
+}
+
+impl BitPredicate {
+    /// The mask and value
+    fn parse(&self) -> Result<(u128, u128), String> {
+        let parse = |hex: &str| {
+            let digits = hex.strip_prefix("0x").unwrap_or(hex);
+            u128::from_str_radix(digits, 16)
+                .ok()
+                .filter(|bits| bits >> ulid::Ulid::RAND_BITS == 0)
+                .ok_or_else(|| {
+                    format!(
+                        "`{hex}` of `{}` is not hexadecimal with at most 80 bits",
+                        self.name
+                    )
+                })
+        };
+
+        let mask = parse(&self.mask)?;
+        let value = self.value.as_deref().map_or(Ok(mask), parse)?;
+        if value & !mask != 0 {
+            return Err(format!("the value of `{}` is outside its mask", self.name));
+        }
+
+        Ok((mask, value))
+    }
+}
+
+/// Buckets of a histogram of the dates of ULIDs
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Bucket {
+    Year,
+    Month,
+    /// From 0 (Monday) to 6 (Sunday)
+    Weekday,
+    Hour,
+}
+
+impl Bucket {
+    fn of(self, ts: &DateTime<Tz>) -> i32 {
+        match self {
+            Self::Year => ts.year(),
+            Self::Month => ts.month() as i32,
+            Self::Weekday => ts.weekday().num_days_from_monday() as i32,
+            Self::Hour => ts.hour() as i32,
+        }
+    }
+}
+
+fn default_dates() -> Vec<NamedDate> {
+    vec![NamedDate {
+        name: "christmas eve".to_string(),
+        month: 12,
+        day: 24,
+    }]
+}
+
+fn default_bits() -> Vec<BitPredicate> {
+    vec![BitPredicate {
+        name: "LSB is 1".to_string(),
+        mask: "1".to_string(),
+        value: None,
+    }]
+}
+
+#[derive(Deserialize, Debug)]
+struct UlidAnalysisRequest {
+    ulids: Vec<Ulid>,
+    /// Christmas Eve by default
+    #[serde(default = "default_dates")]
+    dates: Vec<NamedDate>,
+    /// From 0 (Monday) to 6 (Sunday)
+    weekday: Option<u8>,
+    /// "LSB is 1" by default
+    #[serde(default = "default_bits")]
+    bits: Vec<BitPredicate>,
+    #[serde(default)]
+    histograms: Vec<Bucket>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct UlidAnalysis {
+    count: usize,
+    /// ULIDs generated on each named date
+    dates: BTreeMap<String, u64>,
+    /// ULIDs generated on the weekday
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weekday: Option<u64>,
+    #[serde(rename = "in the future")]
+    in_the_future: u64,
+    /// ULIDs matching each bit predicate
+    bits: BTreeMap<String, u64>,
+    histograms: BTreeMap<Bucket, BTreeMap<i32, u64>>,
+}
+
+/// The first name that was already given before
+fn repeated<'a>(mut names: impl Iterator<Item = &'a String>) -> Option<&'a String> {
+    let mut seen = HashSet::new();
+    names.find(|name| !seen.insert(*name))
+}
+
+/// Count the ULIDs generated on named dates and matching bit predicates, and
+/// when they were generated
+async fn analyze_ulids(
+    State(state): State<AppState>,
+    Query(params): Query<TzParams>,
+    Json(request): Json<UlidAnalysisRequest>,
+) -> Result<Json<UlidAnalysis>, (StatusCode, String)> {
+    let invalid = |message: String| (StatusCode::UNPROCESSABLE_ENTITY, message);
+    let tz = params.tz()?;
+
+    if [
+        request.dates.len(),
+        request.bits.len(),
+        request.histograms.len(),
+    ]
+    .iter()
+    .any(|&len| len > MAX_RULES)
+    {
+        return Err(invalid(format!(
+            "at most {MAX_RULES} dates, bits and histograms can be analyzed"
+        )));
+    }
+    // Counts are keyed by name, so a repeated name would be counted twice
+    if let Some(name) = repeated(request.dates.iter().map(|date| &date.name)) {
+        return Err(invalid(format!("date `{name}` is given more than once")));
+    }
+    if let Some(name) = repeated(request.bits.iter().map(|predicate| &predicate.name)) {
+        return Err(invalid(format!("bits `{name}` are given more than once")));
+    }
+    // Any leap year allows February 29th
+    if let Some(date) = request
+        .dates
+        .iter()
+        .find(|date| NaiveDate::from_ymd_opt(2000, date.month, date.day).is_none())
+    {
+        return Err(invalid(format!("`{}` is not a valid date", date.name)));
+    }
+    if request.weekday.is_some_and(|weekday| weekday > 6) {
+        return Err(invalid("weekday must be between 0 and 6".to_string()));
+    }
+    let bits = request
+        .bits
+        .iter()
+        .map(BitPredicate::parse)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+
+    let now = state.clock.now();
+    let mut analysis = UlidAnalysis {
+        count: request.ulids.len(),
+        dates: request
+            .dates
+            .iter()
+            .map(|date| (date.name.clone(), 0))
+            .collect(),
+        weekday: request.weekday.map(|_| 0),
+        bits: request
+            .bits
+            .iter()
+            .map(|predicate| (predicate.name.clone(), 0))
+            .collect(),
+        histograms: request
+            .histograms
+            .iter()
+            .map(|bucket| (*bucket, BTreeMap::new()))
+            .collect(),
+        ..Default::default()
+    };
+
+    for id in &request.ulids {
+        let ts = ulid_time(*id, tz);
+
+        for date in &request.dates {
+            if ts.month() == date.month && ts.day() == date.day {
+                *analysis.dates.get_mut(&date.name).unwrap() += 1;
+            }
+        }
+        if let (Some(count), Some(weekday)) = (&mut analysis.weekday, request.weekday) {
+            if ts.weekday().num_days_from_monday() == u32::from(weekday) {
+                *count += 1;
+            }
+        }
+        if ts.timestamp() > now.timestamp() {
+            analysis.in_the_future += 1;
+        }
+        for (predicate, (mask, value)) in request.bits.iter().zip(&bits) {
+            if id.random() & mask == *value {
+                *analysis.bits.get_mut(&predicate.name).unwrap() += 1;
+            }
+        }
+        for (bucket, histogram) in &mut analysis.histograms {
+            *histogram.entry(bucket.of(&ts)).or_default() += 1;
+        }
+    }
+
+    Ok(Json(analysis))
 }
 
 #[cfg(test)]
@@ -223,30 +1950,85 @@ mod test {
     use super::*;
     use axum_test::TestServer;
     use serde_json::json;
-    use std::time::Duration;
+
+    /// A clock that only moves when told to
+    struct TestClock(latest::Sender<DateTime<Utc>>);
+
+    impl TestClock {
+        fn new() -> Self {
+            Self(
+                latest::channel(
+                    DateTime::parse_from_rfc3339("2023-12-12T12:00:00Z")
+                        .unwrap()
+                        .to_utc(),
+                )
+                .0,
+            )
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.0.send_modify(|now| *now += duration);
+        }
+
+        fn set(&self, now: DateTime<Utc>) {
+            self.0.send_replace(now);
+        }
+    }
+
+    impl Clock for TestClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.borrow()
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            let until = self.now() + duration;
+            let mut now = self.0.subscribe();
+            Box::pin(async move {
+                // The clock outlives the state sleeping on it
+                let _ = now.wait_for(|now| *now >= until).await;
+            })
+        }
+    }
+
+    fn state() -> (AppState, Arc<TestClock>) {
+        let clock = Arc::new(TestClock::new());
+        let state = AppState {
+            clock: clock.clone(),
+            ..AppState::new(Arc::new(MemoryTimers::default()))
+        };
+
+        (state, clock)
+    }
+
+    fn app() -> Router {
+        router(state().0)
+    }
 
     #[tokio::test]
     async fn test_task1() {
-        let app = get_routes();
+        let (state, clock) = state();
 
-        let server = TestServer::new(app).unwrap();
+        let server = TestServer::new(router(state)).unwrap();
 
         let response = server.post("/12/save/packet20231212").await;
         response.assert_status(StatusCode::OK);
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        clock.advance(Duration::from_secs(1));
         let response = server.get("/12/load/packet20231212").await;
         response.assert_text("1");
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        clock.advance(Duration::from_secs(1));
         let response = server.get("/12/load/packet20231212").await;
         response.assert_text("2");
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        clock.advance(Duration::from_secs(1));
         let response = server.get("/12/load/packet20231212").await;
         response.assert_text("3");
+        server.post("/12/save/packet20231212").await;
+        let response = server.get("/12/load/packet20231212").await;
+        response.assert_text("0");
     }
 
     #[tokio::test]
     async fn test_task2() {
-        let app = get_routes();
+        let app = app();
 
         let server = TestServer::new(app).unwrap();
 
@@ -271,7 +2053,7 @@ mod test {
 
     #[tokio::test]
     async fn test_task3() {
-        let app = get_routes();
+        let app = app();
 
         let server = TestServer::new(app).unwrap();
 
@@ -296,4 +2078,650 @@ mod test {
             "LSB is 1": 5
         }));
     }
+
+    #[tokio::test]
+    async fn test_in_the_future() {
+        let (state, clock) = state();
+        let server = TestServer::new(router(state)).unwrap();
+        let ulids = json!(["01HH9SJEG0KY16H81S3N1BMXM4", "76EP4G39R8JD1N8AQNYDVJBRCF"]);
+
+        // 01HH9SJEG0... is 2023-12-10T12:00:00Z
+        let response = server.post("/12/ulids/0").json(&ulids).await;
+        assert_eq!(response.json::<serde_json::Value>()["in the future"], 1);
+
+        clock.set(
+            DateTime::parse_from_rfc3339("2023-12-01T00:00:00Z")
+                .unwrap()
+                .to_utc(),
+        );
+        let response = server.post("/12/ulids/0").json(&ulids).await;
+        assert_eq!(response.json::<serde_json::Value>()["in the future"], 2);
+
+        clock.set(DateTime::<Utc>::MAX_UTC);
+        let response = server.post("/12/ulids/0").json(&ulids).await;
+        assert_eq!(response.json::<serde_json::Value>()["in the future"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_timers() {
+        let (state, clock) = state();
+        let server = TestServer::new(router(state)).unwrap();
+
+        server.post("/12/save/b").await.assert_status_ok();
+        clock.advance(Duration::from_millis(1500));
+        server.post("/12/save/a").await.assert_status_ok();
+        clock.advance(Duration::from_secs(2));
+
+        server.get("/12/timers").await.assert_json(&json!([
+            {
+                "id": "a",
+                "status": "running",
+                "saved_at": "2023-12-12T12:00:01.500Z",
+                "elapsed": 2,
+                "laps": []
+            },
+            {
+                "id": "b",
+                "status": "running",
+                "saved_at": "2023-12-12T12:00:00.000Z",
+                "elapsed": 3,
+                "laps": []
+            }
+        ]));
+
+        server
+            .delete("/12/save/a")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        server.delete("/12/save/a").await.assert_status_not_found();
+        server
+            .get("/12/load/a")
+            .await
+            .assert_status(StatusCode::NOT_ACCEPTABLE);
+        let timers: Vec<serde_json::Value> = server.get("/12/timers").await.json();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0]["id"], "b");
+    }
+
+    #[tokio::test]
+    async fn test_timer_ttl() {
+        let (state, clock) = state();
+        let timers = state.timers.clone();
+        let server =
+            TestServer::new(router(state.with_ttl(Some(Duration::from_secs(60))))).unwrap();
+
+        server.post("/12/save/old").await.assert_status_ok();
+        server.post("/12/save/expired").await.assert_status_ok();
+        clock.advance(Duration::from_secs(61));
+        server.post("/12/save/new").await.assert_status_ok();
+
+        server
+            .get("/12/load/old")
+            .await
+            .assert_status(StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(timers.load("old").await.unwrap(), None);
+
+        let listed: Vec<serde_json::Value> = server.get("/12/timers").await.json();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0]["id"], "new");
+        assert_eq!(timers.load("expired").await.unwrap(), None);
+
+        // Stopwatches in use don't expire
+        clock.advance(Duration::from_secs(40));
+        server.post("/12/timers/new/lap").await.assert_status_ok();
+        clock.advance(Duration::from_secs(40));
+        let response = server.get("/12/timers/new").await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["elapsed"], 80);
+
+        // Timers saved again after they expired are kept
+        clock.advance(Duration::from_secs(61));
+        let expiry = clock.now() - chrono::Duration::seconds(60);
+        let expired = timers.load("new").await.unwrap().unwrap();
+        assert!(expired.active_at < expiry);
+        timers
+            .save("new", &Stopwatch::start(clock.now()))
+            .await
+            .unwrap();
+        assert!(!timers.delete_expired("new", expiry).await.unwrap());
+        assert!(timers.load("new").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_stopwatch() {
+        let (state, clock) = state();
+        let server = TestServer::new(router(state)).unwrap();
+        let action = |action: &str| server.post(&format!("/12/timers/packet/{action}?unit=ms"));
+
+        action("pause").await.assert_status_not_found();
+
+        action("start").await.assert_status_ok();
+        clock.advance(Duration::from_millis(1200));
+        action("lap").await.assert_status_ok();
+        clock.advance(Duration::from_millis(800));
+        let response = action("pause").await;
+        response.assert_json(&json!({
+            "id": "packet",
+            "status": "paused",
+            "saved_at": "2023-12-12T12:00:00.000Z",
+            "elapsed": 2000,
+            "laps": [{"lap": 1, "split": 1200, "time": 1200}]
+        }));
+
+        // Paused stopwatches don't count and can't lap
+        clock.advance(Duration::from_secs(10));
+        server.get("/12/load/packet").await.assert_text("2");
+        action("lap").await.assert_status(StatusCode::CONFLICT);
+        action("pause").await.assert_status(StatusCode::CONFLICT);
+
+        action("resume").await.assert_status_ok();
+        clock.advance(Duration::from_millis(1500));
+        action("lap").await.assert_status_ok();
+        clock.advance(Duration::from_millis(250));
+        server
+            .get("/12/load/packet?unit=ms")
+            .await
+            .assert_text("3750");
+
+        let response = action("stop").await;
+        let status: serde_json::Value = response.json();
+        assert_eq!(status["status"], "stopped");
+        assert_eq!(status["elapsed"], 3750);
+        assert_eq!(
+            status["laps"],
+            json!([
+                {"lap": 1, "split": 1200, "time": 1200},
+                {"lap": 2, "split": 3500, "time": 2300}
+            ])
+        );
+
+        clock.advance(Duration::from_secs(10));
+        action("resume").await.assert_status(StatusCode::CONFLICT);
+        action("stop").await.assert_status(StatusCode::CONFLICT);
+        let response = server.get("/12/timers/packet").await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<serde_json::Value>()["elapsed"], 3);
+
+        // Starting again resets it, like saving
+        action("start").await.assert_status_ok();
+        clock.advance(Duration::from_millis(10));
+        let response = server.get("/12/timers/packet?unit=ms").await;
+        assert_eq!(response.json::<serde_json::Value>()["elapsed"], 10);
+        assert_eq!(response.json::<serde_json::Value>()["laps"], json!([]));
+
+        action("rewind").await.assert_status_bad_request();
+        server
+            .get("/12/timers/packet?unit=h")
+            .await
+            .assert_status_bad_request();
+
+        // Ids named like other routes are stopwatches too
+        for id in ["save", "load", "watch", "ulids", "timers", "start"] {
+            server
+                .post(&format!("/12/save/{id}"))
+                .await
+                .assert_status_ok();
+            let response = server.post(&format!("/12/timers/{id}/pause")).await;
+            response.assert_status_ok();
+            assert_eq!(response.json::<serde_json::Value>()["id"], id);
+            let response = server.get(&format!("/12/timers/{id}")).await;
+            assert_eq!(response.json::<serde_json::Value>()["status"], "paused");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_actions() {
+        use axum::{body::Body, http::Request};
+        use tower::util::ServiceExt;
+
+        let (state, clock) = state();
+        let app = router(state.clone());
+        let post = |uri: &str| {
+            let app = app.clone();
+            let request = Request::post(uri).body(Body::empty()).unwrap();
+            tokio::spawn(async move { app.oneshot(request).await.unwrap().status() })
+        };
+
+        post("/12/timers/packet/start").await.unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        // No lap is lost to another one
+        let laps: Vec<_> = (0..50).map(|_| post("/12/timers/packet/lap")).collect();
+        for lap in laps {
+            assert_eq!(lap.await.unwrap(), StatusCode::OK);
+        }
+        let stopwatch = state.timers.load("packet").await.unwrap().unwrap();
+        assert_eq!(stopwatch.laps.len(), 50);
+
+        // Failed actions change nothing
+        let result = state
+            .timers
+            .update("packet", Box::new(|_| Err(StopwatchError::TooManyLaps)))
+            .await
+            .unwrap();
+        assert_eq!(result, Err(StopwatchError::TooManyLaps));
+        assert_eq!(state.timers.load("packet").await.unwrap(), Some(stopwatch));
+
+        // Actions don't bring back deleted timers
+        state.timers.delete("packet").await.unwrap();
+        assert_eq!(
+            post("/12/timers/packet/lap").await.unwrap(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(state.timers.load("packet").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_convert() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .post("/12/convert")
+            .json(&json!([
+                "01BJQ0E1C3Z56ABCD0E11HYX4M",
+                "015cae07-0583-f94c-a5b1-a070431f7494",
+                "015cae070583f94ca5b1a070431f7494",
+                "01BJQ0E1C3Z56ABCD0E11HYX4",
+                "not a UUID"
+            ]))
+            .await;
+        response.assert_status_ok();
+        let results: Vec<serde_json::Value> = response.json();
+        for result in &results[..3] {
+            assert_eq!(result["ulid"], "01BJQ0E1C3Z56ABCD0E11HYX4M");
+            assert_eq!(result["uuid"], "015cae07-0583-f94c-a5b1-a070431f7494");
+        }
+        assert_eq!(results[3]["input"], "01BJQ0E1C3Z56ABCD0E11HYX4");
+        assert_eq!(results[3]["error"], "invalid ULID: invalid length");
+        assert!(results[4]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid UUID"));
+    }
+
+    #[tokio::test]
+    async fn test_inspect() {
+        let server = TestServer::new(app()).unwrap();
+
+        let response = server
+            .post("/12/inspect")
+            .json(&json!([
+                "01BJQ0E1C3Z56ABCD0E11HYX4M",
+                "017f22e2-79b0-7cc3-98c4-dc0c0c07398f",
+                "9e0e2a5b-4c3d-4e1f-8a2b-3c4d5e6f7081",
+                "015cae07-0583-f94c-a5b1-a070431f7494"
+            ]))
+            .await;
+        let results: Vec<serde_json::Value> = response.json();
+        assert_eq!(
+            results[0],
+            json!({
+                "input": "01BJQ0E1C3Z56ABCD0E11HYX4M",
+                "kind": "ulid",
+                "ulid": "01BJQ0E1C3Z56ABCD0E11HYX4M",
+                "uuid": "015cae07-0583-f94c-a5b1-a070431f7494",
+                "version": null,
+                "timestamp_ms": 1497568314755u64,
+                "timestamp": "2017-06-15T23:11:54.755Z",
+                "random": "f94ca5b1a070431f7494",
+                "random_bits": 80
+            })
+        );
+
+        assert_eq!(results[1]["kind"], "uuid");
+        assert_eq!(results[1]["version"], 7);
+        assert_eq!(results[1]["timestamp"], "2022-02-22T19:22:22.000Z");
+        assert_eq!(results[1]["random"], "330d8c4dc0c0c07398f");
+        assert_eq!(results[1]["random_bits"], 74);
+
+        assert_eq!(results[2]["version"], 4);
+        assert_eq!(results[2]["timestamp"], serde_json::Value::Null);
+        assert_eq!(results[2]["random"], "27838a96d30f787ca2b3c4d5e6f7081");
+        assert_eq!(results[2]["random_bits"], 122);
+
+        // The UUID of a ULID has no meaningful version
+        assert_eq!(results[3]["version"], 15);
+        assert_eq!(results[3]["random"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_generate() {
+        let (state, _clock) = state();
+        let server = TestServer::new(router(state)).unwrap();
+        let now = 1702382400000u64; // 2023-12-12T12:00:00Z
+
+        let ulids: Vec<Ulid> = server
+            .post("/12/ulids/generate?count=100&monotonic=true")
+            .await
+            .json();
+        assert_eq!(ulids.len(), 100);
+        assert!(ulids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ulids.iter().all(|id| id.timestamp_ms() == now));
+
+        let ulids: Vec<Ulid> = server.post("/12/ulids/generate").await.json();
+        assert_eq!(ulids.len(), 1);
+
+        let uuids: Vec<Uuid> = server.post("/12/uuids/generate?count=3").await.json();
+        assert_eq!(uuids.len(), 3);
+        assert!(uuids.iter().all(|id| id.get_version_num() == 4));
+
+        let uuids: Vec<Uuid> = server
+            .post("/12/uuids/generate?count=3&version=7")
+            .await
+            .json();
+        assert!(uuids.iter().all(|id| id.get_version_num() == 7));
+        assert!(uuids.iter().all(|id| (id.as_u128() >> 80) as u64 == now));
+
+        for uri in [
+            "/12/ulids/generate?count=0",
+            "/12/ulids/generate?count=1001",
+            "/12/uuids/generate?version=1",
+        ] {
+            server
+                .post(uri)
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weekday_timezone() {
+        let server = TestServer::new(app()).unwrap();
+        // 2023-12-23T23:30:00Z, a Saturday, but Christmas Eve in Oslo
+        let ulids = json!([Ulid::from_parts(1703374200000, 0)]);
+
+        let response = server.post("/12/ulids/5").json(&ulids).await;
+        assert_eq!(response.json::<serde_json::Value>()["christmas eve"], 0);
+        assert_eq!(response.json::<serde_json::Value>()["weekday"], 1);
+
+        let response = server.post("/12/ulids/6?tz=Europe/Oslo").json(&ulids).await;
+        assert_eq!(response.json::<serde_json::Value>()["christmas eve"], 1);
+        assert_eq!(response.json::<serde_json::Value>()["weekday"], 1);
+
+        server
+            .post("/12/ulids/6?tz=North/Pole")
+            .json(&ulids)
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_ulids() {
+        let server = TestServer::new(app()).unwrap();
+        let ulids = json!([
+            "00WEGGF0G0J5HEYXS3D7RWZGV8",
+            "76EP4G39R8JD1N8AQNYDVJBRCF",
+            "018CJ7KMG0051CDCS3B7BFJ3AK",
+            "00Y986KPG0AMGB78RD45E9109K",
+            "010451HTG0NYWMPWCEXG6AJ8F2",
+            "01HH9SJEG0KY16H81S3N1BMXM4",
+            "01HH9SJEG0P9M22Z9VGHH9C8CX",
+            "017F8YY0G0NQA16HHC2QT5JD6X",
+            "03QCPC7P003V1NND3B3QJW72QJ"
+        ]);
+
+        // The same as Task 3 by default
+        let response = server
+            .post("/12/ulids/analyze")
+            .json(&json!({"ulids": ulids, "weekday": 5}))
+            .await;
+        response.assert_json(&json!({
+            "count": 9,
+            "dates": {"christmas eve": 3},
+            "weekday": 1,
+            "in the future": 2,
+            "bits": {"LSB is 1": 5},
+            "histograms": {}
+        }));
+
+        let response = server
+            .post("/12/ulids/analyze?tz=Pacific/Kiritimati")
+            .json(&json!({
+                "ulids": ulids,
+                "dates": [
+                    {"name": "christmas eve", "month": 12, "day": 24},
+                    {"name": "christmas", "month": 12, "day": 25}
+                ],
+                "bits": [
+                    {"name": "odd", "mask": "0x1"},
+                    {"name": "even", "mask": "0x1", "value": "0"},
+                    {"name": "low nibble 3", "mask": "f", "value": "3"}
+                ],
+                "histograms": ["weekday"]
+            }))
+            .await;
+        response.assert_status_ok();
+        let analysis: serde_json::Value = response.json();
+        assert_eq!(analysis["bits"]["odd"], 5);
+        assert_eq!(analysis["bits"]["even"], 4);
+        let dates = &analysis["dates"];
+        assert_eq!(
+            dates["christmas eve"].as_u64().unwrap() + dates["christmas"].as_u64().unwrap(),
+            3
+        );
+        let weekdays = analysis["histograms"]["weekday"].as_object().unwrap();
+        assert_eq!(
+            weekdays.values().map(|n| n.as_u64().unwrap()).sum::<u64>(),
+            9
+        );
+        assert!(analysis.get("weekday").is_none());
+
+        for request in [
+            json!({"ulids": [], "dates": [{"name": "no", "month": 2, "day": 30}]}),
+            json!({"ulids": [], "bits": [{"name": "big", "mask": "1ffffffffffffffffffff"}]}),
+            json!({"ulids": [], "bits": [{"name": "no", "mask": "1", "value": "2"}]}),
+            json!({"ulids": [], "weekday": 7}),
+            json!({"ulids": [], "dates": [
+                {"name": "eve", "month": 12, "day": 24},
+                {"name": "eve", "month": 12, "day": 31}
+            ]}),
+            json!({"ulids": [], "bits": [
+                {"name": "odd", "mask": "1"},
+                {"name": "odd", "mask": "1"}
+            ]}),
+        ] {
+            server
+                .post("/12/ulids/analyze")
+                .json(&request)
+                .await
+                .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ulid_histograms() {
+        let server = TestServer::new(app()).unwrap();
+        let ulids = json!([
+            Ulid::from_parts(1703374200000, 0), // 2023-12-23T23:30:00Z
+            Ulid::from_parts(1703377800000, 0), // 2023-12-24T00:30:00Z
+            Ulid::from_parts(1704067200000, 0)  // 2024-01-01T00:00:00Z
+        ]);
+
+        let response = server
+            .post("/12/ulids/analyze?tz=America/New_York")
+            .json(&json!({"ulids": ulids, "histograms": ["year", "month", "hour"]}))
+            .await;
+        let analysis: serde_json::Value = response.json();
+        assert_eq!(
+            analysis["histograms"],
+            json!({
+                "year": {"2023": 3},
+                "month": {"12": 3},
+                "hour": {"18": 1, "19": 2}
+            })
+        );
+        assert_eq!(analysis["dates"]["christmas eve"], 0);
+    }
+
+    /// The next update of a watch, if it comes soon
+    async fn next_update(updates: &mut (impl Stream<Item = WatchUpdate> + Unpin)) -> WatchUpdate {
+        tokio::time::timeout(Duration::from_secs(1), updates.next())
+            .await
+            .expect("an update")
+            .expect("more updates")
+    }
+
+    fn change(update: WatchUpdate) -> (TimerChange, String) {
+        match update {
+            WatchUpdate::Event(event) => (event.change, event.id),
+            update => panic!("expected an event, got {update:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch() {
+        let (state, clock) = state();
+        let server = TestServer::new(router(state.clone())).unwrap();
+        server.post("/12/save/a").await.assert_status_ok();
+        clock.advance(Duration::from_secs(2));
+
+        // Ticks come right away, then every interval
+        let mut updates = Box::pin(watch(
+            state.clone(),
+            Some("a".to_string()),
+            Duration::from_secs(60),
+        ));
+        match next_update(&mut updates).await {
+            WatchUpdate::Tick(timers) => {
+                assert_eq!(timers.len(), 1);
+                assert_eq!(timers[0].0, "a");
+                assert_eq!(timers[0].1.elapsed(clock.now()), 2000);
+            }
+            update => panic!("expected a tick, got {update:?}"),
+        }
+
+        server.post("/12/timers/b/start").await.assert_status_ok();
+        server.post("/12/timers/a/pause").await.assert_status_ok();
+        server
+            .post("/12/timers/a/pause")
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        server.post("/12/save/a").await.assert_status_ok();
+        server.delete("/12/save/a").await;
+
+        assert_eq!(
+            change(next_update(&mut updates).await),
+            (TimerChange::Action(Action::Pause), "a".to_string())
+        );
+        assert_eq!(
+            change(next_update(&mut updates).await),
+            (TimerChange::Saved, "a".to_string())
+        );
+        assert_eq!(
+            change(next_update(&mut updates).await),
+            (TimerChange::Deleted, "a".to_string())
+        );
+
+        // Watching all timers
+        let mut all = Box::pin(watch(state.clone(), None, Duration::from_secs(1)));
+        match next_update(&mut all).await {
+            WatchUpdate::Tick(timers) => assert_eq!(timers.len(), 1),
+            update => panic!("expected a tick, got {update:?}"),
+        }
+        server.post("/12/timers/c/start").await.assert_status_ok();
+        assert_eq!(
+            change(next_update(&mut all).await),
+            (TimerChange::Action(Action::Start), "c".to_string())
+        );
+        clock.advance(Duration::from_secs(1));
+        match next_update(&mut all).await {
+            WatchUpdate::Tick(timers) => assert_eq!(timers.len(), 2),
+            update => panic!("expected a tick, got {update:?}"),
+        }
+
+        // Watchers share the listing of the timers, until there are none
+        let listing = state.listing.lock().unwrap().clone().unwrap();
+        assert_eq!(listing.timers.receiver_count(), 2);
+        drop((updates, all));
+        clock.advance(Duration::from_secs(1));
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while state.listing.lock().unwrap().is_some() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("listing to stop");
+    }
+
+    #[tokio::test]
+    async fn test_watch_expiry() {
+        // Ticks leave out expired timers, and tell about them
+        let (state, clock) = state();
+        let state = state.with_ttl(Some(Duration::from_secs(60)));
+        let server = TestServer::new(router(state.clone())).unwrap();
+        server.post("/12/save/old").await.assert_status_ok();
+        clock.advance(Duration::from_secs(61));
+        server.post("/12/save/new").await.assert_status_ok();
+
+        let mut updates = Box::pin(watch(state, None, Duration::from_secs(60)));
+        let (mut ticked, mut expired) = (false, false);
+        while !(ticked && expired) {
+            match next_update(&mut updates).await {
+                WatchUpdate::Tick(timers) => {
+                    assert_eq!(timers.len(), 1);
+                    assert_eq!(timers[0].0, "new");
+                    ticked = true;
+                }
+                update => {
+                    assert_eq!(change(update), (TimerChange::Expired, "old".to_string()));
+                    expired = true;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_watch_events() {
+        use axum::{body::Body, http::Request};
+        use tower::util::ServiceExt;
+
+        let (state, _clock) = state();
+        let app = router(state.clone());
+        app.clone()
+            .oneshot(Request::post("/12/save/a").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/12/watch/a?unit=ms")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        let mut body = response.into_body().into_data_stream();
+        let tick = body.next().await.unwrap().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&tick).unwrap(),
+            "event: tick\ndata: {\"id\":\"a\",\"status\":\"running\",\"saved_at\":\"2023-12-12T12:00:00.000Z\",\"elapsed\":0,\"laps\":[]}\n\n"
+        );
+
+        app.clone()
+            .oneshot(Request::delete("/12/save/a").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let event = body.next().await.unwrap().unwrap();
+        assert_eq!(
+            std::str::from_utf8(&event).unwrap(),
+            "event: delete\ndata: {\"id\":\"a\"}\n\n"
+        );
+
+        let response = app
+            .oneshot(
+                Request::get("/12/watch?interval=10")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
 }
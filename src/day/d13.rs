@@ -86,16 +86,54 @@
 //!
 //! {"popular":"Toy Train"}
 //! ```
+//!
+//! # Extension: Managing orders
+//!
+//! Orders are inserted all at once, or not at all when one of their ids
+//! already exists, which is a `409 Conflict`.
+//!
+//! GET `/13/orders` lists the orders, filtered by `region_id`, `gift_name`,
+//! `min_quantity` and `max_quantity`. `sort` takes a comma separated list of
+//! columns, prefixed with `!` to sort descending, and pages are selected with
+//! `limit` (100 by default, at most 1000) and `offset`. Unknown columns and
+//! larger limits are a `422 Unprocessable Entity`:
+//!
+//! ```not_rust
+//! curl 'http://localhost:8000/13/orders?region_id=2&sort=!quantity&limit=2'
+//!
+//! [
+//!   {"id":2,"region_id":2,"gift_name":"Doll","quantity":8},
+//!   {"id":5,"region_id":2,"gift_name":"Teddy Bear","quantity":6}
+//! ]
+//! ```
+//!
+//! A single order is read with GET, replaced with PUT, partially updated with
+//! PATCH and deleted with DELETE on `/13/orders/<id>`, which is a `404 Not
+//! Found` when there is no such order:
+//!
+//! ```not_rust
+//! curl -X PATCH http://localhost:8000/13/orders/2 \
+//! -H 'Content-Type: application/json' \
+//! -d '{"quantity":9}'
+//!
+//! {"id":2,"region_id":2,"gift_name":"Doll","quantity":9}
+//!
+//! curl -X DELETE http://localhost:8000/13/orders/2
+//! ```
 use axum::{
-    extract::State,
+    extract::{Path, Query as QueryParams, State},
     http::StatusCode,
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use sea_query::{Alias, ColumnDef, Expr, Iden, Order, PostgresQueryBuilder, Query, Table};
+use modql::filter::{ListOptions, OrderBy, OrderBys};
+use sea_query::{
+    Alias, ColumnDef, Cond, Condition, Expr, Iden, Order, PostgresQueryBuilder, Query,
+    SelectStatement, SimpleExpr, Table,
+};
 use sea_query_binder::SqlxBinder;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, PgPool};
 
 /// Get Day 13 routes
@@ -105,15 +143,23 @@ use sqlx::{FromRow, PgPool};
 /// * `/13/orders`
 /// * `/13/orders/total`
 /// * `/13/orders/popular`
+/// * `/13/orders/:id`
 pub fn get_routes(pool: PgPool) -> Router {
     let state = AppState { pool };
 
     Router::new()
         .route("/13/sql", get(sql))
         .route("/13/reset", post(reset))
-        .route("/13/orders", post(orders))
+        .route("/13/orders", get(list_orders).post(orders))
         .route("/13/orders/total", get(orders_total))
         .route("/13/orders/popular", get(orders_popular))
+        .route(
+            "/13/orders/:id",
+            get(get_order)
+                .put(put_order)
+                .patch(patch_order)
+                .delete(delete_order),
+        )
         .with_state(state)
 }
 
@@ -164,7 +210,7 @@ async fn reset(State(state): State<AppState>) -> Result<StatusCode, StatusCode>
         .if_not_exists()
         .col(ColumnDef::new(Orders::Id).integer().primary_key())
         .col(ColumnDef::new(Orders::RegionId).integer())
-        .col(ColumnDef::new(Orders::GiftName).string_len(50))
+        .col(ColumnDef::new(Orders::GiftName).string_len(MAX_GIFT_NAME))
         .col(ColumnDef::new(Orders::Quantity).integer())
         .build(PostgresQueryBuilder);
 
@@ -176,7 +222,20 @@ async fn reset(State(state): State<AppState>) -> Result<StatusCode, StatusCode>
     Ok(StatusCode::OK)
 }
 
-#[derive(Deserialize)]
+/// Longest gift name the `orders` table holds
+const MAX_GIFT_NAME: u32 = 50;
+
+/// Default number of orders listed by `/13/orders`
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Most orders listed by `/13/orders` at once
+const MAX_LIMIT: u32 = 1000;
+
+/// Columns of the `orders` table, which are also the ones orders can be
+/// sorted by
+const ORDER_COLUMNS: [&str; 4] = ["id", "region_id", "gift_name", "quantity"];
+
+#[derive(Serialize, Deserialize, FromRow)]
 struct OrderStruct {
     id: i32,
     region_id: i32,
@@ -184,10 +243,56 @@ struct OrderStruct {
     quantity: i32,
 }
 
+/// Why an order request failed
+#[derive(thiserror::Error, Debug)]
+enum OrderError {
+    #[error("order {0} not found")]
+    NotFound(i32),
+    #[error("order {0} already exists")]
+    Duplicate(i32),
+    #[error("gift name is longer than {MAX_GIFT_NAME} characters")]
+    GiftNameTooLong,
+    #[error("cannot sort by {0:?}")]
+    UnknownSort(String),
+    #[error("cannot list more than {MAX_LIMIT} orders")]
+    LimitTooLarge,
+    #[error("order database failed: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+impl OrderError {
+    fn status(&self) -> StatusCode {
+        match self {
+            OrderError::NotFound(_) => StatusCode::NOT_FOUND,
+            OrderError::Duplicate(_) => StatusCode::CONFLICT,
+            OrderError::GiftNameTooLong
+            | OrderError::UnknownSort(_)
+            | OrderError::LimitTooLarge => StatusCode::UNPROCESSABLE_ENTITY,
+            OrderError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<OrderError> for (StatusCode, String) {
+    fn from(e: OrderError) -> Self {
+        (e.status(), e.to_string())
+    }
+}
+
+fn check_gift_name(gift_name: &str) -> Result<(), OrderError> {
+    if gift_name.chars().count() > MAX_GIFT_NAME as usize {
+        return Err(OrderError::GiftNameTooLong);
+    }
+    Ok(())
+}
+
+/// Insert all orders, or none of them if one fails
 async fn orders(
     State(state): State<AppState>,
     Json(orders): Json<Vec<OrderStruct>>,
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut tx = state.pool.begin().await.map_err(OrderError::from)?;
+
     for OrderStruct {
         id,
         region_id,
@@ -195,6 +300,8 @@ async fn orders(
         quantity,
     } in orders
     {
+        check_gift_name(&gift_name)?;
+
         let (sql, values) = Query::insert()
             .into_table(Orders::Table)
             .columns([
@@ -213,14 +320,252 @@ async fn orders(
             .build_sqlx(PostgresQueryBuilder);
 
         let _row = sqlx::query_with(&sql, values)
-            .fetch_one(&state.pool)
+            .fetch_one(&mut *tx)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => OrderError::Duplicate(id),
+                e => e.into(),
+            })?;
     }
 
+    tx.commit().await.map_err(OrderError::from)?;
+
     Ok(StatusCode::OK)
 }
 
+/// Query parameters of `/13/orders`
+///
+/// `sort` is a comma separated list of columns, descending when prefixed with
+/// `!`. Orders are always sorted by `id` last, so pages are stable.
+#[derive(Deserialize)]
+struct ListParams {
+    region_id: Option<i32>,
+    gift_name: Option<String>,
+    min_quantity: Option<i32>,
+    max_quantity: Option<i32>,
+    sort: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl ListParams {
+    /// The orders matching the filter
+    fn filter(&self) -> Condition {
+        Cond::all()
+            .add_option(
+                self.region_id
+                    .map(|region_id| Expr::col(Orders::RegionId).eq(region_id)),
+            )
+            .add_option(
+                self.gift_name
+                    .as_deref()
+                    .map(|gift_name| Expr::col(Orders::GiftName).eq(gift_name)),
+            )
+            .add_option(
+                self.min_quantity
+                    .map(|quantity| Expr::col(Orders::Quantity).gte(quantity)),
+            )
+            .add_option(
+                self.max_quantity
+                    .map(|quantity| Expr::col(Orders::Quantity).lte(quantity)),
+            )
+    }
+
+    /// The sorting and page as modql list options
+    fn list_options(&self) -> Result<ListOptions, OrderError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if limit > MAX_LIMIT {
+            return Err(OrderError::LimitTooLarge);
+        }
+
+        let mut order_bys = Vec::new();
+        for order_by in self.sort.iter().flat_map(|sort| sort.split(',')) {
+            let (column, descending) = match order_by.strip_prefix('!') {
+                Some(column) => (column, true),
+                None => (order_by, false),
+            };
+            if !ORDER_COLUMNS.contains(&column) {
+                return Err(OrderError::UnknownSort(order_by.to_string()));
+            }
+            order_bys.push(match descending {
+                true => OrderBy::Desc(column.to_string()),
+                false => OrderBy::Asc(column.to_string()),
+            });
+        }
+        if !order_bys
+            .iter()
+            .any(|(OrderBy::Asc(column) | OrderBy::Desc(column))| column == "id")
+        {
+            order_bys.push(OrderBy::Asc("id".to_string()));
+        }
+
+        Ok(ListOptions {
+            limit: Some(limit.into()),
+            offset: self.offset.map(Into::into),
+            order_bys: Some(OrderBys::new(order_bys)),
+        })
+    }
+}
+
+/// Sort and page a query by list options with known columns
+fn apply_list_options(query: &mut SelectStatement, list_options: ListOptions) {
+    for order_by in list_options.order_bys.into_iter().flatten() {
+        match order_by {
+            OrderBy::Asc(column) => query.order_by(Alias::new(column), Order::Asc),
+            OrderBy::Desc(column) => query.order_by(Alias::new(column), Order::Desc),
+        };
+    }
+    if let Some(limit) = list_options.limit {
+        query.limit(limit.unsigned_abs());
+    }
+    if let Some(offset) = list_options.offset {
+        query.offset(offset.unsigned_abs());
+    }
+}
+
+async fn list_orders(
+    State(state): State<AppState>,
+    QueryParams(params): QueryParams<ListParams>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let list_options = params.list_options()?;
+
+    let mut query = Query::select();
+    query
+        .columns(ORDER_COLUMNS.map(Alias::new))
+        .from(Orders::Table)
+        .cond_where(params.filter());
+    apply_list_options(&mut query, list_options);
+    let (sql, values) = query.build_sqlx(PostgresQueryBuilder);
+
+    let rows = sqlx::query_as_with::<_, OrderStruct, _>(&sql, values)
+        .fetch_all(&state.pool)
+        .await
+        .map_err(OrderError::from)?;
+
+    Ok(Json(rows))
+}
+
+async fn fetch_order(pool: &PgPool, id: i32) -> Result<OrderStruct, OrderError> {
+    let (sql, values) = Query::select()
+        .columns([
+            Orders::Id,
+            Orders::RegionId,
+            Orders::GiftName,
+            Orders::Quantity,
+        ])
+        .from(Orders::Table)
+        .and_where(Expr::col(Orders::Id).eq(id))
+        .build_sqlx(PostgresQueryBuilder);
+
+    sqlx::query_as_with::<_, OrderStruct, _>(&sql, values)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(OrderError::NotFound(id))
+}
+
+async fn get_order(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    Ok(Json(fetch_order(&state.pool, id).await?))
+}
+
+/// Update the given columns of an order and return it
+async fn update_order(
+    pool: &PgPool,
+    id: i32,
+    values: Vec<(Orders, SimpleExpr)>,
+) -> Result<OrderStruct, OrderError> {
+    if values.is_empty() {
+        return fetch_order(pool, id).await;
+    }
+
+    let (sql, values) = Query::update()
+        .table(Orders::Table)
+        .values(values)
+        .and_where(Expr::col(Orders::Id).eq(id))
+        .returning_all()
+        .build_sqlx(PostgresQueryBuilder);
+
+    sqlx::query_as_with::<_, OrderStruct, _>(&sql, values)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(OrderError::NotFound(id))
+}
+
+/// Body of PUT `/13/orders/:id`
+#[derive(Deserialize)]
+struct OrderFields {
+    region_id: i32,
+    gift_name: String,
+    quantity: i32,
+}
+
+async fn put_order(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(order): Json<OrderFields>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    check_gift_name(&order.gift_name)?;
+
+    let values = vec![
+        (Orders::RegionId, order.region_id.into()),
+        (Orders::GiftName, order.gift_name.into()),
+        (Orders::Quantity, order.quantity.into()),
+    ];
+
+    Ok(Json(update_order(&state.pool, id, values).await?))
+}
+
+/// Body of PATCH `/13/orders/:id`
+#[derive(Deserialize)]
+struct OrderPatch {
+    region_id: Option<i32>,
+    gift_name: Option<String>,
+    quantity: Option<i32>,
+}
+
+async fn patch_order(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+    Json(patch): Json<OrderPatch>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if let Some(gift_name) = &patch.gift_name {
+        check_gift_name(gift_name)?;
+    }
+
+    let values = [
+        patch.region_id.map(|v| (Orders::RegionId, v.into())),
+        patch.gift_name.map(|v| (Orders::GiftName, v.into())),
+        patch.quantity.map(|v| (Orders::Quantity, v.into())),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    Ok(Json(update_order(&state.pool, id, values).await?))
+}
+
+async fn delete_order(
+    State(state): State<AppState>,
+    Path(id): Path<i32>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let (sql, values) = Query::delete()
+        .from_table(Orders::Table)
+        .and_where(Expr::col(Orders::Id).eq(id))
+        .build_sqlx(PostgresQueryBuilder);
+
+    let result = sqlx::query_with(&sql, values)
+        .execute(&state.pool)
+        .await
+        .map_err(OrderError::from)?;
+
+    match result.rows_affected() {
+        0 => Err(OrderError::NotFound(id).into()),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
 #[derive(FromRow)]
 struct Task2(i64);
 
@@ -269,3 +614,207 @@ async fn orders_popular(
         _ => Ok(format!("{{\"popular\":\"{}\"}}", rows[0].gift_name)),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    /// Routes on the database in `DATABASE_URL`, with an empty `orders` table
+    async fn server() -> TestServer {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is set");
+        let pool = PgPool::connect(&url).await.unwrap();
+        let server = TestServer::new(get_routes(pool)).unwrap();
+        server.post("/13/reset").await.assert_status_ok();
+        server
+    }
+
+    fn params(sort: Option<&str>, limit: Option<u32>) -> ListParams {
+        ListParams {
+            region_id: None,
+            gift_name: None,
+            min_quantity: None,
+            max_quantity: None,
+            sort: sort.map(str::to_string),
+            limit,
+            offset: None,
+        }
+    }
+
+    /// The SQL of listing orders with the parameters
+    fn list_sql(params: &ListParams) -> String {
+        let mut query = Query::select();
+        query
+            .column(Orders::Id)
+            .from(Orders::Table)
+            .cond_where(params.filter());
+        apply_list_options(&mut query, params.list_options().unwrap());
+        query.to_string(PostgresQueryBuilder)
+    }
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(
+            list_sql(&params(None, None)),
+            r#"SELECT "id" FROM "orders" WHERE TRUE ORDER BY "id" ASC LIMIT 100"#
+        );
+
+        let filtered = ListParams {
+            region_id: Some(2),
+            gift_name: Some("Toy Train".to_string()),
+            min_quantity: Some(3),
+            max_quantity: Some(8),
+            offset: Some(10),
+            ..params(None, None)
+        };
+        assert_eq!(
+            list_sql(&filtered),
+            r#"SELECT "id" FROM "orders" WHERE "region_id" = 2 AND "gift_name" = 'Toy Train' AND "quantity" >= 3 AND "quantity" <= 8 ORDER BY "id" ASC LIMIT 100 OFFSET 10"#
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let order_bys = |sort| {
+            params(Some(sort), None).list_options().map(|options| {
+                options
+                    .order_bys
+                    .unwrap()
+                    .into_iter()
+                    .map(|order_by| order_by.to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            order_bys("!quantity,gift_name").unwrap(),
+            ["quantity DESC", "gift_name ASC", "id ASC"]
+        );
+        // Orders are sorted by id only once
+        assert_eq!(
+            order_bys("!id,region_id").unwrap(),
+            ["id DESC", "region_id ASC"]
+        );
+        assert_eq!(
+            list_sql(&params(Some("!quantity"), None)),
+            r#"SELECT "id" FROM "orders" WHERE TRUE ORDER BY "quantity" DESC, "id" ASC LIMIT 100"#
+        );
+
+        for sort in ["price", "!price", "quantity,,id", "", "!", "quantity "] {
+            assert!(
+                matches!(order_bys(sort), Err(OrderError::UnknownSort(_))),
+                "{sort:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let limit = |limit| {
+            params(None, limit)
+                .list_options()
+                .map(|options| options.limit)
+        };
+
+        assert_eq!(limit(None).unwrap(), Some(100));
+        assert_eq!(limit(Some(0)).unwrap(), Some(0));
+        assert_eq!(limit(Some(1000)).unwrap(), Some(1000));
+        assert!(matches!(limit(Some(1001)), Err(OrderError::LimitTooLarge)));
+        assert!(matches!(
+            limit(Some(u32::MAX)),
+            Err(OrderError::LimitTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_status() {
+        let status = |e: OrderError| <(StatusCode, String)>::from(e).0;
+
+        assert_eq!(status(OrderError::NotFound(1)), StatusCode::NOT_FOUND);
+        assert_eq!(status(OrderError::Duplicate(1)), StatusCode::CONFLICT);
+        for e in [
+            OrderError::GiftNameTooLong,
+            OrderError::UnknownSort("price".to_string()),
+            OrderError::LimitTooLarge,
+        ] {
+            assert_eq!(status(e), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        assert_eq!(
+            status(OrderError::Database(sqlx::Error::RowNotFound)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn test_orders() {
+        let server = server().await;
+        let order = |id, gift_name| json!({"id": id, "region_id": 2, "gift_name": gift_name, "quantity": 5});
+
+        server
+            .post("/13/orders")
+            .json(&json!([order(1, "Toy Train"), order(2, "Doll")]))
+            .await
+            .assert_status_ok();
+
+        // One existing id rolls back the whole batch
+        server
+            .post("/13/orders")
+            .json(&json!([order(3, "Teddy Bear"), order(1, "Lego")]))
+            .await
+            .assert_status(StatusCode::CONFLICT);
+        server.get("/13/orders/3").await.assert_status_not_found();
+        server
+            .get("/13/orders/1")
+            .await
+            .assert_json(&order(1, "Toy Train"));
+
+        server
+            .get("/13/orders")
+            .add_query_param("sort", "price")
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        server
+            .get("/13/orders")
+            .add_query_param("limit", 1001)
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+        server
+            .get("/13/orders")
+            .add_query_param("sort", "!id")
+            .await
+            .assert_json(&json!([order(2, "Doll"), order(1, "Toy Train")]));
+
+        server
+            .patch("/13/orders/2")
+            .json(&json!({"quantity": 9}))
+            .await
+            .assert_json(&json!({"id": 2, "region_id": 2, "gift_name": "Doll", "quantity": 9}));
+        server
+            .delete("/13/orders/2")
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+
+        server.get("/13/orders/2").await.assert_status_not_found();
+        server
+            .put("/13/orders/2")
+            .json(&json!({"region_id": 2, "gift_name": "Doll", "quantity": 5}))
+            .await
+            .assert_status_not_found();
+        server
+            .patch("/13/orders/2")
+            .json(&json!({"quantity": 9}))
+            .await
+            .assert_status_not_found();
+        server
+            .patch("/13/orders/2")
+            .json(&json!({}))
+            .await
+            .assert_status_not_found();
+        server
+            .delete("/13/orders/2")
+            .await
+            .assert_status_not_found();
+    }
+}
@@ -48,47 +48,972 @@
 //!
 //! {"elf":5,"elf on a shelf":1,"shelf with no elf on it":1}
 //! ```
-use axum::{routing::post, Json, Router};
-use serde::Serialize;
+//!
+//! # Extension: Counting arbitrary patterns
+//!
+//! The counters above are a preset of a generic pattern-counting engine that
+//! is also exposed at POST `/6/count`. It takes the text and a list of patterns
+//! and counts all of them in a single pass over the text.
+//!
+//! Every pattern supports the following options:
+//!
+//! * `name`: key of the count in the response (defaults to the pattern).
+//! * `overlapping`: count occurrences that overlap a previous occurrence of the
+//!   same pattern (defaults to `false`).
+//! * `case_insensitive`: ignore ASCII case when matching (defaults to `false`).
+//! * `whole_word`: only count occurrences that are not part of a larger word
+//!   (defaults to `false`).
+//! * `preceded_by` / `not_preceded_by`: only count occurrences that are (not)
+//!   directly preceded by the given string.
+//!
+//! ## Example
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/6/count \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{
+//!     "text": "there is an elf on a shelf on an elf. there is also another shelf in Belfast.",
+//!     "patterns": [
+//!       {"pattern": "elf", "whole_word": true},
+//!       {"name": "lonely shelf", "pattern": "shelf", "not_preceded_by": "elf on a "}
+//!     ]
+//!   }'
+//!
+//! {"elf":2,"lonely shelf":1}
+//! ```
+//!
+//! # Extension: Explaining the counts
+//!
+//! Both `/6` and `/6/count` accept `?explain=true`, which wraps the counts in a
+//! `counts` field and adds a `matches` field listing every counted occurrence
+//! per category with its byte offsets (`start`, `end`) and its 1-based `line`
+//! and `column`.
+//!
+//! Requests with `Accept: text/html` instead get the input rendered as HTML,
+//! with the occurrences of each category highlighted in a different color.
+//!
+//! ## Example
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/6?explain=true' \
+//!   -H 'Content-Type: text/plain' \
+//!   -d 'Belfast shelf'
+//!
+//! {
+//!   "counts": {"elf":2,"elf on a shelf":0,"shelf with no elf on it":1},
+//!   "matches": {
+//!     "elf": [
+//!       {"start":1,"end":4,"line":1,"column":2},
+//!       {"start":10,"end":13,"line":1,"column":11}
+//!     ],
+//!     "elf on a shelf": [],
+//!     "shelf with no elf on it": [{"start":8,"end":13,"line":1,"column":9}]
+//!   }
+//! }
+//! ```
+//!
+//! # Extension: Unicode-aware matching
+//!
+//! By default, text is matched byte for byte. Both `/6` and `/6/count` accept
+//! query parameters to match text the way a reader would:
+//!
+//! * `case_fold=true`: apply Unicode default case folding, so `ELF`, `Elf` and
+//!   `elf` are the same.
+//! * `normalization=nfc|nfkc`: normalize text and patterns, so precomposed and
+//!   decomposed characters match, and with `nfkc` full-width letters like `ＥＬＦ`
+//!   match their ASCII counterparts.
+//! * `graphemes=true`: ignore occurrences that start or end inside a grapheme
+//!   cluster (like `elf` in `elf́`) and count columns in grapheme clusters.
+//!
+//! Offsets reported by `?explain=true` always refer to the original input.
+//!
+//! ## Example
+//!
+//! ```not_rust
+//! curl -X POST 'http://localhost:8000/6?case_fold=true&normalization=nfkc' \
+//!   -H 'Content-Type: text/plain' \
+//!   -d 'ELF on a ＳＨＥＬＦ'
+//!
+//! {"elf":2,"elf on a shelf":1,"shelf with no elf on it":0}
+//! ```
+//!
+//! # Extension: Large letter archives
+//!
+//! `/6` scans the body as it arrives instead of reading it into memory first,
+//! so it handles arbitrarily large (and chunked) bodies. Invalid UTF-8 is
+//! replaced with `U+FFFD`. Explaining and highlighting still need the whole
+//! body and are limited to 2 MiB.
+//!
+//! Files can also be uploaded as `multipart/form-data`, in which case the
+//! counts are reported for every file and in total.
+//!
+//! ## Example
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/6 \
+//!   -F 'letters=@letters.txt' \
+//!   -F 'more=@more_letters.txt'
+//!
+//! {
+//!   "files": [
+//!     {"name":"letters.txt","elf":4,"elf on a shelf":0,"shelf with no elf on it":1},
+//!     {"name":"more_letters.txt","elf":5,"elf on a shelf":1,"shelf with no elf on it":1}
+//!   ],
+//!   "total": {"elf":9,"elf on a shelf":1,"shelf with no elf on it":2}
+//! }
+//! ```
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use axum::{
+    extract::{DefaultBodyLimit, FromRequest, Multipart, Query, Request},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use futures_util::StreamExt;
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use unic::{
+    normal::StrNormalForm,
+    segment::{GraphemeIndices, Graphemes},
+};
 
 /// Get Day 6 routes
 ///
 /// * `/6`
+/// * `/6/count`
 pub fn get_routes() -> Router {
-    Router::new().route("/6", post(elf))
+    Router::new()
+        .route("/6", post(elf).layer(DefaultBodyLimit::disable()))
+        .route("/6/count", post(count))
 }
 
 #[derive(Default, Serialize, Debug)]
 struct Elf {
-    elf: u32,
+    elf: u64,
     #[serde(rename = "elf on a shelf")]
-    elf_on_a_shelf: u32,
+    elf_on_a_shelf: u64,
     #[serde(rename = "shelf with no elf on it")]
-    shelf_with_no_elf: u32,
+    shelf_with_no_elf: u64,
 }
 
-async fn elf(payload: String) -> Json<Elf> {
-    const EOAS: &str = "elf on a shelf";
-    let mut elves = Elf::default();
-    let mut idx = 0;
+impl Elf {
+    /// Create the response from counts of [`elf_patterns`]
+    fn from_counts(counts: &[u64]) -> Self {
+        Self {
+            elf: counts[0],
+            elf_on_a_shelf: counts[1],
+            shelf_with_no_elf: counts[2],
+        }
+    }
+}
 
-    while let Some(i) = payload[idx..].find("elf") {
-        elves.elf += 1;
-        if payload[(idx + i)..].starts_with(EOAS) {
-            elves.elf_on_a_shelf += 1;
+/// Counts of a single file uploaded to `/6`
+#[derive(Serialize, Debug)]
+struct FileElf {
+    name: String,
+    #[serde(flatten)]
+    elves: Elf,
+}
+
+/// Response for files uploaded to `/6`
+#[derive(Serialize, Debug)]
+struct UploadElf {
+    files: Vec<FileElf>,
+    total: Elf,
+}
+
+/// Maximum size of a body that is kept in memory as a whole to explain or
+/// highlight it
+const MAX_BUFFERED: usize = 2 * 1024 * 1024;
+
+/// A pattern to count and the rules an occurrence has to satisfy
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+struct Pattern {
+    name: Option<String>,
+    pattern: String,
+    overlapping: bool,
+    case_insensitive: bool,
+    whole_word: bool,
+    preceded_by: Option<String>,
+    not_preceded_by: Option<String>,
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            ..Default::default()
         }
-        idx += i + "elf".len();
     }
 
-    idx = 0;
-    while let Some(i) = payload[idx..].find("shelf") {
-        idx += i + "shelf".len();
-        if !payload[0..idx].ends_with(EOAS) {
-            elves.shelf_with_no_elf += 1;
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.pattern)
+    }
+
+    /// Compare `a` and `b` honoring the case sensitivity of the pattern
+    fn eq(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive {
+            a.eq_ignore_ascii_case(b)
+        } else {
+            a == b
         }
     }
 
-    Json(elves)
+    /// Check whether `text[..start]` ends with `prefix`
+    fn is_preceded_by(&self, text: &str, start: usize, prefix: &str) -> bool {
+        start
+            .checked_sub(prefix.len())
+            .and_then(|s| text.get(s..start))
+            .is_some_and(|before| self.eq(before, prefix))
+    }
+
+    /// Check whether the occurrence at `text[start..end]` satisfies all rules
+    fn accepts(&self, text: &str, start: usize, end: usize) -> bool {
+        if !self.eq(&text[start..end], &self.pattern) {
+            return false;
+        }
+
+        if self.whole_word {
+            let is_word = |c: char| c.is_alphanumeric() || c == '_';
+            if text[..start].chars().next_back().is_some_and(is_word)
+                || text[end..].chars().next().is_some_and(is_word)
+            {
+                return false;
+            }
+        }
+
+        if let Some(prefix) = &self.preceded_by {
+            if !self.is_preceded_by(text, start, prefix) {
+                return false;
+            }
+        }
+
+        if let Some(prefix) = &self.not_preceded_by {
+            if self.is_preceded_by(text, start, prefix) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Unicode normalization form applied before matching
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Normalization {
+    #[default]
+    None,
+    Nfc,
+    Nfkc,
+}
+
+/// How text and patterns are transformed before matching
+#[derive(Default, Clone, Copy, Debug)]
+struct TextOptions {
+    /// Apply Unicode default case folding
+    case_fold: bool,
+    normalization: Normalization,
+    /// Reject occurrences that start or end inside a grapheme cluster and
+    /// count columns in grapheme clusters
+    graphemes: bool,
+}
+
+impl TextOptions {
+    fn is_identity(&self) -> bool {
+        !self.case_fold && self.normalization == Normalization::None && !self.graphemes
+    }
+
+    /// Transform a single piece of text
+    fn apply(&self, s: &str) -> String {
+        let normalize = |s: &str| match self.normalization {
+            Normalization::None => s.to_string(),
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfkc => s.nfkc().collect(),
+        };
+
+        if self.case_fold {
+            // Folding can produce denormalized text, so normalize once more
+            normalize(&caseless::default_case_fold_str(&normalize(s)))
+        } else {
+            normalize(s)
+        }
+    }
+
+    /// Transform `text` while keeping track of the original offsets
+    ///
+    /// Normalization can reorder and combine characters, so offsets are only
+    /// tracked at grapheme cluster boundaries when normalizing or when
+    /// grapheme-safe offsets are requested, and at character boundaries
+    /// otherwise.
+    fn map<'a>(&self, text: &'a str) -> Mapped<'a> {
+        if self.is_identity() {
+            return Mapped {
+                text: Cow::Borrowed(text),
+                bounds: None,
+                strict: false,
+            };
+        }
+
+        let units: Vec<(usize, &str)> =
+            if self.graphemes || self.normalization != Normalization::None {
+                GraphemeIndices::new(text).collect()
+            } else {
+                text.char_indices()
+                    .map(|(i, c)| (i, &text[i..i + c.len_utf8()]))
+                    .collect()
+            };
+
+        let mut mapped = String::with_capacity(text.len());
+        let mut bounds = Vec::with_capacity(units.len() + 1);
+        for (offset, unit) in units {
+            bounds.push((mapped.len(), offset));
+            mapped.push_str(&self.apply(unit));
+        }
+        bounds.push((mapped.len(), text.len()));
+
+        Mapped {
+            text: Cow::Owned(mapped),
+            bounds: Some(bounds),
+            strict: self.graphemes,
+        }
+    }
+
+    /// Number of columns `s` takes up
+    fn columns(&self, s: &str) -> usize {
+        if self.graphemes {
+            Graphemes::new(s).count()
+        } else {
+            s.chars().count()
+        }
+    }
+}
+
+/// Text transformed by [`TextOptions::map`]
+struct Mapped<'a> {
+    text: Cow<'a, str>,
+    /// Pairs of transformed and original offsets of every unit boundary
+    bounds: Option<Vec<(usize, usize)>>,
+    /// Whether ranges not aligned with unit boundaries are rejected
+    strict: bool,
+}
+
+impl Mapped<'_> {
+    /// Map a range of the transformed text to the original text
+    ///
+    /// Unaligned ranges are widened to the enclosing units, or rejected if the
+    /// mapping is strict.
+    fn original(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let Some(bounds) = &self.bounds else {
+            return Some((start, end));
+        };
+
+        let start = match bounds.binary_search_by_key(&start, |&(t, _)| t) {
+            Ok(idx) => bounds[idx].1,
+            Err(_) if self.strict => return None,
+            Err(idx) => bounds[idx - 1].1,
+        };
+        let end = match bounds.binary_search_by_key(&end, |&(t, _)| t) {
+            Ok(idx) => bounds[idx].1,
+            Err(_) if self.strict => return None,
+            Err(idx) => bounds[idx].1,
+        };
+
+        Some((start, end))
+    }
+}
+
+/// Counts a set of patterns in a single pass using an Aho-Corasick automaton
+struct Counter {
+    /// Patterns as transformed by `options`
+    patterns: Vec<Pattern>,
+    options: TextOptions,
+    automaton: AhoCorasick,
+    /// Indices into `patterns` for every literal in the automaton
+    literals: Vec<Vec<usize>>,
+    /// Bytes of already scanned input kept in front of new input, so that
+    /// occurrences and their preceding text can span input chunks
+    context: usize,
+}
+
+impl Counter {
+    fn new(patterns: Vec<Pattern>, options: TextOptions) -> Result<Self, (StatusCode, String)> {
+        let patterns: Vec<Pattern> = patterns
+            .into_iter()
+            .map(|p| Pattern {
+                name: Some(p.name().to_string()),
+                pattern: options.apply(&p.pattern),
+                preceded_by: p.preceded_by.map(|s| options.apply(&s)),
+                not_preceded_by: p.not_preceded_by.map(|s| options.apply(&s)),
+                ..p
+            })
+            .collect();
+        let mut names = HashMap::new();
+        let mut literals: Vec<(String, Vec<usize>)> = Vec::new();
+
+        for (idx, pattern) in patterns.iter().enumerate() {
+            if pattern.pattern.is_empty() {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("pattern `{}` is empty", pattern.name()),
+                ));
+            }
+            if names.insert(pattern.name(), idx).is_some() {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("pattern name `{}` is not unique", pattern.name()),
+                ));
+            }
+
+            // Case-insensitive matching finds a superset of the case-sensitive
+            // matches, so patterns are grouped by their lowercase form and
+            // `Pattern::accepts` filters out the wrong case afterwards.
+            let literal = pattern.pattern.to_ascii_lowercase();
+            match literals.iter_mut().find(|(l, _)| *l == literal) {
+                Some((_, indices)) => indices.push(idx),
+                None => literals.push((literal, vec![idx])),
+            }
+        }
+
+        let automaton = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(true)
+            .build(literals.iter().map(|(l, _)| l))
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+
+        // Transformations can shrink text (like full-width letters) by up to 4
+        // bytes per character.
+        let context = patterns
+            .iter()
+            .map(|p| {
+                p.pattern.len()
+                    + p.preceded_by
+                        .iter()
+                        .chain(&p.not_preceded_by)
+                        .map(String::len)
+                        .max()
+                        .unwrap_or_default()
+            })
+            .max()
+            .unwrap_or_default()
+            * 4
+            + LOOKAHEAD;
+
+        Ok(Self {
+            patterns,
+            options,
+            automaton,
+            literals: literals.into_iter().map(|(_, indices)| indices).collect(),
+            context,
+        })
+    }
+
+    /// Start scanning input that arrives in chunks
+    ///
+    /// If `keep_occurrences` is set, the scanner keeps every occurrence,
+    /// otherwise only the counts.
+    fn scanner(&self, keep_occurrences: bool) -> Scanner<'_> {
+        Scanner {
+            counter: self,
+            buffer: String::new(),
+            pending: Vec::new(),
+            base: 0,
+            reported: 0,
+            last_end: vec![0; self.patterns.len()],
+            counts: vec![0; self.patterns.len()],
+            occurrences: keep_occurrences.then(Vec::new),
+        }
+    }
+
+    /// Find the occurrences of every pattern in `text`
+    ///
+    /// Occurrences are returned in order of their end offset.
+    fn matches(&self, text: &str) -> Vec<Occurrence> {
+        let mut scanner = self.scanner(true);
+        scanner.feed(text.as_bytes());
+        scanner.finish().1
+    }
+
+    /// Count the occurrences of every pattern in `occurrences`
+    ///
+    /// The returned counts are in the same order as the patterns.
+    fn count(&self, occurrences: &[Occurrence]) -> Vec<u64> {
+        let mut counts = vec![0; self.patterns.len()];
+        for occurrence in occurrences {
+            counts[occurrence.pattern] += 1;
+        }
+        counts
+    }
+
+    /// Locate every occurrence in `text`, grouped by pattern name
+    fn explain(&self, text: &str, occurrences: &[Occurrence]) -> BTreeMap<String, Vec<Location>> {
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        let mut locations: BTreeMap<String, Vec<Location>> = self
+            .patterns
+            .iter()
+            .map(|p| (p.name().to_string(), Vec::new()))
+            .collect();
+
+        for occurrence in occurrences {
+            let line = line_starts.partition_point(|&s| s <= occurrence.start);
+            let column = self
+                .options
+                .columns(&text[line_starts[line - 1]..occurrence.start])
+                + 1;
+            locations
+                .get_mut(self.patterns[occurrence.pattern].name())
+                .expect("pattern names are known")
+                .push(Location {
+                    start: occurrence.start,
+                    end: occurrence.end,
+                    line,
+                    column,
+                });
+        }
+
+        for list in locations.values_mut() {
+            list.sort_by_key(|l| (l.start, l.end));
+        }
+
+        locations
+    }
+
+    /// Render `text` as HTML with the occurrences highlighted per pattern
+    fn highlight(&self, text: &str, occurrences: &[Occurrence]) -> Result<String, String> {
+        const COLORS: [&str; 8] = [
+            "#ffd54f", "#81c784", "#64b5f6", "#e57373", "#ba68c8", "#4db6ac", "#ff8a65", "#a1887f",
+        ];
+
+        // Split the text at every occurrence boundary; each piece is covered by
+        // the same set of occurrences and highlighted with the innermost one.
+        let mut bounds: Vec<usize> = occurrences
+            .iter()
+            .flat_map(|o| [o.start, o.end])
+            .chain([0, text.len()])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        // Sweep over the pieces, keeping the occurrences covering the current
+        // one by pattern, and by length for the innermost
+        let mut starts: Vec<usize> = (0..occurrences.len())
+            .filter(|&idx| occurrences[idx].start < occurrences[idx].end)
+            .collect();
+        let mut ends = starts.clone();
+        starts.sort_by_key(|&idx| occurrences[idx].start);
+        ends.sort_by_key(|&idx| occurrences[idx].end);
+        let (mut starts, mut ends) = (starts.into_iter().peekable(), ends.into_iter().peekable());
+        let mut covering: BTreeSet<(usize, usize)> = BTreeSet::new();
+        let mut by_length: BTreeSet<(usize, usize, usize)> = BTreeSet::new();
+
+        let mut segments = Vec::with_capacity(bounds.len());
+        for w in bounds.windows(2) {
+            while let Some(idx) = starts.next_if(|&idx| occurrences[idx].start <= w[0]) {
+                let o = &occurrences[idx];
+                covering.insert((o.pattern, idx));
+                by_length.insert((o.end - o.start, o.pattern, idx));
+            }
+            while let Some(idx) = ends.next_if(|&idx| occurrences[idx].end <= w[0]) {
+                let o = &occurrences[idx];
+                covering.remove(&(o.pattern, idx));
+                by_length.remove(&(o.end - o.start, o.pattern, idx));
+            }
+
+            let innermost = by_length.first().map(|&(_, pattern, _)| pattern);
+            segments.push(Segment {
+                text: &text[w[0]..w[1]],
+                color: innermost.map(|pattern| COLORS[pattern % COLORS.len()]),
+                title: covering
+                    .iter()
+                    .map(|&(pattern, _)| self.patterns[pattern].name())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            });
+        }
+
+        let legend: Vec<Segment> = self
+            .patterns
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| Segment {
+                text: p.name(),
+                color: Some(COLORS[idx % COLORS.len()]),
+                title: p.pattern.clone(),
+            })
+            .collect();
+
+        let source = "\
+<html>
+  <head>
+    <title>CCH23 Day 6</title>
+  </head>
+  <body>
+    <ul>
+      {{#each legend}}<li><mark style=\"background-color: {{color}}\" title=\"{{title}}\">{{text}}</mark></li>{{/each}}
+    </ul>
+    <pre>{{#each segments}}{{#if color}}<mark style=\"background-color: {{color}}\" title=\"{{title}}\">{{text}}</mark>{{else}}{{text}}{{/if}}{{/each}}</pre>
+  </body>
+</html>";
+
+        Handlebars::new()
+            .render_template(source, &json!({"legend": legend, "segments": segments}))
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Bytes at the end of the input that are only scanned once more input
+/// arrives, as they may be part of a longer grapheme cluster or be followed by
+/// a word character
+const LOOKAHEAD: usize = 32;
+
+/// Incremental scanner over input arriving in chunks
+///
+/// Input is decoded as UTF-8, replacing invalid sequences with `U+FFFD`, and
+/// scanned as soon as it arrives. Only the last [`Counter::context`] bytes are
+/// kept for the next chunk, so the input never has to fit in memory at once.
+struct Scanner<'a> {
+    counter: &'a Counter,
+    /// Decoded input that may still be needed
+    buffer: String,
+    /// Bytes of an incomplete UTF-8 sequence at the end of the last chunk
+    pending: Vec<u8>,
+    /// Offset of `buffer` in the input
+    base: usize,
+    /// Offset in the input up to which occurrences have been reported
+    reported: usize,
+    /// End offset of the last occurrence of every pattern
+    last_end: Vec<usize>,
+    counts: Vec<u64>,
+    occurrences: Option<Vec<Occurrence>>,
+}
+
+impl Scanner<'_> {
+    /// Scan the next chunk of input
+    fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+
+        let mut consumed = 0;
+        while consumed < self.pending.len() {
+            match std::str::from_utf8(&self.pending[consumed..]) {
+                Ok(s) => {
+                    self.buffer.push_str(s);
+                    consumed = self.pending.len();
+                }
+                Err(e) => {
+                    let valid = consumed + e.valid_up_to();
+                    self.buffer.push_str(
+                        std::str::from_utf8(&self.pending[consumed..valid])
+                            .expect("prefix is valid UTF-8"),
+                    );
+                    match e.error_len() {
+                        Some(len) => {
+                            self.buffer.push(char::REPLACEMENT_CHARACTER);
+                            consumed = valid + len;
+                        }
+                        // Incomplete sequence, wait for the next chunk
+                        None => {
+                            consumed = valid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending.drain(..consumed);
+
+        self.scan(false);
+    }
+
+    /// Scan the rest of the input and return the counts and, if kept, the
+    /// occurrences
+    fn finish(mut self) -> (Vec<u64>, Vec<Occurrence>) {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.buffer.push(char::REPLACEMENT_CHARACTER);
+        }
+        self.scan(true);
+
+        (self.counts, self.occurrences.unwrap_or_default())
+    }
+
+    fn scan(&mut self, last: bool) {
+        let counter = self.counter;
+        let limit = if last {
+            self.buffer.len()
+        } else {
+            floor_char_boundary(&self.buffer, self.buffer.len().saturating_sub(LOOKAHEAD))
+        };
+        let mapped = counter.options.map(&self.buffer);
+
+        // Matches are reported in order of their end offset, and for a single
+        // literal also in order of their start offset.
+        for m in counter
+            .automaton
+            .find_overlapping_iter(mapped.text.as_ref())
+        {
+            for &idx in &counter.literals[m.pattern()] {
+                let pattern = &counter.patterns[idx];
+                if !pattern.accepts(&mapped.text, m.start(), m.end()) {
+                    continue;
+                }
+                let Some((start, end)) = mapped.original(m.start(), m.end()) else {
+                    continue;
+                };
+                // Reported by a previous scan, or decided by the next one
+                if end > limit || self.base + end <= self.reported {
+                    continue;
+                }
+                let (start, end) = (self.base + start, self.base + end);
+                if !pattern.overlapping && start < self.last_end[idx] {
+                    continue;
+                }
+
+                self.counts[idx] += 1;
+                self.last_end[idx] = end;
+                if let Some(occurrences) = &mut self.occurrences {
+                    occurrences.push(Occurrence {
+                        pattern: idx,
+                        start,
+                        end,
+                    });
+                }
+            }
+        }
+
+        self.reported = self.base + limit;
+
+        let cut = floor_char_boundary(&self.buffer, limit.saturating_sub(counter.context));
+        self.buffer.drain(..cut);
+        self.base += cut;
+    }
+}
+
+/// Largest character boundary in `s` not after `idx`
+fn floor_char_boundary(s: &str, mut idx: usize) -> usize {
+    while !s.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// A single accepted occurrence of a pattern
+struct Occurrence {
+    /// Index of the pattern
+    pattern: usize,
+    start: usize,
+    end: usize,
+}
+
+/// Position of an occurrence as reported by `?explain=true`
+///
+/// `start` and `end` are byte offsets, `line` and `column` are 1-based and the
+/// column is counted in characters.
+#[derive(Serialize, Debug)]
+struct Location {
+    start: usize,
+    end: usize,
+    line: usize,
+    column: usize,
+}
+
+/// A piece of highlighted text
+#[derive(Serialize)]
+struct Segment<'a> {
+    text: &'a str,
+    color: Option<&'static str>,
+    title: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Params {
+    explain: bool,
+    case_fold: bool,
+    normalization: Normalization,
+    graphemes: bool,
+}
+
+impl Params {
+    fn text_options(&self) -> TextOptions {
+        TextOptions {
+            case_fold: self.case_fold,
+            normalization: self.normalization,
+            graphemes: self.graphemes,
+        }
+    }
+}
+
+fn wants_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Render the counts in the format requested by the client
+///
+/// Clients accepting `text/html` get the highlighted text, `?explain=true`
+/// adds the location of every occurrence to the JSON response.
+fn respond<T: Serialize>(
+    counter: &Counter,
+    text: &str,
+    occurrences: &[Occurrence],
+    counts: T,
+    params: &Params,
+    headers: &HeaderMap,
+) -> Result<Response, (StatusCode, String)> {
+    if wants_html(headers) {
+        let html = counter
+            .highlight(text, occurrences)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        Ok(Html(html).into_response())
+    } else if params.explain {
+        Ok(Json(json!({
+            "counts": counts,
+            "matches": counter.explain(text, occurrences),
+        }))
+        .into_response())
+    } else {
+        Ok(Json(counts).into_response())
+    }
+}
+
+/// The patterns counted by `/6`, named after the fields of [`Elf`]
+fn elf_patterns() -> Vec<Pattern> {
+    vec![
+        Pattern::new("elf"),
+        Pattern::new("elf on a shelf"),
+        Pattern {
+            name: Some("shelf with no elf on it".to_string()),
+            not_preceded_by: Some("elf on a ".to_string()),
+            ..Pattern::new("shelf")
+        },
+    ]
+}
+
+async fn elf(
+    Query(params): Query<Params>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Response, (StatusCode, String)> {
+    let counter = Counter::new(elf_patterns(), params.text_options())?;
+
+    let is_multipart = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+
+    if is_multipart {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?;
+        return Ok(Json(upload(&counter, multipart).await?).into_response());
+    }
+
+    if params.explain || wants_html(&headers) {
+        let payload = axum::body::to_bytes(request.into_body(), MAX_BUFFERED)
+            .await
+            .map_err(|e| (StatusCode::PAYLOAD_TOO_LARGE, e.to_string()))?;
+        let payload = String::from_utf8_lossy(&payload);
+        let occurrences = counter.matches(&payload);
+        let elves = Elf::from_counts(&counter.count(&occurrences));
+
+        return respond(&counter, &payload, &occurrences, elves, &params, &headers);
+    }
+
+    let mut stream = request.into_body().into_data_stream();
+    let mut scanner = counter.scanner(false);
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        scanner.feed(&chunk);
+    }
+    let (counts, _) = scanner.finish();
+
+    Ok(Json(Elf::from_counts(&counts)).into_response())
+}
+
+/// Count the elves in every file of a multipart upload
+async fn upload(
+    counter: &Counter,
+    mut multipart: Multipart,
+) -> Result<UploadElf, (StatusCode, String)> {
+    let mut files = Vec::new();
+    let mut total = vec![0; counter.patterns.len()];
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (e.status(), e.body_text()))?
+    {
+        let name = field
+            .file_name()
+            .or(field.name())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut scanner = counter.scanner(false);
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?
+        {
+            scanner.feed(&chunk);
+        }
+        let (counts, _) = scanner.finish();
+
+        for (total, count) in total.iter_mut().zip(&counts) {
+            *total += count;
+        }
+        files.push(FileElf {
+            name,
+            elves: Elf::from_counts(&counts),
+        });
+    }
+
+    Ok(UploadElf {
+        files,
+        total: Elf::from_counts(&total),
+    })
+}
+
+#[derive(Deserialize)]
+struct CountRequest {
+    text: String,
+    patterns: Vec<Pattern>,
+}
+
+async fn count(
+    Query(params): Query<Params>,
+    headers: HeaderMap,
+    Json(request): Json<CountRequest>,
+) -> Result<Response, (StatusCode, String)> {
+    let counter = Counter::new(request.patterns, params.text_options())?;
+    let occurrences = counter.matches(&request.text);
+    let counts: BTreeMap<String, u64> = counter
+        .patterns
+        .iter()
+        .map(|p| p.name().to_string())
+        .zip(counter.count(&occurrences))
+        .collect();
+
+    respond(
+        &counter,
+        &request.text,
+        &occurrences,
+        counts,
+        &params,
+        &headers,
+    )
 }
 
 #[cfg(test)]
@@ -98,6 +1023,10 @@ mod test {
         body::Body,
         http::{Method, Request},
     };
+    use axum_test::{
+        multipart::{MultipartForm, Part},
+        TestServer,
+    };
     use serde_json::{json, Value};
     use tower::util::ServiceExt;
 
@@ -154,4 +1083,275 @@ mod test {
 
         assert_eq!(body_json, expected_json);
     }
+
+    #[tokio::test]
+    async fn test_count() {
+        let app = get_routes();
+
+        let input = json!({
+            "text": "Elf, elf on a shelf! Belfast has no ELF. aaaa",
+            "patterns": [
+                {"pattern": "elf"},
+                {"name": "any elf", "pattern": "elf", "case_insensitive": true},
+                {"name": "word elf", "pattern": "elf", "case_insensitive": true, "whole_word": true},
+                {"name": "shelf on elf", "pattern": "shelf", "preceded_by": "elf on a "},
+                {"name": "aa", "pattern": "aa"},
+                {"name": "overlapping aa", "pattern": "aa", "overlapping": true}
+            ]
+        });
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/json")
+            .uri("/6/count")
+            .body(Body::from(input.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+        let expected_json = json!({
+            "elf": 3,
+            "any elf": 5,
+            "word elf": 3,
+            "shelf on elf": 1,
+            "aa": 2,
+            "overlapping aa": 3
+        });
+
+        assert_eq!(body_json, expected_json);
+    }
+
+    #[tokio::test]
+    async fn test_count_invalid() {
+        let app = get_routes();
+
+        let input = json!({"text": "elf", "patterns": [{"pattern": ""}]});
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/json")
+            .uri("/6/count")
+            .body(Body::from(input.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_explain() {
+        let app = get_routes();
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "text/plain")
+            .uri("/6?explain=true")
+            .body(Body::from("Belfast\n  an elf on a shelf"))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+        let expected_json = json!({
+            "counts": {"elf": 3, "elf on a shelf": 1, "shelf with no elf on it": 0},
+            "matches": {
+                "elf": [
+                    {"start": 1, "end": 4, "line": 1, "column": 2},
+                    {"start": 13, "end": 16, "line": 2, "column": 6},
+                    {"start": 24, "end": 27, "line": 2, "column": 17}
+                ],
+                "elf on a shelf": [{"start": 13, "end": 27, "line": 2, "column": 6}],
+                "shelf with no elf on it": []
+            }
+        });
+
+        assert_eq!(body_json, expected_json);
+    }
+
+    #[tokio::test]
+    async fn test_highlight() {
+        let app = get_routes();
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "text/plain")
+            .header("Accept", "text/html")
+            .uri("/6")
+            .body(Body::from("<b>shelf</b>"))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        assert!(response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        let body_string =
+            String::from_utf8(body_bytes.to_vec()).expect("Failed to convert body to string");
+
+        assert!(body_string.contains("&lt;b&gt;"));
+        assert!(body_string.contains(r#"title="shelf with no elf on it">sh</mark>"#));
+        assert!(body_string.contains(r#"title="elf, shelf with no elf on it">elf</mark>"#));
+    }
+
+    #[tokio::test]
+    async fn test_unicode() {
+        let input = "ELF, Elf and \u{ff25}\u{ff2c}\u{ff26} on a \u{017f}helf, elf\u{0301}";
+
+        for (uri, expected_json) in [
+            (
+                "/6",
+                json!({"elf": 2, "elf on a shelf": 0, "shelf with no elf on it": 0}),
+            ),
+            (
+                "/6?case_fold=true",
+                json!({"elf": 4, "elf on a shelf": 0, "shelf with no elf on it": 1}),
+            ),
+            (
+                "/6?case_fold=true&normalization=nfkc",
+                json!({"elf": 5, "elf on a shelf": 1, "shelf with no elf on it": 0}),
+            ),
+            (
+                "/6?case_fold=true&normalization=nfkc&graphemes=true",
+                json!({"elf": 4, "elf on a shelf": 1, "shelf with no elf on it": 0}),
+            ),
+        ] {
+            let app = get_routes();
+
+            let req = Request::builder()
+                .method(Method::POST)
+                .header("Content-Type", "text/plain")
+                .uri(uri)
+                .body(Body::from(input))
+                .unwrap();
+
+            let response = app.oneshot(req).await.unwrap();
+
+            let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("Failed to read response body");
+
+            let body_json: Value =
+                serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+
+            assert_eq!(body_json, expected_json, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unicode_explain() {
+        let app = get_routes();
+
+        let input = json!({
+            "text": "cafe\u{0301}\n\u{ff23}af\u{e9} caf\u{e9}",
+            "patterns": [{"pattern": "caf\u{e9}"}]
+        });
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/json")
+            .uri("/6/count?explain=true&case_fold=true&normalization=nfkc&graphemes=true")
+            .body(Body::from(input.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+        let expected_json = json!({
+            "counts": {"caf\u{e9}": 3},
+            "matches": {
+                "caf\u{e9}": [
+                    {"start": 0, "end": 6, "line": 1, "column": 1},
+                    {"start": 7, "end": 14, "line": 2, "column": 1},
+                    {"start": 15, "end": 20, "line": 2, "column": 6}
+                ]
+            }
+        });
+
+        assert_eq!(body_json, expected_json);
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let app = get_routes();
+
+        // Split occurrences, their preceding text and a multi-byte character
+        // across chunks, with enough filler in between to drop scanned input
+        let filler = "x".repeat(200);
+        let chunks: Vec<Vec<u8>> = vec![
+            b"an elf on a sh".to_vec(),
+            format!("elf{filler}caf").into_bytes(),
+            vec![0xc3],
+            [&[0xa9][..], format!(" shelf{filler}e").as_bytes()].concat(),
+            b"lf \xff".to_vec(),
+        ];
+        let stream = futures_util::stream::iter(chunks.into_iter().map(Ok::<_, std::io::Error>));
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "text/plain")
+            .uri("/6")
+            .body(Body::from_stream(stream))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+        let expected_json = json!({"elf": 4, "elf on a shelf": 1, "shelf with no elf on it": 1});
+
+        assert_eq!(body_json, expected_json);
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let app = get_routes();
+
+        let server = TestServer::new(app).unwrap();
+
+        let form = MultipartForm::new()
+            .add_part(
+                "letters",
+                Part::text("there is an elf on a shelf on an elf.").file_name("a.txt"),
+            )
+            .add_part(
+                "letters",
+                Part::text("there is also another shelf in Belfast.").file_name("b.txt"),
+            );
+
+        let response = server.post("/6").multipart(form).await;
+
+        response.assert_json(&json!({
+            "files": [
+                {"name": "a.txt", "elf": 3, "elf on a shelf": 1, "shelf with no elf on it": 0},
+                {"name": "b.txt", "elf": 2, "elf on a shelf": 0, "shelf with no elf on it": 1}
+            ],
+            "total": {"elf": 5, "elf on a shelf": 1, "shelf with no elf on it": 1}
+        }));
+    }
 }
@@ -123,86 +123,1395 @@
 //!   }
 //! }
 //! ```
-use axum::{http::StatusCode, routing::get, Router};
-use axum_extra::extract::CookieJar;
-use base64::engine::{general_purpose, Engine};
-use serde_json::Value;
+//!
+//! # Extension: Forgiving recipes
+//!
+//! Recipes may be encoded with the standard or the URL-safe base64 alphabet,
+//! with or without padding. Quantities can be decimal numbers with up to 9
+//! decimal places, which are calculated with exactly.
+//!
+//! An ingredient of the recipe that is missing from the pantry is not
+//! available at all, so no cookies can be baked, as in the example of Task 3.
+//! Ingredients with a quantity of 0 are not needed.
+//!
+//! Recipes that can't be decoded are answered with `400 Bad Request`. Recipes
+//! with invalid quantities are answered with `422 Unprocessable Entity` and a
+//! list of the invalid fields:
+//!
+//! ```not_rust
+//! {"errors":[{"field":"pantry.flour","message":"must not be negative"}]}
+//! ```
+//!
+//! # Extension: Units
+//!
+//! Quantities can be given as strings with a unit, like `"95 g"` or
+//! `"1.5 cup"`. Masses (`mg`, `g`, `kg`, `oz`, `lb`), volumes (`ml`, `l`,
+//! `tsp`, `tbsp`, `cup`, `fl oz`) and counts (`pc`, `dozen`) are converted
+//! into each other, so a recipe in grams can be baked from a pantry in
+//! kilograms. The pantry in the response keeps the units of the pantry.
+//!
+//! Converting between mass and volume needs the density of the ingredient.
+//! Densities of common ingredients like flour, sugar, butter and milk are
+//! known, others can be given in `densities` as g/ml or like `"120 g/cup"`.
+//! An ingredient given with a unit in one place and without in another, or
+//! with units that can't be converted, is answered with `422 Unprocessable
+//! Entity`.
+//!
+//! ```not_rust
+//! {
+//!   "recipe": {"flour": "1 cup", "butter": "113 g", "eggs": 1},
+//!   "pantry": {"flour": "1 kg", "butter": "1 lb", "eggs": 12},
+//!   "densities": {"flour": "125 g/cup"}
+//! }
+//!
+//! {"cookies":4,"pantry":{"butter":"0.003510575 lb","eggs":8,"flour":"0.5 kg"}}
+//! ```
+//!
+//! # Extension: Planning the baking
+//!
+//! POST `/7/plan` takes a pantry and either a single `recipe` or named
+//! `recipes` as JSON.
+//!
+//! With a `target` number of cookies (per recipe, or a single number for a
+//! single recipe), it answers which quantities of the ingredients are required
+//! and how much of them is missing from the pantry:
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/7/plan \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{"recipe":{"flour":95,"sugar":50},"pantry":{"flour":385,"sugar":507},"target":10}'
+//!
+//! {"missing":{"flour":565,"sugar":0},"required":{"flour":950,"sugar":500}}
+//! ```
+//!
+//! Without a target, it answers how many cookies of every recipe to bake to get
+//! the most out of the shared pantry. Recipes can be given `weights` to prefer
+//! some cookies over others, the mix with the highest total weight (`value`)
+//! wins. Finding the best mix is an integer program. If it can't be solved in
+//! reasonable time, the best mix found so far is returned with `"optimal":
+//! false`.
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/7/plan \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{
+//!     "recipes": {
+//!       "shortbread": {"flour": 3, "sugar": 1},
+//!       "meringue": {"flour": 1, "sugar": 3}
+//!     },
+//!     "pantry": {"flour": 10, "sugar": 10},
+//!     "weights": {"shortbread": 1, "meringue": 3}
+//!   }'
+//!
+//! {
+//!   "cookies": {"meringue":3,"shortbread":1},
+//!   "total": 4,
+//!   "value": 10,
+//!   "optimal": true,
+//!   "pantry": {"flour":4}
+//! }
+//! ```
+//!
+//! # Extension: Recipes issued by the server
+//!
+//! POST `/7/recipe` takes a recipe and pantry as JSON and stores them in a
+//! cookie that can't be altered by the client. With `?protection=signed` (the
+//! default) the cookie `signed_recipe` is signed with an HMAC, with
+//! `?protection=private` the cookie `private_recipe` is encrypted.
+//!
+//! `/7/decode` and `/7/bake` read these cookies before falling back to the
+//! plain `recipe` cookie, and reject cookies that have been tampered with
+//! (`400 Bad Request`).
+//!
+//! The keys are read from the `COOKIE_KEYS` environment variable, a comma
+//! separated list of base64 encoded keys of at least 64 bytes with the current
+//! key first. Cookies using one of the other keys are still accepted and
+//! replaced with a cookie using the current key, so keys can be rotated
+//! without invalidating existing cookies.
+//!
+//! The cookies are limited to the `/7` path, hidden from scripts and not sent
+//! along cross-site requests. With `COOKIE_SECURE=true`, they are only sent
+//! over HTTPS.
+//!
+//! ## Example
+//!
+//! ```not_rust
+//! curl -i -X POST 'http://localhost:8000/7/recipe?protection=private' \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{"recipe":{"flour":95},"pantry":{"flour":385}}'
+//!
+//! HTTP/1.1 200 OK
+//! set-cookie: private_recipe=...; HttpOnly; SameSite=Lax; Path=/7
+//! ```
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, Key, SameSite},
+    CookieJar, PrivateCookieJar, SignedCookieJar,
+};
+use base64::{
+    alphabet,
+    engine::{general_purpose, DecodePaddingMode, Engine, GeneralPurpose, GeneralPurposeConfig},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Name of the plain cookie of the challenge
+const RECIPE: &str = "recipe";
+/// Name of the cookie signed by the server
+const SIGNED_RECIPE: &str = "signed_recipe";
+/// Name of the cookie encrypted by the server
+const PRIVATE_RECIPE: &str = "private_recipe";
+
+/// URL-safe base64 engine accepting recipes with and without padding
+const URL_SAFE_INDIFFERENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Standard base64 engine accepting recipes with and without padding
+const STANDARD_INDIFFERENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
 
 /// Get Day 7 routes
 ///
 /// * `/7/decode`
 /// * `/7/bake`
+/// * `/7/recipe`
+/// * `/7/plan`
 pub fn get_routes() -> Router {
+    router(CookieKeys::from_env())
+}
+
+fn router(keys: CookieKeys) -> Router {
     Router::new()
         .route("/7/decode", get(decode))
         .route("/7/bake", get(bake))
+        .route("/7/recipe", post(recipe))
+        .route("/7/plan", post(plan))
+        .with_state(Arc::new(keys))
 }
 
-async fn decode(jar: CookieJar) -> Result<String, StatusCode> {
-    if let Some(recipe) = jar.get("recipe") {
-        general_purpose::URL_SAFE
-            .decode(recipe.value())
-            .ok()
-            .and_then(|r| String::from_utf8(r).ok())
-            .ok_or(StatusCode::NOT_ACCEPTABLE)
-    } else {
-        Err(StatusCode::NOT_FOUND)
+/// Keys used to sign and encrypt recipe cookies
+///
+/// New cookies always use the current key. Cookies using one of the previous
+/// keys are still accepted, and replaced with a cookie using the current key.
+struct CookieKeys {
+    current: Key,
+    previous: Vec<Key>,
+    /// Whether the cookies are only sent over HTTPS
+    secure: bool,
+}
+
+impl CookieKeys {
+    /// Read the keys from `COOKIE_KEYS`
+    ///
+    /// The variable holds a comma separated list of base64 encoded keys of at
+    /// least 64 bytes, current key first. Without it, a random key is
+    /// generated and cookies don't survive a restart. Cookies are only sent
+    /// over HTTPS with `COOKIE_SECURE=true`.
+    fn from_env() -> Self {
+        let secure = std::env::var("COOKIE_SECURE").is_ok_and(|value| value == "true");
+        let keys: Vec<Key> = std::env::var("COOKIE_KEYS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .filter_map(|key| {
+                let key = general_purpose::STANDARD.decode(key.trim()).ok();
+                let key = key.and_then(|key| Key::try_from(key.as_slice()).ok());
+                if key.is_none() {
+                    tracing::warn!("ignoring invalid key in COOKIE_KEYS");
+                }
+                key
+            })
+            .collect();
+
+        let mut keys = keys.into_iter();
+        match keys.next() {
+            Some(current) => Self {
+                current,
+                previous: keys.collect(),
+                secure,
+            },
+            None => Self {
+                current: Key::generate(),
+                previous: Vec::new(),
+                secure,
+            },
+        }
+    }
+
+    /// A recipe cookie to send to the client
+    fn cookie(&self, name: &'static str, value: String) -> Cookie<'static> {
+        Cookie::build((name, value))
+            .path("/7")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(self.secure)
+            .build()
+    }
+
+    /// All keys, current key first
+    fn all(&self) -> impl Iterator<Item = &Key> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+}
+
+/// The recipe cookie sent by the client
+struct RecipeCookie {
+    /// The base64 encoded recipe
+    value: String,
+    /// Cookies to send back if the recipe cookie used a previous key
+    signed: SignedCookieJar,
+    private: PrivateCookieJar,
+}
+
+impl RecipeCookie {
+    /// Load the recipe from the private, signed or plain cookie, in that order
+    ///
+    /// Private and signed cookies that don't verify with any of the keys have
+    /// been tampered with and are rejected.
+    fn load(headers: &HeaderMap, keys: &CookieKeys) -> Result<Self, (StatusCode, String)> {
+        let signed = SignedCookieJar::new(keys.current.clone());
+        let private = PrivateCookieJar::new(keys.current.clone());
+        let plain = CookieJar::from_headers(headers);
+
+        if plain.get(PRIVATE_RECIPE).is_some() {
+            for (idx, key) in keys.all().enumerate() {
+                if let Some(cookie) =
+                    PrivateCookieJar::from_headers(headers, key.clone()).get(PRIVATE_RECIPE)
+                {
+                    let value = cookie.value().to_string();
+                    let private = if idx > 0 {
+                        private.add(keys.cookie(PRIVATE_RECIPE, value.clone()))
+                    } else {
+                        private
+                    };
+                    return Ok(Self {
+                        value,
+                        signed,
+                        private,
+                    });
+                }
+            }
+
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("cookie `{PRIVATE_RECIPE}` can't be decrypted, it has been tampered with"),
+            ));
+        }
+
+        if plain.get(SIGNED_RECIPE).is_some() {
+            for (idx, key) in keys.all().enumerate() {
+                if let Some(cookie) =
+                    SignedCookieJar::from_headers(headers, key.clone()).get(SIGNED_RECIPE)
+                {
+                    let value = cookie.value().to_string();
+                    let signed = if idx > 0 {
+                        signed.add(keys.cookie(SIGNED_RECIPE, value.clone()))
+                    } else {
+                        signed
+                    };
+                    return Ok(Self {
+                        value,
+                        signed,
+                        private,
+                    });
+                }
+            }
+
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "cookie `{SIGNED_RECIPE}` has an invalid signature, it has been tampered with"
+                ),
+            ));
+        }
+
+        match plain.get(RECIPE) {
+            Some(cookie) => Ok(Self {
+                value: cookie.value().to_string(),
+                signed,
+                private,
+            }),
+            None => Err((StatusCode::NOT_FOUND, "no recipe cookie".to_string())),
+        }
+    }
+
+    /// Decode the base64 encoded recipe
+    ///
+    /// Both the standard and the URL-safe alphabet are accepted, with or
+    /// without padding.
+    fn decode(&self) -> Result<String, (StatusCode, String)> {
+        let value = self.value.trim();
+        let bytes = URL_SAFE_INDIFFERENT
+            .decode(value)
+            .or_else(|_| STANDARD_INDIFFERENT.decode(value))
+            .map_err(|e| {
+                (
+                    StatusCode::BAD_REQUEST,
+                    format!("recipe is not base64 encoded: {e}"),
+                )
+            })?;
+
+        String::from_utf8(bytes).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("recipe is not valid UTF-8: {e}"),
+            )
+        })
+    }
+}
+
+async fn decode(
+    State(keys): State<Arc<CookieKeys>>,
+    headers: HeaderMap,
+) -> Result<(SignedCookieJar, PrivateCookieJar, String), (StatusCode, String)> {
+    let recipe = RecipeCookie::load(&headers, &keys)?;
+    let decoded = recipe.decode()?;
+
+    Ok((recipe.signed, recipe.private, decoded))
+}
+
+async fn bake(
+    State(keys): State<Arc<CookieKeys>>,
+    headers: HeaderMap,
+) -> Result<(SignedCookieJar, PrivateCookieJar, Json<Value>), Response> {
+    let recipe = RecipeCookie::load(&headers, &keys).map_err(IntoResponse::into_response)?;
+    let decoded = recipe.decode().map_err(IntoResponse::into_response)?;
+    let json: Value = serde_json::from_str(&decoded).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("recipe is not valid JSON: {e}"),
+        )
+            .into_response()
+    })?;
+    let result = bake_recipe(&json).map_err(|errors| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": errors })),
+        )
+            .into_response()
+    })?;
+
+    Ok((recipe.signed, recipe.private, Json(result)))
+}
+
+/// A validation error of a single field of the recipe
+#[derive(Serialize, Debug)]
+struct FieldError {
+    /// Path of the field, like `pantry.flour`
+    field: String,
+    message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// A non-negative decimal quantity of an ingredient
+///
+/// Quantities are stored as fixed-point numbers with [`Quantity::DECIMALS`]
+/// decimal places, so the arithmetic is exact and checked for overflow.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+struct Quantity(u128);
+
+impl Quantity {
+    const DECIMALS: u32 = 9;
+    const SCALE: u128 = 10u128.pow(Self::DECIMALS);
+
+    /// Parse a JSON number
+    fn from_json(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Number(n) => n.to_string().parse(),
+            _ => Err("must be a number".to_string()),
+        }
+    }
+
+    /// Convert back to a JSON number, an integer if there is no fraction
+    fn to_json(self) -> Value {
+        if self.0.is_multiple_of(Self::SCALE) {
+            if let Ok(int) = u64::try_from(self.0 / Self::SCALE) {
+                return json!(int);
+            }
+        }
+
+        serde_json::from_str(&self.to_decimal()).expect("decimal is a valid JSON number")
+    }
+
+    /// Format as a decimal number without trailing zeros
+    fn to_decimal(self) -> String {
+        let (int, frac) = (self.0 / Self::SCALE, self.0 % Self::SCALE);
+        if frac == 0 {
+            return int.to_string();
+        }
+
+        let frac = format!("{frac:0width$}", width = Self::DECIMALS as usize);
+        format!("{int}.{}", frac.trim_end_matches('0'))
+    }
+
+    /// Multiply by a decimal factor, rounded to the nearest quantity
+    fn mul_round(self, factor: Self) -> Option<Self> {
+        self.0
+            .checked_mul(factor.0)?
+            .checked_add(Self::SCALE / 2)
+            .map(|product| Self(product / Self::SCALE))
+    }
+
+    /// Divide by a decimal divisor, rounded to the nearest quantity
+    fn div_round(self, divisor: Self) -> Option<Self> {
+        self.0
+            .checked_mul(Self::SCALE)?
+            .checked_add(divisor.0 / 2)?
+            .checked_div(divisor.0)
+            .map(Self)
+    }
+
+    /// How many times `other` fits into `self`
+    fn div_floor(self, other: Self) -> u128 {
+        self.0 / other.0
+    }
+
+    fn checked_mul(self, count: u128) -> Option<Self> {
+        self.0.checked_mul(count).map(Self)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
     }
 }
 
-async fn bake(jar: CookieJar) -> Result<String, StatusCode> {
-    if let Some(recipe) = jar.get("recipe") {
-        let recipe_d = general_purpose::URL_SAFE
-            .decode(recipe.value())
+impl std::str::FromStr for Quantity {
+    type Err = String;
+
+    /// Parse a decimal number like `12`, `0.5` or `1.5e3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (
+                mantissa,
+                exponent
+                    .parse::<i32>()
+                    .map_err(|_| format!("`{s}` is not a number"))?,
+            ),
+            None => (s, 0),
+        };
+        if mantissa.starts_with('-') && mantissa.bytes().any(|b| (b'1'..=b'9').contains(&b)) {
+            return Err("must not be negative".to_string());
+        }
+        let mantissa = mantissa.trim_start_matches(['-', '+']);
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty()
+            || !int.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit())
+        {
+            return Err(format!("`{s}` is not a number"));
+        }
+
+        // Shift the decimal point of the digits to the fixed-point scale
+        let digits = format!("{int}{frac}");
+        let digits = digits.trim_start_matches('0');
+        let shift = i64::from(exponent) + i64::from(Self::DECIMALS) - frac.len() as i64;
+        let (digits, shift) = if shift < 0 {
+            let keep = digits.len().saturating_sub(shift.unsigned_abs() as usize);
+            if digits[keep..].bytes().any(|b| b != b'0') {
+                return Err(format!(
+                    "must not have more than {} decimal places",
+                    Self::DECIMALS
+                ));
+            }
+            (&digits[..keep], 0)
+        } else {
+            (digits, shift)
+        };
+
+        let too_large = || "is too large".to_string();
+        let value = if digits.is_empty() {
+            0
+        } else {
+            digits.parse::<u128>().map_err(|_| too_large())?
+        };
+        u32::try_from(shift)
             .ok()
-            .and_then(|r| String::from_utf8(r).ok())
-            .ok_or(StatusCode::NOT_ACCEPTABLE)?;
-        let json: Value =
-            serde_json::from_str(&recipe_d).map_err(|_| StatusCode::NOT_ACCEPTABLE)?;
-        let recipe = json
-            .get("recipe")
-            .ok_or(StatusCode::NOT_ACCEPTABLE)?
-            .clone();
-        let mut pantry = json
-            .get("pantry")
-            .ok_or(StatusCode::NOT_ACCEPTABLE)?
-            .clone();
-
-        let mut cookies = u64::MAX;
-
-        // Get the number of cookies we can bake
-        for (key, value) in recipe.as_object().unwrap() {
-            if let Some(ingredient) = pantry.get(key) {
-                if value.as_u64().unwrap_or_default() > 0 {
-                    cookies = (ingredient.as_u64().unwrap_or_default()
-                        / value.as_u64().unwrap_or(1))
-                    .min(cookies);
+            .and_then(|shift| 10u128.checked_pow(shift))
+            .and_then(|scale| value.checked_mul(scale))
+            .map(Self)
+            .ok_or_else(too_large)
+    }
+}
+
+/// What a unit measures
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Dimension {
+    Mass,
+    Volume,
+    Count,
+}
+
+/// A unit of measurement of ingredients
+#[derive(Debug)]
+struct Unit {
+    symbol: &'static str,
+    aliases: &'static [&'static str],
+    dimension: Dimension,
+    /// Size of the unit in the base unit of its dimension, grams, milliliters
+    /// or pieces
+    factor: Quantity,
+}
+
+/// Units known to recipes and pantries
+const UNITS: &[Unit] = &[
+    Unit::new(
+        "mg",
+        &["milligram", "milligrams"],
+        Dimension::Mass,
+        1_000_000,
+    ),
+    Unit::new("g", &["gram", "grams"], Dimension::Mass, 1_000_000_000),
+    Unit::new(
+        "kg",
+        &["kilogram", "kilograms"],
+        Dimension::Mass,
+        1_000_000_000_000,
+    ),
+    Unit::new("oz", &["ounce", "ounces"], Dimension::Mass, 28_349_523_125),
+    Unit::new(
+        "lb",
+        &["lbs", "pound", "pounds"],
+        Dimension::Mass,
+        453_592_370_000,
+    ),
+    Unit::new(
+        "ml",
+        &["milliliter", "milliliters"],
+        Dimension::Volume,
+        1_000_000_000,
+    ),
+    Unit::new(
+        "l",
+        &["liter", "liters"],
+        Dimension::Volume,
+        1_000_000_000_000,
+    ),
+    Unit::new(
+        "tsp",
+        &["teaspoon", "teaspoons"],
+        Dimension::Volume,
+        4_928_921_594,
+    ),
+    Unit::new(
+        "tbsp",
+        &["tablespoon", "tablespoons"],
+        Dimension::Volume,
+        14_786_764_781,
+    ),
+    Unit::new("cup", &["cups"], Dimension::Volume, 236_588_236_500),
+    Unit::new(
+        "fl oz",
+        &["floz", "fluid ounce", "fluid ounces"],
+        Dimension::Volume,
+        29_573_529_563,
+    ),
+    Unit::new(
+        "pc",
+        &["pcs", "piece", "pieces"],
+        Dimension::Count,
+        1_000_000_000,
+    ),
+    Unit::new("dozen", &[], Dimension::Count, 12_000_000_000),
+];
+
+/// Densities in g/ml used if the recipe doesn't override them
+const DENSITIES: &[(&str, &str)] = &[
+    ("water", "1"),
+    ("milk", "1.03"),
+    ("flour", "0.593"),
+    ("sugar", "0.845"),
+    ("butter", "0.911"),
+    ("honey", "1.42"),
+    ("oil", "0.92"),
+];
+
+impl Unit {
+    const fn new(
+        symbol: &'static str,
+        aliases: &'static [&'static str],
+        dimension: Dimension,
+        factor: u128,
+    ) -> Self {
+        Self {
+            symbol,
+            aliases,
+            dimension,
+            factor: Quantity(factor),
+        }
+    }
+
+    /// Find a unit by its symbol or one of its aliases, ignoring case
+    fn find(name: &str) -> Result<&'static Self, String> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        UNITS
+            .iter()
+            .find(|unit| {
+                std::iter::once(&unit.symbol)
+                    .chain(unit.aliases)
+                    .any(|alias| alias.eq_ignore_ascii_case(&name))
+            })
+            .ok_or_else(|| format!("has an unknown unit `{name}`"))
+    }
+}
+
+/// A quantity of an ingredient as written, with an optional unit
+#[derive(Clone, Copy, Debug)]
+struct Amount {
+    value: Quantity,
+    unit: Option<&'static Unit>,
+}
+
+impl Amount {
+    /// Parse a JSON number, or a string with a number and a unit like `95 g`
+    fn from_json(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(s) => s.parse(),
+            value => Ok(Self {
+                value: Quantity::from_json(value)?,
+                unit: None,
+            }),
+        }
+    }
+
+    /// The quantity in the base unit of its dimension
+    fn to_base(self) -> Result<Quantity, String> {
+        match self.unit {
+            Some(unit) => self
+                .value
+                .mul_round(unit.factor)
+                .ok_or_else(|| "is too large".to_string()),
+            None => Ok(self.value),
+        }
+    }
+}
+
+impl std::str::FromStr for Amount {
+    type Err = String;
+
+    /// Parse a number followed by an optional unit, like `1.5 cup` or `95g`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut end = s
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+            .unwrap_or(s.len());
+        // Only treat an `e` as exponent if digits follow it
+        if let Some(exponent) = s[end..].strip_prefix(['e', 'E']) {
+            let digits = exponent.trim_start_matches(['-', '+']);
+            if digits.starts_with(|c: char| c.is_ascii_digit()) {
+                end = s.len() - digits.len()
+                    + digits
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(digits.len());
+            }
+        }
+
+        let (number, unit) = s.split_at(end);
+        if number.is_empty() {
+            return Err("must be a number".to_string());
+        }
+        let unit = unit.trim();
+
+        Ok(Self {
+            value: number.parse()?,
+            unit: (!unit.is_empty()).then(|| Unit::find(unit)).transpose()?,
+        })
+    }
+}
+
+/// Parse the ingredients in `value`, a recipe or pantry at `path`
+fn ingredients(
+    value: Option<&Value>,
+    path: &str,
+    errors: &mut Vec<FieldError>,
+) -> Vec<(String, Amount)> {
+    let Some(value) = value else {
+        errors.push(FieldError::new(path, "is missing"));
+        return Vec::new();
+    };
+    let Some(object) = value.as_object() else {
+        errors.push(FieldError::new(path, "must be an object"));
+        return Vec::new();
+    };
+
+    object
+        .iter()
+        .filter_map(|(name, value)| match Amount::from_json(value) {
+            Ok(amount) => Some((name.clone(), amount)),
+            Err(message) => {
+                errors.push(FieldError::new(format!("{path}.{name}"), message));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Units and densities of the ingredients of a recipe and pantry
+///
+/// Every ingredient is measured in the unit it is first seen with, normally
+/// the unit of the pantry. Quantities are converted to the base unit of that
+/// dimension, so they can be compared, and back to the unit for the response.
+#[derive(Default)]
+struct Measures {
+    /// Density of ingredients in g/ml
+    densities: Vec<(String, Quantity)>,
+    /// Unit every ingredient is answered in, `None` for plain numbers
+    units: Vec<(String, Option<&'static Unit>)>,
+}
+
+impl Measures {
+    /// Read the `densities` of the recipe, on top of the defaults
+    fn new(json: &Value, errors: &mut Vec<FieldError>) -> Self {
+        let mut densities: Vec<(String, Quantity)> = DENSITIES
+            .iter()
+            .map(|(name, density)| {
+                let density = density.parse().expect("default densities are valid");
+                (name.to_string(), density)
+            })
+            .collect();
+
+        match json.get("densities") {
+            None => {}
+            Some(Value::Object(object)) => {
+                for (name, value) in object {
+                    match Self::density(value) {
+                        Ok(density) => {
+                            densities.retain(|(n, _)| n != name);
+                            densities.push((name.clone(), density));
+                        }
+                        Err(message) => {
+                            errors.push(FieldError::new(format!("densities.{name}"), message));
+                        }
+                    }
+                }
+            }
+            Some(_) => errors.push(FieldError::new("densities", "must be an object")),
+        }
+
+        Self {
+            densities,
+            units: Vec::new(),
+        }
+    }
+
+    /// Parse a density in g/ml, or with units like `120 g/cup`
+    fn density(value: &Value) -> Result<Quantity, String> {
+        let density = match value {
+            Value::String(s) => {
+                let (mass, volume) = s
+                    .split_once('/')
+                    .ok_or("must be a mass per volume like `120 g/cup`")?;
+                let mass: Amount = mass.parse()?;
+                let volume = Unit::find(volume)?;
+                match mass.unit {
+                    Some(unit)
+                        if unit.dimension == Dimension::Mass
+                            && volume.dimension == Dimension::Volume =>
+                    {
+                        mass.to_base()?
+                            .div_round(volume.factor)
+                            .ok_or("is too large")?
+                    }
+                    _ => return Err("must be a mass per volume like `120 g/cup`".to_string()),
                 }
             }
+            value => Quantity::from_json(value)?,
+        };
+
+        if density == Quantity::default() {
+            return Err("must be greater than zero".to_string());
+        }
+        Ok(density)
+    }
+
+    /// Convert the amounts of a recipe or pantry at `path` to base units
+    fn normalize(
+        &mut self,
+        amounts: &[(String, Amount)],
+        path: &str,
+        errors: &mut Vec<FieldError>,
+    ) -> Vec<(String, Quantity)> {
+        amounts
+            .iter()
+            .filter_map(|(name, amount)| match self.convert(name, *amount) {
+                Ok(quantity) => Some((name.clone(), quantity)),
+                Err(message) => {
+                    errors.push(FieldError::new(format!("{path}.{name}"), message));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Convert an amount of ingredient `name` to the base unit of its dimension
+    fn convert(&mut self, name: &str, amount: Amount) -> Result<Quantity, String> {
+        let Some((_, unit)) = self.units.iter().find(|(n, _)| n == name) else {
+            self.units.push((name.to_string(), amount.unit));
+            return amount.to_base();
+        };
+
+        let (from, to) = match (amount.unit, unit) {
+            (None, None) => return Ok(amount.value),
+            (Some(from), Some(to)) => (from, to),
+            _ => return Err("must be given with or without a unit everywhere".to_string()),
+        };
+        let base = amount.to_base()?;
+        if from.dimension == to.dimension {
+            return Ok(base);
+        }
+
+        let density = self.densities.iter().find(|(n, _)| n == name);
+        let converted = match (from.dimension, to.dimension, density) {
+            (Dimension::Volume, Dimension::Mass, Some((_, density))) => base.mul_round(*density),
+            (Dimension::Mass, Dimension::Volume, Some((_, density))) => base.div_round(*density),
+            (Dimension::Volume | Dimension::Mass, Dimension::Volume | Dimension::Mass, None) => {
+                return Err(format!(
+                    "needs a density to convert {} to {}",
+                    from.symbol, to.symbol
+                ))
+            }
+            _ => return Err(format!("can't convert {} to {}", from.symbol, to.symbol)),
+        };
+        converted.ok_or_else(|| "is too large".to_string())
+    }
+
+    /// A quantity of ingredient `name` in base units, in its unit for the
+    /// response
+    fn to_json(&self, name: &str, quantity: Quantity) -> Value {
+        match self.units.iter().find(|(n, _)| n == name) {
+            Some((_, Some(unit))) => match quantity.div_round(unit.factor) {
+                Some(value) => json!(format!("{} {}", value.to_decimal(), unit.symbol)),
+                // Too much to express in small units, fall back to the base unit
+                None => {
+                    let base = UNITS
+                        .iter()
+                        .find(|base| {
+                            base.dimension == unit.dimension && base.factor.0 == Quantity::SCALE
+                        })
+                        .expect("every dimension has a base unit");
+                    json!(format!("{} {}", quantity.to_decimal(), base.symbol))
+                }
+            },
+            _ => quantity.to_json(),
         }
+    }
+}
+
+/// Bake as many cookies as possible with the recipe from the pantry
+///
+/// Ingredients of the recipe missing from the pantry make 0 cookies.
+fn bake_recipe(json: &Value) -> Result<Value, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let recipe = ingredients(json.get("recipe"), "recipe", &mut errors);
+    let pantry = ingredients(json.get("pantry"), "pantry", &mut errors);
+    let mut measures = Measures::new(json, &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // The response is in the units of the pantry, so they are seen first
+    let mut pantry = measures.normalize(&pantry, "pantry", &mut errors);
+    let recipe = measures.normalize(&recipe, "recipe", &mut errors);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // Every ingredient of the recipe limits the number of cookies, ingredients
+    // missing from the pantry are not available at all.
+    let cookies = recipe
+        .iter()
+        .filter(|(_, quantity)| *quantity > Quantity::default())
+        .map(|(name, quantity)| {
+            pantry
+                .iter()
+                .find(|(n, _)| n == name)
+                .map_or(0, |(_, available)| available.div_floor(*quantity))
+        })
+        .min()
+        .unwrap_or(0);
+    let cookies = u64::try_from(cookies)
+        .map_err(|_| vec![FieldError::new("recipe", "makes too many cookies to count")])?;
+
+    // Subtract the ingredients used to make the cookies
+    for (name, available) in &mut pantry {
+        if let Some((_, quantity)) = recipe.iter().find(|(n, _)| n == name) {
+            *available = quantity
+                .checked_mul(cookies.into())
+                .and_then(|used| available.checked_sub(used))
+                .expect("cookies are limited by the pantry");
+        }
+    }
+
+    let pantry: serde_json::Map<String, Value> = pantry
+        .into_iter()
+        .map(|(name, quantity)| {
+            let value = measures.to_json(&name, quantity);
+            (name, value)
+        })
+        .collect();
+
+    Ok(json!({
+        "cookies": cookies,
+        "pantry": pantry,
+    }))
+}
+
+/// How `/7/recipe` protects the cookie
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Protection {
+    #[default]
+    Signed,
+    Private,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RecipeParams {
+    protection: Protection,
+}
+
+/// Store a recipe and pantry in a signed or private cookie
+async fn recipe(
+    State(keys): State<Arc<CookieKeys>>,
+    Query(params): Query<RecipeParams>,
+    Json(recipe): Json<Value>,
+) -> Result<(SignedCookieJar, PrivateCookieJar, StatusCode), (StatusCode, String)> {
+    if !recipe.get("recipe").is_some_and(Value::is_object) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "`recipe` must be an object".to_string(),
+        ));
+    }
+
+    let value = general_purpose::URL_SAFE.encode(recipe.to_string());
+    let signed = SignedCookieJar::new(keys.current.clone());
+    let private = PrivateCookieJar::new(keys.current.clone());
+
+    Ok(match params.protection {
+        Protection::Signed => (
+            signed.add(keys.cookie(SIGNED_RECIPE, value)),
+            private,
+            StatusCode::OK,
+        ),
+        Protection::Private => (
+            signed,
+            private.add(keys.cookie(PRIVATE_RECIPE, value)),
+            StatusCode::OK,
+        ),
+    })
+}
+
+/// Maximum number of recipes `/7/plan` optimizes at once
+const MAX_RECIPES: usize = 16;
+
+/// Maximum number of partial solutions `/7/plan` explores
+const MAX_NODES: u64 = 100_000;
 
-        // Subtract the ingredients used to make the cookies
-        for (key, value) in recipe.as_object().unwrap() {
-            if let Some(ingredient) = pantry.get_mut(key) {
-                *ingredient = Value::Number(serde_json::Number::from(
-                    ingredient.as_u64().unwrap() - cookies * value.as_u64().unwrap(),
+/// Plan the ingredients needed for a target number of cookies, or the mix of
+/// recipes that makes the most of the pantry
+async fn plan(Json(json): Json<Value>) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    let invalid = |errors: Vec<FieldError>| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": errors })),
+        )
+    };
+
+    let mut errors = Vec::new();
+    let pantry = ingredients(json.get("pantry"), "pantry", &mut errors);
+    let recipes: Vec<(String, Vec<(String, Amount)>)> =
+        match (json.get("recipe"), json.get("recipes")) {
+            (Some(recipe), None) => vec![(
+                "cookie".to_string(),
+                ingredients(Some(recipe), "recipe", &mut errors),
+            )],
+            (None, Some(Value::Object(recipes))) if recipes.len() <= MAX_RECIPES => recipes
+                .iter()
+                .map(|(name, recipe)| {
+                    let path = format!("recipes.{name}");
+                    (name.clone(), ingredients(Some(recipe), &path, &mut errors))
+                })
+                .collect(),
+            (None, Some(Value::Object(_))) => {
+                errors.push(FieldError::new(
+                    "recipes",
+                    format!("must not have more than {MAX_RECIPES} recipes"),
                 ));
+                Vec::new()
+            }
+            (None, Some(_)) => {
+                errors.push(FieldError::new("recipes", "must be an object"));
+                Vec::new()
+            }
+            (Some(_), Some(_)) => {
+                errors.push(FieldError::new("recipe", "must not be used with `recipes`"));
+                Vec::new()
+            }
+            (None, None) => {
+                errors.push(FieldError::new("recipe", "is missing"));
+                Vec::new()
+            }
+        };
+    let names: Vec<&str> = recipes.iter().map(|(name, _)| name.as_str()).collect();
+    let target = json
+        .get("target")
+        .map(|target| per_recipe(target, "target", &names, &mut errors));
+    let weights = json
+        .get("weights")
+        .map(|weights| per_recipe(weights, "weights", &names, &mut errors));
+    let mut measures = Measures::new(&json, &mut errors);
+    if !errors.is_empty() {
+        return Err(invalid(errors));
+    }
+
+    let pantry = measures.normalize(&pantry, "pantry", &mut errors);
+    let recipes: Vec<(String, Vec<(String, Quantity)>)> = recipes
+        .iter()
+        .map(|(name, recipe)| {
+            let path = match json.get("recipes") {
+                Some(_) => format!("recipes.{name}"),
+                None => "recipe".to_string(),
+            };
+            (name.clone(), measures.normalize(recipe, &path, &mut errors))
+        })
+        .collect();
+    if !errors.is_empty() {
+        return Err(invalid(errors));
+    }
+
+    let pantry = Pantry::new(&recipes, &pantry, &measures);
+
+    match target {
+        Some(target) => Ok(Json(pantry.shopping_list(&target))),
+        None => pantry
+            .optimize(&weights.unwrap_or_else(|| vec![1; recipes.len()]))
+            .map(Json)
+            .map_err(invalid),
+    }
+}
+
+/// Parse `value` at `path` as a non-negative integer per recipe
+///
+/// A single number is accepted if there is only one recipe.
+fn per_recipe(value: &Value, path: &str, names: &[&str], errors: &mut Vec<FieldError>) -> Vec<u64> {
+    let mut values = vec![0; names.len()];
+
+    match value {
+        Value::Number(n) if names.len() == 1 => match n.as_u64() {
+            Some(n) => values[0] = n,
+            None => errors.push(FieldError::new(path, "must be a non-negative integer")),
+        },
+        Value::Object(object) => {
+            for (name, value) in object {
+                let field = format!("{path}.{name}");
+                match (names.iter().position(|n| n == name), value.as_u64()) {
+                    (Some(idx), Some(value)) => values[idx] = value,
+                    (None, _) => errors.push(FieldError::new(field, "is not a recipe")),
+                    (_, None) => {
+                        errors.push(FieldError::new(field, "must be a non-negative integer"));
+                    }
+                }
             }
         }
+        _ => errors.push(FieldError::new(
+            path,
+            "must be an object with a value per recipe",
+        )),
+    }
+
+    values
+}
 
-        if cookies == u64::MAX {
-            cookies = 0;
+/// Recipes and a pantry, with the quantities stored per ingredient
+struct Pantry<'a> {
+    recipe_names: Vec<&'a str>,
+    /// Names of all ingredients, of the pantry and the recipes
+    ingredients: Vec<&'a str>,
+    /// Quantity of every ingredient per recipe
+    recipes: Vec<Vec<Quantity>>,
+    /// Available quantity of every ingredient
+    available: Vec<Quantity>,
+    measures: &'a Measures,
+}
+
+impl<'a> Pantry<'a> {
+    fn new(
+        recipes: &'a [(String, Vec<(String, Quantity)>)],
+        pantry: &'a [(String, Quantity)],
+        measures: &'a Measures,
+    ) -> Self {
+        let mut ingredients: Vec<&str> = pantry.iter().map(|(name, _)| name.as_str()).collect();
+        for (_, recipe) in recipes {
+            for (name, _) in recipe {
+                if !ingredients.contains(&name.as_str()) {
+                    ingredients.push(name);
+                }
+            }
         }
 
-        let result = serde_json::json!({
+        let quantities = |list: &[(String, Quantity)]| -> Vec<Quantity> {
+            ingredients
+                .iter()
+                .map(|ingredient| {
+                    list.iter()
+                        .find(|(name, _)| name == ingredient)
+                        .map(|(_, quantity)| *quantity)
+                        .unwrap_or_default()
+                })
+                .collect()
+        };
+
+        Self {
+            recipe_names: recipes.iter().map(|(name, _)| name.as_str()).collect(),
+            recipes: recipes
+                .iter()
+                .map(|(_, recipe)| quantities(recipe))
+                .collect(),
+            available: quantities(pantry),
+            ingredients,
+            measures,
+        }
+    }
+
+    /// Ingredients required and missing to bake `target` cookies per recipe
+    fn shopping_list(&self, target: &[u64]) -> Value {
+        let mut required = serde_json::Map::new();
+        let mut missing = serde_json::Map::new();
+
+        for (idx, ingredient) in self.ingredients.iter().enumerate() {
+            let total = self
+                .recipes
+                .iter()
+                .zip(target)
+                .map(|(recipe, &count)| recipe[idx].0.saturating_mul(count.into()))
+                .fold(0u128, u128::saturating_add);
+            if total == 0 {
+                continue;
+            }
+
+            let total = Quantity(total);
+            let lacking = total.checked_sub(self.available[idx]).unwrap_or_default();
+            let measures = self.measures;
+            required.insert(ingredient.to_string(), measures.to_json(ingredient, total));
+            missing.insert(
+                ingredient.to_string(),
+                measures.to_json(ingredient, lacking),
+            );
+        }
+
+        json!({
+            "required": required,
+            "missing": missing,
+        })
+    }
+
+    /// Most cookies of recipe `idx` that can be baked from `available`
+    fn max_cookies(&self, idx: usize, available: &[Quantity]) -> u128 {
+        self.recipes[idx]
+            .iter()
+            .zip(available)
+            .filter(|(quantity, _)| quantity.0 > 0)
+            .map(|(quantity, available)| available.div_floor(*quantity))
+            .min()
+            .unwrap_or(u128::MAX)
+    }
+
+    /// Upper bound of the value of the cookies of recipes `from..` that can be
+    /// baked from `available`
+    ///
+    /// Every recipe is bounded by the pantry on its own, and every ingredient
+    /// by the recipe that makes the most of it.
+    fn bound(&self, from: usize, available: &[Quantity], weights: &[u64]) -> u128 {
+        let alone: Vec<u128> = (from..self.recipes.len())
+            .map(|idx| {
+                self.max_cookies(idx, available)
+                    .saturating_mul(weights[idx].into())
+            })
+            .collect();
+        let mut bound = alone.iter().fold(0u128, |acc, &v| acc.saturating_add(v));
+
+        for (ingredient, available) in available.iter().enumerate() {
+            // Recipes without the ingredient are bounded on their own, the
+            // others together by using the whole ingredient for the recipe
+            // that makes the most of it
+            let mut without = 0u128;
+            let mut with = 0u128;
+            for (idx, value) in (from..self.recipes.len()).zip(&alone) {
+                let quantity = self.recipes[idx][ingredient].0;
+                if quantity == 0 {
+                    without = without.saturating_add(*value);
+                } else {
+                    with = with.max(
+                        available
+                            .0
+                            .saturating_mul(weights[idx].into())
+                            .checked_div(quantity)
+                            .unwrap_or(u128::MAX),
+                    );
+                }
+            }
+            let by_ingredient = without.saturating_add(with);
+            bound = bound.min(by_ingredient);
+        }
+
+        bound
+    }
+
+    /// Find the number of cookies per recipe with the highest total weight
+    ///
+    /// This is a small integer program, solved with branch and bound. If the
+    /// search takes too long, the best mix found so far is returned and marked
+    /// as not optimal.
+    fn optimize(&self, weights: &[u64]) -> Result<Value, Vec<FieldError>> {
+        let unbounded: Vec<FieldError> = (0..self.recipes.len())
+            .filter(|&idx| weights[idx] > 0 && self.max_cookies(idx, &self.available) == u128::MAX)
+            .map(|idx| {
+                FieldError::new(
+                    format!("recipes.{}", self.recipe_names[idx]),
+                    "needs no ingredients, so there is no limit to the cookies",
+                )
+            })
+            .collect();
+        if !unbounded.is_empty() {
+            return Err(unbounded);
+        }
+
+        let mut search = Search {
+            pantry: self,
+            weights,
+            counts: vec![0; self.recipes.len()],
+            best: vec![0; self.recipes.len()],
+            best_value: 0,
+            nodes: 0,
+        };
+        search.branch(0, self.available.clone(), 0);
+        let optimal = search.nodes <= MAX_NODES;
+
+        let mut available = self.available.clone();
+        for (recipe, &count) in self.recipes.iter().zip(&search.best) {
+            for (available, quantity) in available.iter_mut().zip(recipe) {
+                *available = quantity
+                    .checked_mul(count)
+                    .and_then(|used| available.checked_sub(used))
+                    .expect("cookies are limited by the pantry");
+            }
+        }
+
+        let too_many = |_| vec![FieldError::new("pantry", "makes too many cookies to count")];
+        let cookies = self
+            .recipe_names
+            .iter()
+            .zip(&search.best)
+            .map(|(name, &count)| Ok((name.to_string(), json!(u64::try_from(count)?))))
+            .collect::<Result<serde_json::Map<_, _>, _>>()
+            .map_err(too_many)?;
+        let total = u64::try_from(
+            search
+                .best
+                .iter()
+                .fold(0u128, |acc, &c| acc.saturating_add(c)),
+        )
+        .map_err(too_many)?;
+        let value = u64::try_from(search.best_value).map_err(too_many)?;
+        let pantry: serde_json::Map<String, Value> = self
+            .ingredients
+            .iter()
+            .zip(available)
+            .filter(|(_, quantity)| quantity.0 > 0)
+            .map(|(name, quantity)| (name.to_string(), self.measures.to_json(name, quantity)))
+            .collect();
+
+        Ok(json!({
             "cookies": cookies,
+            "total": total,
+            "value": value,
+            "optimal": optimal,
             "pantry": pantry,
-        })
-        .to_string();
+        }))
+    }
+}
+
+/// State of the branch and bound search of [`Pantry::optimize`]
+struct Search<'a> {
+    pantry: &'a Pantry<'a>,
+    weights: &'a [u64],
+    counts: Vec<u128>,
+    best: Vec<u128>,
+    best_value: u128,
+    nodes: u64,
+}
+
+impl Search<'_> {
+    /// Try all counts of recipe `idx` with the `available` ingredients, most
+    /// cookies first
+    fn branch(&mut self, idx: usize, available: Vec<Quantity>, value: u128) {
+        self.nodes += 1;
+        if self.nodes > MAX_NODES {
+            return;
+        }
+
+        if idx == self.counts.len() {
+            if value > self.best_value {
+                self.best_value = value;
+                self.best.clone_from(&self.counts);
+            }
+            return;
+        }
+
+        let weight = u128::from(self.weights[idx]);
+        let rest = self.pantry.bound(idx + 1, &available, self.weights);
+        let max = if weight == 0 {
+            0
+        } else {
+            self.pantry.max_cookies(idx, &available)
+        };
+
+        for count in (0..=max).rev() {
+            // Fewer cookies of this recipe only leave more for the others,
+            // which are bounded by `rest` with all of the ingredients.
+            let value = value.saturating_add(count.saturating_mul(weight));
+            if value.saturating_add(rest) <= self.best_value {
+                break;
+            }
 
-        Ok(result)
-    } else {
-        Err(StatusCode::NOT_FOUND)
+            let available = available
+                .iter()
+                .zip(&self.pantry.recipes[idx])
+                .map(|(available, quantity)| {
+                    quantity
+                        .checked_mul(count)
+                        .and_then(|used| available.checked_sub(used))
+                        .expect("count is limited by the pantry")
+                })
+                .collect();
+
+            self.counts[idx] = count;
+            self.branch(idx + 1, available, value);
+            self.counts[idx] = 0;
+
+            if self.nodes > MAX_NODES {
+                return;
+            }
+        }
     }
 }
 
@@ -308,4 +1617,503 @@ mod test {
 
         assert_eq!(body_json, expected_json);
     }
+
+    /// Store `recipe` with `protection` and return the cookie to send back
+    async fn issue(app: Router, recipe: &Value, protection: &str) -> String {
+        issue_cookie(app, recipe, protection)
+            .await
+            .stripped()
+            .to_string()
+    }
+
+    /// Store `recipe` with `protection` and return the cookie set
+    async fn issue_cookie(app: Router, recipe: &Value, protection: &str) -> Cookie<'static> {
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/json")
+            .uri(format!("/7/recipe?protection={protection}"))
+            .body(Body::from(recipe.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookie = response
+            .headers()
+            .get("Set-Cookie")
+            .expect("Missing cookie");
+        Cookie::parse(cookie.to_str().unwrap().to_string()).unwrap()
+    }
+
+    fn set_cookie<B>(response: &axum::http::Response<B>) -> Option<String> {
+        response.headers().get("Set-Cookie").map(|cookie| {
+            cookie
+                .to_str()
+                .unwrap()
+                .split(';')
+                .next()
+                .unwrap()
+                .to_string()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_recipe() {
+        let recipe =
+            json!({"recipe": {"flour": 95, "sugar": 50}, "pantry": {"flour": 385, "sugar": 507}});
+
+        for protection in ["signed", "private"] {
+            let app = router(CookieKeys {
+                current: Key::from(&[1; 64]),
+                previous: Vec::new(),
+                secure: false,
+            });
+
+            let cookie = issue_cookie(app.clone(), &recipe, protection).await;
+            assert_eq!(cookie.name(), format!("{protection}_recipe"));
+            assert_eq!(cookie.path(), Some("/7"));
+            assert_eq!(cookie.http_only(), Some(true));
+            assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+            assert_eq!(cookie.secure(), None);
+            let cookie = cookie.stripped().to_string();
+
+            let req = Request::builder()
+                .method(Method::GET)
+                .header("Cookie", &cookie)
+                .uri("/7/bake")
+                .body(Body::from(()))
+                .unwrap();
+
+            let response = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(set_cookie(&response), None);
+
+            let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .expect("Failed to read response body");
+
+            let body_json: Value =
+                serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+
+            let expected_json = json!({"cookies": 4, "pantry": {"flour": 5, "sugar": 307}});
+
+            assert_eq!(body_json, expected_json, "{protection}");
+
+            // Replace the last character of the cookie
+            let mut tampered = cookie.clone();
+            let last = tampered.pop().unwrap();
+            tampered.push(if last == 'A' { 'B' } else { 'A' });
+
+            let req = Request::builder()
+                .method(Method::GET)
+                .header("Cookie", &tampered)
+                .uri("/7/decode")
+                .body(Body::from(()))
+                .unwrap();
+
+            let response = app.oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{protection}");
+
+            let app = router(CookieKeys {
+                current: Key::from(&[1; 64]),
+                previous: Vec::new(),
+                secure: true,
+            });
+            let cookie = issue_cookie(app, &recipe, protection).await;
+            assert_eq!(cookie.secure(), Some(true));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation() {
+        let recipe = json!({"recipe": {"flour": 100}, "pantry": {"flour": 250}});
+        let old_key = Key::from(&[1; 64]);
+        let new_key = Key::from(&[2; 64]);
+
+        for protection in ["signed", "private"] {
+            let app = router(CookieKeys {
+                current: old_key.clone(),
+                previous: Vec::new(),
+                secure: false,
+            });
+            let cookie = issue(app, &recipe, protection).await;
+
+            // The old key is still accepted, and the cookie is replaced
+            let app = router(CookieKeys {
+                current: new_key.clone(),
+                previous: vec![old_key.clone()],
+                secure: false,
+            });
+
+            let req = Request::builder()
+                .method(Method::GET)
+                .header("Cookie", &cookie)
+                .uri("/7/decode")
+                .body(Body::from(()))
+                .unwrap();
+
+            let response = app.oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let rotated = set_cookie(&response).expect("Cookie was not rotated");
+            assert_ne!(rotated, cookie);
+
+            // Once the old key is retired, only the rotated cookie is accepted
+            let app = router(CookieKeys {
+                current: new_key.clone(),
+                previous: Vec::new(),
+                secure: false,
+            });
+
+            for (cookie, status) in [(cookie, StatusCode::BAD_REQUEST), (rotated, StatusCode::OK)] {
+                let req = Request::builder()
+                    .method(Method::GET)
+                    .header("Cookie", &cookie)
+                    .uri("/7/bake")
+                    .body(Body::from(()))
+                    .unwrap();
+
+                let response = app.clone().oneshot(req).await.unwrap();
+                assert_eq!(response.status(), status, "{protection}");
+            }
+        }
+    }
+
+    /// Bake `recipe` encoded with `engine` and return the response
+    async fn bake_with(recipe: &Value, engine: &GeneralPurpose) -> (StatusCode, Value) {
+        let app = get_routes();
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .header(
+                "Cookie",
+                format!("recipe={}", engine.encode(recipe.to_string())),
+            )
+            .uri("/7/bake")
+            .body(Body::from(()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+        let status = response.status();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+
+        (status, body_json)
+    }
+
+    #[tokio::test]
+    async fn test_decimal() {
+        // `?` and `>` encode to `/` and `+` in the standard alphabet
+        let recipe = json!({
+            "recipe": {"flour": 0.5, "sugar": 1, "salt?>": 0.001, "water": 0},
+            "pantry": {"flour": 2.25, "sugar": 1e1, "salt?>": 1, "chocolate": 3.5}
+        });
+
+        for engine in [
+            &general_purpose::STANDARD,
+            &general_purpose::STANDARD_NO_PAD,
+            &general_purpose::URL_SAFE,
+            &general_purpose::URL_SAFE_NO_PAD,
+        ] {
+            let (status, body_json) = bake_with(&recipe, engine).await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(
+                body_json,
+                json!({
+                    "cookies": 4,
+                    "pantry": {"flour": 0.25, "sugar": 6, "salt?>": 0.996, "chocolate": 3.5}
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_missing_ingredient() {
+        let recipe = json!({
+            "recipe": {"flour": 10, "slime": 1},
+            "pantry": {"flour": 100}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body_json, json!({"cookies": 0, "pantry": {"flour": 100}}));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_quantities() {
+        let recipe = json!({
+            "recipe": {"flour": -1, "sugar": "a lot", "salt": 0.0000000001},
+            "pantry": {"flour": 1e300}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [
+                {"field": "recipe.flour", "message": "must not be negative"},
+                {"field": "recipe.salt", "message": "must not have more than 9 decimal places"},
+                {"field": "recipe.sugar", "message": "must be a number"},
+                {"field": "pantry.flour", "message": "is too large"}
+            ]})
+        );
+
+        let (status, body_json) =
+            bake_with(&json!({"recipe": []}), &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [
+                {"field": "recipe", "message": "must be an object"},
+                {"field": "pantry", "message": "is missing"}
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_units() {
+        let recipe = json!({
+            "recipe": {"flour": "1 cup", "butter": "113 g", "eggs": 1},
+            "pantry": {"flour": "1 kg", "butter": "1 lb", "eggs": 12},
+            "densities": {"flour": "125 g/cup"}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "cookies": 4,
+                "pantry": {"flour": "0.5 kg", "butter": "0.003510575 lb", "eggs": 8}
+            })
+        );
+
+        // Default densities and mixed notations
+        let recipe = json!({
+            "recipe": {"milk": "250ml", "sugar": "2 tbsp", "salt": "1.5e3 mg"},
+            "pantry": {"milk": "1.03 kg", "sugar": "100 g", "salt": "10 G"}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "cookies": 4,
+                "pantry": {"milk": "0 kg", "sugar": "0.04147008 g", "salt": "4 g"}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_units() {
+        let recipe = json!({
+            "recipe": {"flour": "1 cup", "eggs": "2 pc", "slime": "1 cup", "salt": 1},
+            "pantry": {"flour": 500, "eggs": "1 kg", "slime": "1 kg", "salt": "5 parsecs"},
+            "densities": {"flour": "2 cup/g", "milk": 0}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [
+                {"field": "pantry.salt", "message": "has an unknown unit `parsecs`"},
+                {"field": "densities.flour", "message": "must be a mass per volume like `120 g/cup`"},
+                {"field": "densities.milk", "message": "must be greater than zero"}
+            ]})
+        );
+
+        let recipe = json!({
+            "recipe": {"flour": "1 cup", "eggs": "2 pc", "slime": "1 cup"},
+            "pantry": {"flour": 500, "eggs": "1 kg", "slime": "1 kg"}
+        });
+
+        let (status, body_json) = bake_with(&recipe, &general_purpose::URL_SAFE).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [
+                {"field": "recipe.eggs", "message": "can't convert pc to kg"},
+                {"field": "recipe.flour", "message": "must be given with or without a unit everywhere"},
+                {"field": "recipe.slime", "message": "needs a density to convert cup to kg"}
+            ]})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_units() {
+        let (status, body_json) = plan_with(json!({
+            "recipe": {"flour": "95 g", "sugar": "50 g"},
+            "pantry": {"flour": "0.5 kg"},
+            "target": 10
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "required": {"flour": "0.95 kg", "sugar": "500 g"},
+                "missing": {"flour": "0.45 kg", "sugar": "500 g"}
+            })
+        );
+    }
+
+    async fn plan_with(input: Value) -> (StatusCode, Value) {
+        let app = get_routes();
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .header("Content-Type", "application/json")
+            .uri("/7/plan")
+            .body(Body::from(input.to_string()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+        let status = response.status();
+
+        let body_bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+
+        let body_json: Value =
+            serde_json::from_slice(&body_bytes).expect("Failed to convert body to json");
+
+        (status, body_json)
+    }
+
+    #[tokio::test]
+    async fn test_plan_target() {
+        let (status, body_json) = plan_with(json!({
+            "recipe": {"flour": 95, "sugar": 50, "butter": 30.5},
+            "pantry": {"flour": 385, "sugar": 507, "chocolate": 20},
+            "target": 10
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "required": {"flour": 950, "sugar": 500, "butter": 305},
+                "missing": {"flour": 565, "sugar": 0, "butter": 305}
+            })
+        );
+
+        let (status, body_json) = plan_with(json!({
+            "recipes": {"a": {"flour": 2}, "b": {"flour": 3, "sugar": 1}},
+            "pantry": {"flour": 10},
+            "target": {"a": 2, "b": 3}
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "required": {"flour": 13, "sugar": 3},
+                "missing": {"flour": 3, "sugar": 3}
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_optimize() {
+        let recipes = json!({
+            "shortbread": {"flour": 3, "sugar": 1},
+            "meringue": {"flour": 1, "sugar": 3}
+        });
+
+        let (status, body_json) = plan_with(json!({
+            "recipes": recipes,
+            "pantry": {"flour": 10, "sugar": 10, "salt": 1}
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body_json["total"], 4);
+        assert_eq!(body_json["optimal"], true);
+
+        let (status, body_json) = plan_with(json!({
+            "recipes": recipes,
+            "pantry": {"flour": 10, "sugar": 10, "salt": 1},
+            "weights": {"shortbread": 1, "meringue": 3}
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body_json,
+            json!({
+                "cookies": {"meringue": 3, "shortbread": 1},
+                "total": 4,
+                "value": 10,
+                "optimal": true,
+                "pantry": {"flour": 4, "salt": 1}
+            })
+        );
+
+        // The bound must not depend on the order of the recipes
+        for recipes in [
+            json!({"a": {"flour": 2}, "b": {"sugar": 1}, "c": {"flour": 1}}),
+            json!({"a": {"flour": 1}, "b": {"sugar": 1}, "c": {"flour": 2}}),
+        ] {
+            let (status, body_json) = plan_with(json!({
+                "recipes": recipes,
+                "pantry": {"flour": 10, "sugar": 10}
+            }))
+            .await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body_json["total"], 20);
+            assert_eq!(body_json["optimal"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_plan_invalid() {
+        let (status, body_json) = plan_with(json!({
+            "recipes": {"air": {"flour": 0}, "bread": {"flour": 1}},
+            "pantry": {"flour": 10}
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [{
+                "field": "recipes.air",
+                "message": "needs no ingredients, so there is no limit to the cookies"
+            }]})
+        );
+
+        let (status, body_json) = plan_with(json!({
+            "recipes": {"bread": {"flour": 1}},
+            "pantry": {"flour": 10},
+            "target": {"cake": 1, "bread": -1}
+        }))
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body_json,
+            json!({"errors": [
+                {"field": "target.bread", "message": "must be a non-negative integer"},
+                {"field": "target.cake", "message": "is not a recipe"}
+            ]})
+        );
+    }
 }
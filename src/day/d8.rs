@@ -50,62 +50,1165 @@
 //!
 //! 84.10707461325713
 //! ```
-use axum::{extract::Path, http::StatusCode, routing::get, Router};
-use serde::Deserialize;
+//!
+//! # Extension: Other chimneys
+//!
+//! `/8/drop/<pokedex_number>` takes query parameters to drop Pokémon
+//! elsewhere:
+//!
+//! * `height` of the drop in m, 10 by default
+//! * `gravity` in m/s², or the `latitude` or a named `location` (`north-pole`,
+//!   `south-pole`, `equator`, `rovaniemi` or `greenwich`) to calculate the
+//!   gravity at sea level there. Santa's 9.825 m/s² by default.
+//! * `drag_coefficient` and cross-section `area` in m² for air resistance,
+//!   with an optional `air_density` in kg/m³, 1.225 by default. Drag too
+//!   large to calculate the fall with is a `422 Unprocessable Entity`.
+//!
+//! Clients accepting `application/json` get a breakdown of the impact:
+//!
+//! ```not_rust
+//! curl 'http://localhost:8000/8/drop/25?height=20&location=equator' \
+//!   -H 'Accept: application/json'
+//!
+//! {
+//!   "mass": 6.0,
+//!   "height": 20.0,
+//!   "gravity": 9.7803253359,
+//!   "velocity": 19.779105476133143,
+//!   "time": 2.0223361490370135,
+//!   "momentum": 118.67463285679887,
+//!   "kinetic_energy": 1173.6390403080004
+//! }
+//! ```
+//!
+//! # Extension: Pokémon by name
+//!
+//! Pokémon can also be looked up by name, like `/8/weight/pikachu`.
+//! `/8/pokemon/<pokemon>` answers with everything known about a Pokémon, by
+//! pokédex number or name, in meters and kilograms.
+//!
+//! ```not_rust
+//! curl http://localhost:8000/8/pokemon/pikachu
+//!
+//! {
+//!   "id": 25,
+//!   "name": "pikachu",
+//!   "height": 0.4,
+//!   "weight": 6.0,
+//!   "types": ["electric"],
+//!   "stats": {
+//!     "attack": 55,
+//!     "defense": 40,
+//!     "hp": 35,
+//!     "special-attack": 50,
+//!     "special-defense": 50,
+//!     "speed": 90
+//!   }
+//! }
+//! ```
+//!
+//! # Extension: Loading the sleigh
+//!
+//! POST `/8/sleigh` takes the Pokémon to load by pokédex number, with an
+//! optional count, and the maximal payload of the sleigh in kilograms. It
+//! answers with the total weight and whether it fits. If it doesn't, the
+//! heaviest load of the Pokémon that does fit is added, for payloads of up to
+//! 100000 kg and as long as the search doesn't take too long.
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/8/sleigh \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{"pokemon":[{"pokedex":25,"count":3},{"pokedex":143}],"max_payload":100}'
+//!
+//! {
+//!   "total": 478.0,
+//!   "max_payload": 100.0,
+//!   "fits": false,
+//!   "load": {"total": 18.0, "pokemon": [{"pokedex": 25, "count": 3}]}
+//! }
+//! ```
+//!
+//! # Extension: Weighing many Pokémon
+//!
+//! POST `/8/weights` takes a list of pokédex numbers or names and answers
+//! with the weight of every Pokémon in kilograms, in the same order. The
+//! Pokémon are looked up concurrently, and lookups of the same Pokémon by
+//! concurrent requests are shared. Pokémon that can't be looked up, or that
+//! aren't a pokédex number or a name, get an error and its HTTP status
+//! instead of a weight.
+//!
+//! ```not_rust
+//! curl -X POST http://localhost:8000/8/weights \
+//!   -H 'Content-Type: application/json' \
+//!   -d '{"pokemon":[25,"Snorlax",9999]}'
+//!
+//! [
+//!   {"pokemon":25,"weight":6.0},
+//!   {"pokemon":"snorlax","weight":460.0},
+//!   {"pokemon":9999,"error":"there is no Pokémon 9999","status":404}
+//! ]
+//! ```
+//!
+//! # Extension: Pokémon sources
+//!
+//! The Pokémon are fetched from [PokéAPI](https://pokeapi.co), or with
+//! `POKEMON_SOURCE=local` from a small dataset bundled with the server. The
+//! base URL of PokéAPI can be changed with `POKEAPI_URL`.
+//!
+//! Pokémon are cached for a day, in memory and in the `pokemon_cache` table,
+//! so they survive restarts. Unknown Pokémon are answered with `404 Not
+//! Found`, failures of PokéAPI with `502 Bad Gateway`.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{
+    future::{BoxFuture, Shared},
+    FutureExt, StreamExt, TryStreamExt,
+};
+use sea_query::{Expr, Iden, OnConflict, PostgresQueryBuilder};
+use sea_query_binder::SqlxBinder;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// Default base URL of PokéAPI
+const POKEAPI_URL: &str = "https://pokeapi.co/api/v2";
+
+/// How long fetched Pokémon are cached
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Pokémon bundled with the server, in the format of PokéAPI
+static BUNDLED: LazyLock<Vec<Pokemon>> = LazyLock::new(|| {
+    serde_json::from_str(include_str!("../../assets/pokemon.json"))
+        .expect("bundled Pokémon are valid")
+});
 
 /// Get Day 8 routes
 ///
 /// * `/8/weight/<pokedex_number>`
 /// * `/8/drop/<pokedex_number>`
-pub fn get_routes() -> Router {
+/// * `/8/pokemon/<pokemon>`
+/// * `/8/sleigh`
+/// * `/8/weights`
+pub fn get_routes(pool: PgPool) -> Router {
+    let source: Arc<dyn PokemonSource> = match std::env::var("POKEMON_SOURCE").as_deref() {
+        Ok("local") => Arc::new(LocalSource::bundled()),
+        _ => Arc::new(HttpSource::from_env()),
+    };
+
+    router(Arc::new(
+        CachedSource::new(source, CACHE_TTL).with_pool(pool),
+    ))
+}
+
+fn router(source: Arc<dyn PokemonSource>) -> Router {
     Router::new()
         .route("/8/weight/:pokedex", get(pokedex))
         .route("/8/drop/:pokedex", get(drop))
+        .route("/8/pokemon/:pokemon", get(pokemon))
+        .route("/8/sleigh", post(sleigh))
+        .route("/8/weights", post(weights))
+        .with_state(source)
 }
 
-#[derive(Deserialize)]
+/// A Pokémon, with the fields of PokéAPI we need
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
 struct Pokemon {
+    id: u32,
+    name: String,
+    /// Height in decimeters
+    #[serde(default)]
+    height: u32,
+    /// Weight in hectograms
     weight: u32,
+    #[serde(default)]
+    stats: Vec<PokemonStat>,
+    #[serde(default)]
+    types: Vec<PokemonType>,
 }
 
-async fn pokedex(Path(pokedex): Path<u32>) -> Result<String, StatusCode> {
-    let uri = format!("https://pokeapi.co/api/v2/pokemon/{pokedex}");
-    let pokemon = reqwest::get(&uri)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .json::<Pokemon>()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// A base stat of a Pokémon, like `hp` or `speed`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct PokemonStat {
+    base_stat: u32,
+    stat: NamedResource,
+}
+
+/// A type of a Pokémon, in the order of its `slot`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct PokemonType {
+    slot: u32,
+    #[serde(rename = "type")]
+    kind: NamedResource,
+}
+
+/// Reference to another resource of PokéAPI, of which we only need the name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct NamedResource {
+    name: String,
+}
+
+/// A Pokémon as answered by `/8/pokemon/<pokemon>`, in SI units
+#[derive(Serialize)]
+struct PokemonInfo {
+    id: u32,
+    name: String,
+    /// Height in m
+    height: f64,
+    /// Weight in kg
+    weight: f64,
+    types: Vec<String>,
+    /// Base stats by name
+    stats: BTreeMap<String, u32>,
+}
+
+impl From<Pokemon> for PokemonInfo {
+    fn from(pokemon: Pokemon) -> Self {
+        let mut types = pokemon.types;
+        types.sort_by_key(|t| t.slot);
+
+        Self {
+            id: pokemon.id,
+            name: pokemon.name,
+            height: f64::from(pokemon.height) / 10.,
+            weight: f64::from(pokemon.weight) / 10.,
+            types: types.into_iter().map(|t| t.kind.name).collect(),
+            stats: pokemon
+                .stats
+                .into_iter()
+                .map(|s| (s.stat.name, s.base_stat))
+                .collect(),
+        }
+    }
+}
+
+/// How a Pokémon is looked up, by pokédex number or by name
+///
+/// Names are case insensitive, and names made of digits are pokédex numbers.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged, try_from = "RawPokemonKey")]
+enum PokemonKey {
+    Id(u32),
+    Name(String),
+}
+
+/// A Pokémon as given, which may not be a valid [`PokemonKey`]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+enum RawPokemonKey {
+    Id(u32),
+    Name(String),
+    Other(serde_json::Value),
+}
+
+impl From<PokemonKey> for RawPokemonKey {
+    fn from(key: PokemonKey) -> Self {
+        match key {
+            PokemonKey::Id(id) => Self::Id(id),
+            PokemonKey::Name(name) => Self::Name(name),
+        }
+    }
+}
+
+impl TryFrom<RawPokemonKey> for PokemonKey {
+    type Error = String;
+
+    fn try_from(raw: RawPokemonKey) -> Result<Self, Self::Error> {
+        match raw {
+            RawPokemonKey::Id(id) => Ok(Self::Id(id)),
+            RawPokemonKey::Name(name) => name.parse(),
+            RawPokemonKey::Other(_) => {
+                Err("a Pokémon needs a pokédex number or a name".to_string())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for PokemonKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("a Pokémon needs a pokédex number or a name".to_string());
+        }
+        if s.bytes().all(|b| b.is_ascii_digit()) {
+            return s
+                .parse()
+                .map(Self::Id)
+                .map_err(|_| format!("there is no pokédex number {s}"));
+        }
+
+        Ok(Self::Name(s.to_lowercase()))
+    }
+}
+
+impl std::fmt::Display for PokemonKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Id(id) => write!(f, "{id}"),
+            Self::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl PokemonKey {
+    fn matches(&self, pokemon: &Pokemon) -> bool {
+        match self {
+            Self::Id(id) => pokemon.id == *id,
+            Self::Name(name) => pokemon.name == *name,
+        }
+    }
+}
+
+/// Why a Pokémon couldn't be looked up
+#[derive(thiserror::Error, Clone, Debug)]
+enum SourceError {
+    #[error("there is no Pokémon {0}")]
+    NotFound(PokemonKey),
+    #[error("the Pokémon source failed: {0}")]
+    Upstream(String),
+}
+
+impl SourceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            SourceError::NotFound(_) => StatusCode::NOT_FOUND,
+            SourceError::Upstream(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl IntoResponse for SourceError {
+    fn into_response(self) -> Response {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+/// Where Pokémon are looked up
+trait PokemonSource: Send + Sync {
+    /// Look up a Pokémon by its pokédex number or name
+    fn pokemon(&self, key: &PokemonKey) -> BoxFuture<'_, Result<Pokemon, SourceError>>;
+}
+
+/// Pokémon from PokéAPI
+struct HttpSource {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpSource {
+    fn new(client: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Use the base URL in `POKEAPI_URL`, or the public PokéAPI
+    fn from_env() -> Self {
+        let base_url = std::env::var("POKEAPI_URL").unwrap_or_else(|_| POKEAPI_URL.to_string());
+        Self::new(reqwest::Client::new(), base_url)
+    }
+}
+
+impl PokemonSource for HttpSource {
+    fn pokemon(&self, key: &PokemonKey) -> BoxFuture<'_, Result<Pokemon, SourceError>> {
+        let key = key.clone();
+        Box::pin(async move {
+            let upstream = |e: reqwest::Error| SourceError::Upstream(e.to_string());
+            let mut url = reqwest::Url::parse(&self.base_url)
+                .map_err(|e| SourceError::Upstream(e.to_string()))?;
+            url.path_segments_mut()
+                .map_err(|_| SourceError::Upstream("invalid base URL".to_string()))?
+                .extend(["pokemon", &key.to_string()]);
+            let response = self.client.get(url).send().await.map_err(upstream)?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Err(SourceError::NotFound(key));
+            }
+
+            response
+                .error_for_status()
+                .map_err(upstream)?
+                .json::<Pokemon>()
+                .await
+                .map_err(upstream)
+        })
+    }
+}
+
+/// Pokémon from a dataset in memory
+struct LocalSource {
+    pokemon: Vec<Pokemon>,
+}
+
+impl LocalSource {
+    fn new(pokemon: impl IntoIterator<Item = Pokemon>) -> Self {
+        Self {
+            pokemon: pokemon.into_iter().collect(),
+        }
+    }
+
+    /// The Pokémon bundled with the server
+    fn bundled() -> Self {
+        Self::new(BUNDLED.iter().cloned())
+    }
+}
+
+impl PokemonSource for LocalSource {
+    fn pokemon(&self, key: &PokemonKey) -> BoxFuture<'_, Result<Pokemon, SourceError>> {
+        let pokemon = self.pokemon.iter().find(|p| key.matches(p)).cloned();
+        let key = key.clone();
+        Box::pin(async move { pokemon.ok_or(SourceError::NotFound(key)) })
+    }
+}
+
+#[derive(Iden)]
+enum PokemonCache {
+    Table,
+    Id,
+    Name,
+    Data,
+    FetchedAt,
+}
+
+#[derive(FromRow)]
+struct CachedPokemon(String);
+
+/// A lookup that can be awaited by every request for the same Pokémon
+type SharedLookup = Shared<BoxFuture<'static, Result<Pokemon, SourceError>>>;
+
+/// Cache of Pokémon from another source
+///
+/// Pokémon are kept in memory, and in the `pokemon_cache` table if there is a
+/// database. The database is only a cache, so its errors are logged and
+/// otherwise ignored. Concurrent lookups of the same Pokémon share a single
+/// lookup of the source.
+struct CachedSource {
+    source: Arc<dyn PokemonSource>,
+    ttl: Duration,
+    memory: Mutex<HashMap<u32, (Instant, Pokemon)>>,
+    /// Pokédex numbers of the Pokémon in memory by name
+    names: Mutex<HashMap<String, u32>>,
+    in_flight: Mutex<HashMap<PokemonKey, SharedLookup>>,
+    pool: Option<PgPool>,
+}
+
+impl CachedSource {
+    fn new(source: Arc<dyn PokemonSource>, ttl: Duration) -> Self {
+        Self {
+            source,
+            ttl,
+            memory: Mutex::new(HashMap::new()),
+            names: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            pool: None,
+        }
+    }
+
+    /// Also cache Pokémon in the database
+    fn with_pool(mut self, pool: PgPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    fn in_memory(&self, key: &PokemonKey) -> Option<Pokemon> {
+        let id = match key {
+            PokemonKey::Id(id) => *id,
+            PokemonKey::Name(name) => *self.names.lock().unwrap().get(name)?,
+        };
+
+        let mut memory = self.memory.lock().unwrap();
+        match memory.get(&id) {
+            Some((fetched, pokemon)) if fetched.elapsed() < self.ttl => Some(pokemon.clone()),
+            Some(_) => {
+                memory.remove(&id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, pokemon: &Pokemon) {
+        self.names
+            .lock()
+            .unwrap()
+            .insert(pokemon.name.clone(), pokemon.id);
+        self.memory
+            .lock()
+            .unwrap()
+            .insert(pokemon.id, (Instant::now(), pokemon.clone()));
+    }
+
+    async fn in_database(
+        pool: &PgPool,
+        key: &PokemonKey,
+        ttl: Duration,
+    ) -> Result<Option<Pokemon>, sqlx::Error> {
+        let oldest = unix_time().saturating_sub(ttl.as_secs());
+        let (sql, values) = sea_query::Query::select()
+            .column(PokemonCache::Data)
+            .from(PokemonCache::Table)
+            .and_where(match key {
+                PokemonKey::Id(id) => Expr::col(PokemonCache::Id).eq(i64::from(*id)),
+                PokemonKey::Name(name) => Expr::col(PokemonCache::Name).eq(name.as_str()),
+            })
+            .and_where(Expr::col(PokemonCache::FetchedAt).gte(oldest as i64))
+            .build_sqlx(PostgresQueryBuilder);
+
+        let row = sqlx::query_as_with::<_, CachedPokemon, _>(&sql, values)
+            .fetch_optional(pool)
+            .await?;
+
+        Ok(row.and_then(|row| serde_json::from_str(&row.0).ok()))
+    }
+
+    async fn store_in_database(pool: &PgPool, pokemon: &Pokemon) -> Result<(), sqlx::Error> {
+        let data = serde_json::to_string(pokemon).expect("Pokémon can be serialized");
+        let (sql, values) = sea_query::Query::insert()
+            .into_table(PokemonCache::Table)
+            .columns([
+                PokemonCache::Id,
+                PokemonCache::Name,
+                PokemonCache::Data,
+                PokemonCache::FetchedAt,
+            ])
+            .values_panic([
+                i64::from(pokemon.id).into(),
+                pokemon.name.clone().into(),
+                data.into(),
+                (unix_time() as i64).into(),
+            ])
+            .on_conflict(
+                OnConflict::column(PokemonCache::Id)
+                    .update_columns([
+                        PokemonCache::Name,
+                        PokemonCache::Data,
+                        PokemonCache::FetchedAt,
+                    ])
+                    .to_owned(),
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        sqlx::query_with(&sql, values).execute(pool).await?;
+
+        Ok(())
+    }
+
+    /// Look up a Pokémon in the database or the source
+    fn fetch(&self, key: PokemonKey) -> SharedLookup {
+        let source = self.source.clone();
+        let pool = self.pool.clone();
+        let ttl = self.ttl;
+
+        async move {
+            if let Some(pool) = &pool {
+                match Self::in_database(pool, &key, ttl).await {
+                    Ok(Some(pokemon)) => return Ok(pokemon),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("failed to read Pokémon {key} from the cache: {e}"),
+                }
+            }
+
+            let pokemon = source.pokemon(&key).await?;
+            if let Some(pool) = &pool {
+                if let Err(e) = Self::store_in_database(pool, &pokemon).await {
+                    tracing::warn!("failed to cache Pokémon {key}: {e}");
+                }
+            }
+
+            Ok(pokemon)
+        }
+        .boxed()
+        .shared()
+    }
+
+    async fn lookup(&self, key: &PokemonKey) -> Result<Pokemon, SourceError> {
+        if let Some(pokemon) = self.in_memory(key) {
+            return Ok(pokemon);
+        }
+
+        let lookup = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| self.fetch(key.clone()))
+            .clone();
+        let result = lookup.clone().await;
+        {
+            // A later lookup may have replaced ours after it failed
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight.get(key).is_some_and(|l| l.ptr_eq(&lookup)) {
+                in_flight.remove(key);
+            }
+        }
+
+        let pokemon = result?;
+        self.remember(&pokemon);
+
+        Ok(pokemon)
+    }
+}
+
+impl PokemonSource for CachedSource {
+    fn pokemon(&self, key: &PokemonKey) -> BoxFuture<'_, Result<Pokemon, SourceError>> {
+        let key = key.clone();
+        Box::pin(async move { self.lookup(&key).await })
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+async fn pokedex(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Path(pokedex): Path<PokemonKey>,
+) -> Result<String, SourceError> {
+    let pokemon = source.pokemon(&pokedex).await?;
 
     Ok((f64::from(pokemon.weight) / 10.).to_string())
 }
 
-async fn drop(Path(pokedex): Path<u32>) -> Result<String, StatusCode> {
-    let uri = format!("https://pokeapi.co/api/v2/pokemon/{pokedex}");
-    let pokemon = reqwest::get(&uri)
+/// Everything we know about a Pokémon
+async fn pokemon(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Path(pokemon): Path<PokemonKey>,
+) -> Result<Json<PokemonInfo>, SourceError> {
+    Ok(Json(source.pokemon(&pokemon).await?.into()))
+}
+
+/// Most Pokémon `/8/weights` looks up at the same time
+const MAX_CONCURRENT_LOOKUPS: usize = 8;
+
+/// Most Pokémon `/8/weights` weighs in one request
+const MAX_WEIGHTS: usize = 1000;
+
+#[derive(Deserialize)]
+struct WeightsRequest {
+    pokemon: Vec<RawPokemonKey>,
+}
+
+/// Weight of a single Pokémon of `/8/weights`, or why it is unknown
+#[derive(Serialize)]
+struct WeightResult {
+    /// The Pokémon as looked up, or as given if it can't be
+    pokemon: RawPokemonKey,
+    /// Weight in kg
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+}
+
+/// Weigh many Pokémon at once
+async fn weights(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Json(request): Json<WeightsRequest>,
+) -> Result<Json<Vec<WeightResult>>, (StatusCode, String)> {
+    if request.pokemon.len() > MAX_WEIGHTS {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("at most {MAX_WEIGHTS} Pokémon can be weighed at once"),
+        ));
+    }
+
+    let results = futures_util::stream::iter(request.pokemon)
+        .map(|raw| {
+            let source = source.clone();
+            async move {
+                let key = match PokemonKey::try_from(raw.clone()) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        return WeightResult {
+                            pokemon: raw,
+                            weight: None,
+                            status: Some(StatusCode::UNPROCESSABLE_ENTITY.as_u16()),
+                            error: Some(e),
+                        }
+                    }
+                };
+                match source.pokemon(&key).await {
+                    Ok(pokemon) => WeightResult {
+                        pokemon: key.into(),
+                        weight: Some(f64::from(pokemon.weight) / 10.),
+                        error: None,
+                        status: None,
+                    },
+                    Err(e) => WeightResult {
+                        pokemon: key.into(),
+                        weight: None,
+                        status: Some(e.status().as_u16()),
+                        error: Some(e.to_string()),
+                    },
+                }
+            }
+        })
+        .buffered(MAX_CONCURRENT_LOOKUPS)
+        .collect()
+        .await;
+
+    Ok(Json(results))
+}
+
+/// Gravity of Santa's physics book, measured close to the North Pole
+const SANTA_GRAVITY: f64 = 9.825;
+
+/// Height of the chimney in meters
+const CHIMNEY_HEIGHT: f64 = 10.;
+
+/// Density of air at sea level in kg/m³
+const AIR_DENSITY: f64 = 1.225;
+
+/// Latitudes of the named locations of `/8/drop`
+const LOCATIONS: &[(&str, f64)] = &[
+    ("north-pole", 90.),
+    ("south-pole", -90.),
+    ("equator", 0.),
+    ("rovaniemi", 66.5),
+    ("greenwich", 51.48),
+];
+
+/// Parameters of the drop, see the module documentation
+#[derive(Deserialize, Default)]
+struct DropParams {
+    height: Option<f64>,
+    gravity: Option<f64>,
+    location: Option<String>,
+    latitude: Option<f64>,
+    drag_coefficient: Option<f64>,
+    area: Option<f64>,
+    air_density: Option<f64>,
+}
+
+impl DropParams {
+    /// Validate the parameters and fill in the defaults
+    fn drop(&self, mass: f64) -> Result<Drop, String> {
+        let height = self.height.unwrap_or(CHIMNEY_HEIGHT);
+        if !(height.is_finite() && height >= 0.) {
+            return Err("height must be a non-negative number".to_string());
+        }
+
+        let latitude = match (&self.location, self.latitude) {
+            (Some(location), None) => Some(
+                LOCATIONS
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(location))
+                    .map(|(_, latitude)| *latitude)
+                    .ok_or_else(|| format!("unknown location `{location}`"))?,
+            ),
+            (None, latitude) => latitude,
+            (Some(_), Some(_)) => {
+                return Err("location and latitude can't be combined".to_string());
+            }
+        };
+        let gravity = match (self.gravity, latitude) {
+            (Some(gravity), None) => gravity,
+            (None, Some(latitude)) if (-90. ..=90.).contains(&latitude) => normal_gravity(latitude),
+            (None, Some(_)) => return Err("latitude must be between -90 and 90".to_string()),
+            (None, None) => SANTA_GRAVITY,
+            (Some(_), Some(_)) => {
+                return Err("gravity and location can't be combined".to_string());
+            }
+        };
+        if !(gravity.is_finite() && gravity > 0.) {
+            return Err("gravity must be a positive number".to_string());
+        }
+
+        let drag = match (self.drag_coefficient, self.area) {
+            (None, None) => 0.,
+            (Some(coefficient), Some(area)) => {
+                let air_density = self.air_density.unwrap_or(AIR_DENSITY);
+                let valid = |value: f64| value.is_finite() && value >= 0.;
+                if !(valid(coefficient) && valid(area) && valid(air_density)) {
+                    return Err(
+                        "drag_coefficient, area and air_density must be non-negative numbers"
+                            .to_string(),
+                    );
+                }
+                0.5 * air_density * coefficient * area
+            }
+            _ => return Err("drag_coefficient and area must be given together".to_string()),
+        };
+        if drag > 0. && mass == 0. {
+            return Err("a weightless Pokémon never lands with air resistance".to_string());
+        }
+
+        Ok(Drop {
+            mass,
+            height,
+            gravity,
+            drag,
+        })
+    }
+}
+
+/// Normal gravity at sea level at `latitude` in degrees, by the Somigliana
+/// equation of WGS 84
+fn normal_gravity(latitude: f64) -> f64 {
+    let sin2 = latitude.to_radians().sin().powi(2);
+    9.780_325_335_9 * (1. + 0.001_931_852_652_41 * sin2) / (1. - 0.006_694_379_990_13 * sin2).sqrt()
+}
+
+/// A Pokémon dropped from a height
+struct Drop {
+    /// Mass in kg
+    mass: f64,
+    /// Height in m
+    height: f64,
+    /// Gravity in m/s²
+    gravity: f64,
+    /// Drag force per squared velocity in kg/m, zero without air resistance
+    drag: f64,
+}
+
+impl Drop {
+    /// Time in s and velocity in m/s at the impact
+    fn impact(&self) -> Result<(f64, f64), String> {
+        let (time, velocity) = if self.drag == 0. {
+            let velocity = (2. * self.gravity * self.height).sqrt();
+            (velocity / self.gravity, velocity)
+        } else {
+            // With quadratic drag, v² = g/k (1 - e^(-2kh)) and
+            // t = acosh(e^(kh)) / √(gk), written to stay finite for large kh
+            let k = self.drag / self.mass;
+            let kh = k * self.height;
+            let fallen = -(-2. * kh).exp_m1();
+            let velocity = (self.gravity * fallen / k).sqrt();
+            let time = (kh + fallen.sqrt().ln_1p()) / (self.gravity * k).sqrt();
+            (time, velocity)
+        };
+
+        if !(time.is_finite() && velocity.is_finite()) {
+            return Err("the fall can't be calculated for these parameters".to_string());
+        }
+        Ok((time, velocity))
+    }
+}
+
+/// Momentum and more of a Pokémon hitting the floor
+#[derive(Serialize)]
+struct Impact {
+    /// Mass in kg
+    mass: f64,
+    height: f64,
+    gravity: f64,
+    /// Velocity at the impact in m/s
+    velocity: f64,
+    /// Time of the fall in s
+    time: f64,
+    /// Momentum in N·s
+    momentum: f64,
+    /// Kinetic energy in J
+    kinetic_energy: f64,
+}
+
+async fn drop(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Path(pokedex): Path<PokemonKey>,
+    Query(params): Query<DropParams>,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let pokemon = source
+        .pokemon(&pokedex)
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .json::<Pokemon>()
+        .map_err(IntoResponse::into_response)?;
+
+    let drop = params
+        .drop(f64::from(pokemon.weight) / 10.)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e).into_response())?;
+    let (time, velocity) = drop
+        .impact()
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e).into_response())?;
+    let momentum = velocity * drop.mass;
+
+    let json = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if !json {
+        return Ok(momentum.to_string().into_response());
+    }
+
+    Ok(Json(Impact {
+        mass: drop.mass,
+        height: drop.height,
+        gravity: drop.gravity,
+        velocity,
+        time,
+        momentum,
+        kinetic_energy: 0.5 * drop.mass * velocity * velocity,
+    })
+    .into_response())
+}
+
+/// Largest payload in hectograms `/8/sleigh` searches the best load for
+const MAX_SEARCHED_PAYLOAD: u64 = 1_000_000;
+
+/// Most steps `/8/sleigh` takes searching the best load, bundles of Pokémon
+/// times the payload in hectograms
+const MAX_SEARCH_STEPS: u64 = 50_000_000;
+
+/// Pokémon of the same kind loaded into the sleigh
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Passengers {
+    pokedex: u32,
+    #[serde(default = "one")]
+    count: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct SleighRequest {
+    pokemon: Vec<Passengers>,
+    /// Maximal payload in kg
+    max_payload: f64,
+}
+
+#[derive(Serialize)]
+struct Load {
+    /// Weight in kg
+    total: f64,
+    pokemon: Vec<Passengers>,
+}
+
+#[derive(Serialize)]
+struct SleighResponse {
+    /// Weight of all Pokémon in kg
+    total: f64,
+    max_payload: f64,
+    fits: bool,
+    /// Heaviest load that fits, if not all Pokémon do
+    #[serde(skip_serializing_if = "Option::is_none")]
+    load: Option<Load>,
+}
+
+/// Check if the Pokémon fit into the sleigh, and find the heaviest load that
+/// does if not
+async fn sleigh(
+    State(source): State<Arc<dyn PokemonSource>>,
+    Json(request): Json<SleighRequest>,
+) -> Result<Json<SleighResponse>, Response> {
+    if !(request.max_payload.is_finite() && request.max_payload >= 0.) {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "max_payload must be a non-negative number",
+        )
+            .into_response());
+    }
+
+    // Merge Pokémon of the same kind, and weigh every kind once
+    let mut merged: Vec<Passengers> = Vec::new();
+    for passengers in request.pokemon {
+        match merged.iter_mut().find(|p| p.pokedex == passengers.pokedex) {
+            Some(p) => p.count = p.count.saturating_add(passengers.count),
+            None => merged.push(passengers),
+        }
+    }
+    let kinds: Vec<(Passengers, u64)> = futures_util::stream::iter(merged)
+        .map(|passengers| {
+            let source = source.clone();
+            async move {
+                let pokemon = source.pokemon(&PokemonKey::Id(passengers.pokedex)).await?;
+                Ok((passengers, pokemon.weight.into()))
+            }
+        })
+        .buffered(MAX_CONCURRENT_LOOKUPS)
+        .try_collect()
         .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        .map_err(|e: SourceError| e.into_response())?;
 
-    let speed = (2.0f64 * 9.825 * 10.).sqrt();
-    let momentum = speed * (f64::from(pokemon.weight) / 10.);
-    Ok(momentum.to_string())
+    let weight = |load: &[(Passengers, u64)]| {
+        load.iter()
+            .map(|(passengers, weight)| u64::from(passengers.count) * weight)
+            .sum::<u64>()
+    };
+    let total = weight(&kinds);
+    // Weights are in hectograms
+    let max_payload = (request.max_payload * 10.).floor();
+    let fits = total as f64 <= max_payload;
+
+    let load = if fits {
+        None
+    } else if max_payload > MAX_SEARCHED_PAYLOAD as f64 {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "max_payload must not exceed {} kg to find the best load",
+                MAX_SEARCHED_PAYLOAD / 10
+            ),
+        )
+            .into_response());
+    } else if bundles(&kinds).len() as u64 * max_payload as u64 > MAX_SEARCH_STEPS {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "too many kinds and counts of Pokémon to find the best load",
+        )
+            .into_response());
+    } else {
+        // The search takes a while, so it doesn't block other requests
+        let capacity = max_payload as u64;
+        let load = tokio::task::spawn_blocking(move || heaviest_load(&kinds, capacity))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+        Some(Load {
+            total: weight(&load) as f64 / 10.,
+            pokemon: load.into_iter().map(|(passengers, _)| passengers).collect(),
+        })
+    };
+
+    Ok(Json(SleighResponse {
+        total: total as f64 / 10.,
+        max_payload: request.max_payload,
+        fits,
+        load,
+    }))
+}
+
+/// Split the counts of the kinds into bundles of 1, 2, 4, ... Pokémon, so
+/// every count can be made of them
+fn bundles(kinds: &[(Passengers, u64)]) -> Vec<(usize, u32)> {
+    let mut bundles: Vec<(usize, u32)> = Vec::new();
+    for (idx, (passengers, _)) in kinds.iter().enumerate() {
+        let (mut left, mut size) = (passengers.count, 1);
+        while left > 0 {
+            let bundle = size.min(left);
+            bundles.push((idx, bundle));
+            left -= bundle;
+            size = size.saturating_mul(2);
+        }
+    }
+
+    bundles
+}
+
+/// Heaviest selection of the Pokémon with a total weight up to `capacity`
+///
+/// This is a bounded subset sum problem over the [`bundles`] of the kinds,
+/// where every reachable weight remembers the bundle that reached it first.
+fn heaviest_load(kinds: &[(Passengers, u64)], capacity: u64) -> Vec<(Passengers, u64)> {
+    let bundles = bundles(kinds);
+
+    let capacity = capacity as usize;
+    let mut reached_by: Vec<Option<usize>> = vec![None; capacity + 1];
+    let mut reached = vec![false; capacity + 1];
+    reached[0] = true;
+    for (bundle, &(idx, count)) in bundles.iter().enumerate() {
+        let weight = kinds[idx].1 * u64::from(count);
+        let Ok(weight) = usize::try_from(weight) else {
+            continue;
+        };
+        if weight == 0 || weight > capacity {
+            continue;
+        }
+        for sum in (weight..=capacity).rev() {
+            if !reached[sum] && reached[sum - weight] {
+                reached[sum] = true;
+                reached_by[sum] = Some(bundle);
+            }
+        }
+    }
+
+    // Pokémon without weight always fit
+    let mut counts: Vec<u32> = kinds
+        .iter()
+        .map(|(passengers, weight)| if *weight == 0 { passengers.count } else { 0 })
+        .collect();
+    let mut sum = (0..=capacity).rev().find(|&sum| reached[sum]).unwrap_or(0);
+    while let Some(bundle) = reached_by[sum] {
+        let (idx, count) = bundles[bundle];
+        counts[idx] += count;
+        sum -= (kinds[idx].1 * u64::from(count)) as usize;
+    }
+
+    kinds
+        .iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|((passengers, weight), count)| {
+            let passengers = Passengers {
+                pokedex: passengers.pokedex,
+                count,
+            };
+            (passengers, *weight)
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use axum::http::HeaderValue;
     use axum::{
         body::Body,
         http::{Method, Request},
     };
+    use axum_test::TestServer;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tower::util::ServiceExt;
 
+    /// Source of a few Pokémon, counting the lookups
+    struct StubSource {
+        local: LocalSource,
+        lookups: AtomicUsize,
+    }
+
+    impl StubSource {
+        fn new() -> Self {
+            Self {
+                local: LocalSource::new([
+                    Pokemon {
+                        id: 25,
+                        name: "pikachu".to_string(),
+                        weight: 60,
+                        ..Default::default()
+                    },
+                    Pokemon {
+                        id: 143,
+                        name: "snorlax".to_string(),
+                        weight: 4600,
+                        ..Default::default()
+                    },
+                ]),
+                lookups: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl PokemonSource for StubSource {
+        fn pokemon(&self, key: &PokemonKey) -> BoxFuture<'_, Result<Pokemon, SourceError>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let key = key.clone();
+            Box::pin(async move {
+                // Give concurrent lookups a chance to overlap
+                tokio::task::yield_now().await;
+                self.local.pokemon(&key).await
+            })
+        }
+    }
+
     #[tokio::test]
     async fn test_task1() {
-        let app = get_routes();
+        let app = router(Arc::new(StubSource::new()));
 
         let req = Request::builder()
             .method(Method::GET)
@@ -127,7 +1230,7 @@ mod test {
 
     #[tokio::test]
     async fn test_task2() {
-        let app = get_routes();
+        let app = router(Arc::new(StubSource::new()));
 
         let req = Request::builder()
             .method(Method::GET)
@@ -147,4 +1250,301 @@ mod test {
 
         assert!((body_f64 - 84.10707461325713).abs() < 0.001)
     }
+
+    #[tokio::test]
+    async fn test_not_found() {
+        let app = router(Arc::new(StubSource::new()));
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/8/weight/9999")
+            .body(Body::from(()))
+            .unwrap();
+
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        let stub = Arc::new(StubSource::new());
+        let cache = CachedSource::new(stub.clone(), Duration::from_secs(60));
+
+        assert_eq!(
+            cache.pokemon(&PokemonKey::Id(143)).await.unwrap().weight,
+            4600
+        );
+        assert_eq!(
+            cache.pokemon(&PokemonKey::Id(143)).await.unwrap().weight,
+            4600
+        );
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 1);
+
+        // Unknown Pokémon are not cached
+        assert!(matches!(
+            cache.pokemon(&PokemonKey::Id(1)).await,
+            Err(SourceError::NotFound(PokemonKey::Id(1)))
+        ));
+        assert!(cache.pokemon(&PokemonKey::Id(1)).await.is_err());
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 3);
+
+        // Expired Pokémon are looked up again
+        let stub = Arc::new(StubSource::new());
+        let cache = CachedSource::new(stub.clone(), Duration::ZERO);
+
+        cache.pokemon(&PokemonKey::Id(25)).await.unwrap();
+        cache.pokemon(&PokemonKey::Id(25)).await.unwrap();
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_bundled() {
+        let source = LocalSource::bundled();
+
+        let pikachu = source.pokemon(&PokemonKey::Id(25)).await.unwrap();
+        assert_eq!(pikachu.name, "pikachu");
+        assert_eq!(pikachu.weight, 60);
+    }
+
+    #[tokio::test]
+    async fn test_drop_params() {
+        let server = TestServer::new(router(Arc::new(StubSource::new()))).unwrap();
+
+        let response = server
+            .get("/8/drop/25")
+            .add_query_param("height", 20)
+            .add_query_param("location", "equator")
+            .add_header(header::ACCEPT, HeaderValue::from_static("application/json"))
+            .await;
+        response.assert_status_ok();
+        let impact: Value = response.json();
+        let velocity = (2. * 9.780_325_335_9 * 20.0f64).sqrt();
+        assert_eq!(impact["mass"], 6.0);
+        assert_eq!(impact["height"], 20.0);
+        assert!((impact["velocity"].as_f64().unwrap() - velocity).abs() < 1e-9);
+        assert!((impact["momentum"].as_f64().unwrap() - 6. * velocity).abs() < 1e-9);
+        assert!((impact["time"].as_f64().unwrap() - velocity / 9.780_325_335_9).abs() < 1e-9);
+
+        let response = server
+            .get("/8/drop/25")
+            .add_query_param("latitude", 90)
+            .await;
+        let momentum: f64 = response.text().parse().unwrap();
+        assert!((momentum - 6. * (2. * 9.832_184_9 * 10.0f64).sqrt()).abs() < 1e-5);
+
+        for query in [
+            "height=-1",
+            "gravity=0",
+            "location=mars",
+            "latitude=91",
+            "gravity=9.8&latitude=10",
+            "drag_coefficient=0.5",
+        ] {
+            server
+                .get(&format!("/8/drop/25?{query}"))
+                .await
+                .assert_status(StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drop_drag() {
+        let server = TestServer::new(router(Arc::new(StubSource::new()))).unwrap();
+
+        // Snorlax as a sphere, 460 kg and 2.1 m high
+        let response = server
+            .get("/8/drop/143?height=1000&drag_coefficient=0.47&area=3.46")
+            .add_header(header::ACCEPT, HeaderValue::from_static("application/json"))
+            .await;
+        response.assert_status_ok();
+        let impact: Value = response.json();
+
+        // v² = g/k (1 - e^(-2kh)) with k = ρ c A / 2m
+        let k = 0.5 * 1.225 * 0.47 * 3.46 / 460.;
+        let velocity = (9.825 / k * (1. - (-2. * k * 1000.0f64).exp())).sqrt();
+        assert!((impact["velocity"].as_f64().unwrap() - velocity).abs() < 1e-6);
+        assert!(impact["time"].as_f64().unwrap() > (2. * 1000. / 9.825f64).sqrt());
+
+        // Pikachu falls at its terminal velocity almost right away
+        let response = server
+            .get("/8/drop/25?drag_coefficient=1e6&area=1")
+            .add_header(header::ACCEPT, HeaderValue::from_static("application/json"))
+            .await;
+        response.assert_status_ok();
+        let impact: Value = response.json();
+        let k: f64 = 0.5 * 1.225 * 1e6 / 6.;
+        let terminal = (9.825 / k).sqrt();
+        assert!((impact["velocity"].as_f64().unwrap() - terminal).abs() < 1e-12);
+        assert!((impact["time"].as_f64().unwrap() - 10. / terminal).abs() < 1e-3);
+
+        // Drag too large to calculate with
+        server
+            .get("/8/drop/25?drag_coefficient=1e300&area=1e300")
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_sleigh() {
+        let server = TestServer::new(router(Arc::new(StubSource::new()))).unwrap();
+
+        let response = server
+            .post("/8/sleigh")
+            .json(&json!({
+                "pokemon": [{"pokedex": 25, "count": 3}, {"pokedex": 143}],
+                "max_payload": 100
+            }))
+            .await;
+        response.assert_status_ok();
+        response.assert_json(&json!({
+            "total": 478.0,
+            "max_payload": 100.0,
+            "fits": false,
+            "load": {"total": 18.0, "pokemon": [{"pokedex": 25, "count": 3}]}
+        }));
+
+        let response = server
+            .post("/8/sleigh")
+            .json(&json!({
+                "pokemon": [{"pokedex": 25, "count": 10}, {"pokedex": 25, "count": 5}, {"pokedex": 143}],
+                "max_payload": 500
+            }))
+            .await;
+        response.assert_json(&json!({
+            "total": 550.0,
+            "max_payload": 500.0,
+            "fits": false,
+            "load": {
+                "total": 496.0,
+                "pokemon": [{"pokedex": 25, "count": 6}, {"pokedex": 143, "count": 1}]
+            }
+        }));
+
+        let response = server
+            .post("/8/sleigh")
+            .json(&json!({"pokemon": [{"pokedex": 25}], "max_payload": 6}))
+            .await;
+        response.assert_json(&json!({"total": 6.0, "max_payload": 6.0, "fits": true}));
+
+        server
+            .post("/8/sleigh")
+            .json(&json!({"pokemon": [{"pokedex": 1}], "max_payload": 6}))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        // Searching many bundles of Pokémon for a large payload takes too long
+        server
+            .post("/8/sleigh")
+            .json(&json!({
+                "pokemon": [
+                    {"pokedex": 25, "count": u32::MAX},
+                    {"pokedex": 143, "count": u32::MAX}
+                ],
+                "max_payload": 100000
+            }))
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_coalescing() {
+        let stub = Arc::new(StubSource::new());
+        let cache = CachedSource::new(stub.clone(), Duration::from_secs(60));
+        let key = PokemonKey::Id(25);
+
+        let lookups = futures_util::future::join_all((0..5).map(|_| cache.pokemon(&key))).await;
+
+        assert!(lookups
+            .iter()
+            .all(|pokemon| pokemon.as_ref().unwrap().weight == 60));
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 1);
+
+        // Names find the Pokémon cached by number
+        let pokemon = cache
+            .pokemon(&PokemonKey::Name("pikachu".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(pokemon.id, 25);
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 1);
+
+        // Failed lookups are forgotten, even when a later lookup started
+        // before an earlier one finished
+        let missing = PokemonKey::Id(9999);
+        let lookups = futures_util::future::join_all((0..5).map(|_| cache.pokemon(&missing))).await;
+        assert!(lookups.iter().all(Result::is_err));
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+        let before = stub.lookups.load(Ordering::SeqCst);
+        assert!(cache.pokemon(&missing).await.is_err());
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), before + 1);
+    }
+
+    #[tokio::test]
+    async fn test_weights() {
+        let stub = Arc::new(StubSource::new());
+        let cache = Arc::new(CachedSource::new(stub.clone(), Duration::from_secs(60)));
+        let server = TestServer::new(router(cache)).unwrap();
+
+        let response = server
+            .post("/8/weights")
+            .json(&json!({"pokemon": [25, "Snorlax", 9999, "25", 143]}))
+            .await;
+
+        response.assert_status_ok();
+        response.assert_json(&json!([
+            {"pokemon": 25, "weight": 6.0},
+            {"pokemon": "snorlax", "weight": 460.0},
+            {"pokemon": 9999, "error": "there is no Pokémon 9999", "status": 404},
+            {"pokemon": 25, "weight": 6.0},
+            {"pokemon": 143, "weight": 460.0}
+        ]));
+        // The second pikachu is shared with the first lookup
+        assert_eq!(stub.lookups.load(Ordering::SeqCst), 4);
+
+        // Invalid Pokémon don't spoil the others
+        let response = server
+            .post("/8/weights")
+            .json(&json!({"pokemon": ["", 25, null]}))
+            .await;
+        response.assert_status_ok();
+        response.assert_json(&json!([
+            {"pokemon": "", "error": "a Pokémon needs a pokédex number or a name", "status": 422},
+            {"pokemon": 25, "weight": 6.0},
+            {"pokemon": null, "error": "a Pokémon needs a pokédex number or a name", "status": 422}
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_pokemon_by_name() {
+        let server = TestServer::new(router(Arc::new(LocalSource::bundled()))).unwrap();
+
+        let response = server.get("/8/weight/Pikachu").await;
+        response.assert_status_ok();
+        response.assert_text("6");
+
+        let response = server.get("/8/pokemon/charizard").await;
+        response.assert_status_ok();
+        response.assert_json(&json!({
+            "id": 6,
+            "name": "charizard",
+            "height": 1.7,
+            "weight": 90.5,
+            "types": ["fire", "flying"],
+            "stats": {
+                "hp": 78,
+                "attack": 84,
+                "defense": 78,
+                "special-attack": 109,
+                "special-defense": 85,
+                "speed": 100
+            }
+        }));
+        let by_number: Value = server.get("/8/pokemon/6").await.json();
+        assert_eq!(by_number["name"], "charizard");
+
+        server
+            .get("/8/pokemon/missingno")
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
 }
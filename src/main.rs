@@ -17,9 +17,9 @@ async fn main(#[shuttle_shared_db::Postgres] pool: PgPool) -> shuttle_axum::Shut
         .merge(day::d5::get_routes())
         .merge(day::d6::get_routes())
         .merge(day::d7::get_routes())
-        .merge(day::d8::get_routes())
-        .merge(day::d11::get_routes())
-        .merge(day::d12::get_routes())
+        .merge(day::d8::get_routes(pool.clone()))
+        .merge(day::d11::get_routes(pool.clone()))
+        .merge(day::d12::get_routes(pool.clone()))
         .merge(day::d13::get_routes(pool.clone()))
         .merge(day::d14::get_routes())
         .merge(day::d15::get_routes())